@@ -42,35 +42,114 @@ directory.
 !*/
 
 pub mod config;
+pub mod ignore;
 pub mod index;
 pub mod init;
 pub mod walk;
 
 use std::{
+    collections::{BTreeMap, HashMap},
     fs,
     path::{Path, PathBuf},
 };
 
 use anyhow::*;
+use chrono::NaiveDate;
 use thiserror::Error;
 
 use self::{
-    config::{BookRon, IndexRon},
-    index::Index,
+    config::{BookRon, IndexRon, OutputScheme},
+    ignore::IgnorePatterns,
+    index::{Index, IndexLoadOptions},
 };
 
-const INDEX_RON: &'static str = "index.ron";
+/// File extensions of source files that `adbook` can convert
+pub const SRC_EXTENSIONS: &[&str] = &["adoc", "asciidoc", "asc", "ad", "txt"];
+
+/// File extensions of plain text source files, which are HTML-escaped and wrapped in `<pre>`
+/// instead of being converted with `asciidoctor`
+pub const TEXT_EXTENSIONS: &[&str] = &["txt"];
+
+/// If the file has one of the [`SRC_EXTENSIONS`]
+pub fn is_src_file(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|s| s.to_str()),
+        Some(ext) if SRC_EXTENSIONS.contains(&ext)
+    )
+}
+
+/// If the file has one of the [`TEXT_EXTENSIONS`]
+pub fn is_text_file(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|s| s.to_str()),
+        Some(ext) if TEXT_EXTENSIONS.contains(&ext)
+    )
+}
+
+/// Error for a file that is not one of the [`SRC_EXTENSIONS`]
+#[derive(Error, Debug)]
+#[error("Unsupported source format: {} (supported extensions: {}){}", .0.display(), SRC_EXTENSIONS.join(", "), self::format_hint(.0))]
+pub struct UnsupportedSourceFormat(pub PathBuf);
+
+/// Makes sure the file has one of the [`SRC_EXTENSIONS`]
+pub fn ensure_src_file(path: &Path) -> std::result::Result<(), UnsupportedSourceFormat> {
+    if self::is_src_file(path) {
+        std::result::Result::Ok(())
+    } else {
+        Err(UnsupportedSourceFormat(path.to_path_buf()))
+    }
+}
+
+/// Suggestion for converting an unsupported format into AsciiDoc
+fn format_hint(path: &Path) -> &'static str {
+    match path.extension().and_then(|s| s.to_str()) {
+        Some("org") | Some("md") | Some("markdown") | Some("rst") => {
+            ". Convert it to AsciiDoc first, e.g. with `pandoc -t asciidoc`"
+        }
+        _ => "",
+    }
+}
 
 /// Error while loading `book.ron`
 #[derive(Error, Debug)]
 pub enum BookLoadError {
     #[error("Given non-directory path")]
     GivenNonDirectoryPath,
-    #[error("Not found root directory (not found `book.ron`)")]
-    NotFoundRoot,
+    /// (searched_directories)
+    #[error("Not found root directory (not found `book.ron` in: {})", self::display_dirs(.0))]
+    NotFoundRoot(Vec<PathBuf>),
+}
+
+fn display_dirs(dirs: &[PathBuf]) -> String {
+    dirs.iter()
+        .map(|dir| format!("`{}`", dir.display()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Directories searched for `book.ron` by [`BookStructure::from_dir_with`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RootSearch {
+    /// The given directory and its ancestors. The search stops at the repository root (the first
+    /// directory with `.git`), so a `book.ron` of another project above it is not picked up
+    Ancestors,
+    /// The given directory only
+    GivenDir,
 }
 
 /// File structure of an adbook project read from `book.ron` and `index.ron`s
+///
+/// It's usually loaded with [`BookStructure::from_dir`], but it can also be constructed in memory
+/// (e.g. by tests or embedders). The build derives every path (source, site and cache directories)
+/// from the fields, so a hand-constructed `BookStructure` must satisfy these invariants (see
+/// [`BookStructure::validate`]):
+///
+/// * `root` is a canonicalized absolute path to an existing directory. `book.ron` doesn't have to
+///   be there.
+/// * The source directory (`root` joined with `src_dir`) exists.
+/// * Paths in `index` and `home` are canonicalized absolute paths to files in the source directory.
+/// * No two source files are mapped to the same output file.
+/// * `ignore` is compiled from `ignore` in `book.ron` ([`IgnorePatterns::new`]).
 #[derive(Debug, Clone)]
 pub struct BookStructure {
     /// Absolute path to a directory with `book.ron`
@@ -79,6 +158,14 @@ pub struct BookStructure {
     pub book_ron: BookRon,
     /// `src/index.ron`, the recursive book structure
     pub index: Index,
+    /// Absolute path to the source file of the home page (`home` in `book.ron`)
+    pub home: Option<PathBuf>,
+    /// `ignore` patterns in `book.ron`, compiled
+    pub ignore: IgnorePatterns,
+    /// Active build profile, which selects `profile_attributes` in `book.ron`
+    pub profile: Option<String>,
+    /// Whether build articles with the `draft` attribute (`adbook build --drafts`) or not
+    pub drafts: bool,
 }
 
 impl BookStructure {
@@ -89,12 +176,167 @@ impl BookStructure {
     pub fn site_dir_path(&self) -> PathBuf {
         self.root.join(&self.book_ron.site_dir)
     }
+
+    /// Maps a source file to the relative path of its output file from the site directory
+    ///
+    /// The extension follows the `asciidoctor` backend (`.html` by default) and the path follows
+    /// the explicit output path in `index.ron` if any, or else `output_scheme` in `book.ron`.
+    ///
+    /// * `src_file`: absolute path to a source file
+    pub fn src_file_to_dst_rel(&self, src_file: &Path) -> Result<PathBuf> {
+        let ext = self.book_ron.output_extension();
+
+        if self.home.as_deref() == Some(src_file) {
+            return Ok(PathBuf::from("index").with_extension(ext));
+        }
+
+        if let Some(output) = self.index.find_output(src_file) {
+            return Ok(output.with_extension(ext));
+        }
+
+        let src_dir = self.src_dir_path();
+
+        match self.book_ron.output_scheme {
+            OutputScheme::Mirror => {}
+            OutputScheme::Flat => {
+                // `<dir name>.html`
+                let name = match self.index.find_summarized_dir(src_file) {
+                    Some(dir) if dir != src_dir => dir.file_name(),
+                    _ => src_file.file_stem(),
+                };
+                let name = name.with_context(|| {
+                    format!("Unable to get file name of: {}", src_file.display())
+                })?;
+                return Ok(PathBuf::from(name).with_extension(ext));
+            }
+            OutputScheme::DateBased => {
+                // `<yyyy>/<mm>/<file stem>.html`
+                if let Some(date) = self::read_revdate(src_file) {
+                    let stem = src_file.file_stem().with_context(|| {
+                        format!("Unable to get file name of: {}", src_file.display())
+                    })?;
+                    return Ok(PathBuf::from(date.format("%Y/%m").to_string())
+                        .join(stem)
+                        .with_extension(ext));
+                }
+            }
+        }
+
+        // `<dir>/index.html`
+        if self.book_ron.trailing_slash {
+            if let Some(dir) = self.index.find_summarized_dir(src_file) {
+                let rel_dir = dir.strip_prefix(&src_dir).with_context(|| {
+                    format!(
+                        "Unable to strip prefix: `{}` from `{}`",
+                        src_dir.display(),
+                        dir.display()
+                    )
+                })?;
+                return Ok(rel_dir.join("index").with_extension(ext));
+            }
+        }
+
+        let rel_path = src_file.strip_prefix(&src_dir).with_context(|| {
+            format!(
+                "Unable to strip prefix: `{}` from `{}`",
+                src_dir.display(),
+                src_file.display()
+            )
+        })?;
+
+        Ok(rel_path.with_extension(ext))
+    }
+
+    /// Maps a source file to the absolute path of its output file in the site directory
+    pub fn src_file_to_dst_file(&self, src_file: &Path) -> Result<PathBuf> {
+        Ok(self
+            .site_dir_path()
+            .join(self.src_file_to_dst_rel(src_file)?))
+    }
+
+    /// Maps a source file to the URL of its output file: `{base_url}/path/to/file.html`
+    ///
+    /// With `trailing_slash`, `index.html` is omitted: `{base_url}/path/to/dir/`
+    pub fn src_file_to_url(&self, src_file: &Path) -> Result<String> {
+        let rel_path = self.src_file_to_dst_rel(src_file)?;
+
+        if self.book_ron.trailing_slash && rel_path.file_name() == Some("index.html".as_ref()) {
+            let dir = rel_path.parent().unwrap_or_else(|| Path::new(""));
+            if dir.as_os_str().is_empty() {
+                return Ok(format!("{}/", self.book_ron.base_url));
+            }
+            return Ok(format!("{}/{}/", self.book_ron.base_url, dir.display()));
+        }
+
+        Ok(format!("{}/{}", self.book_ron.base_url, rel_path.display()))
+    }
+
+    /// Activates a build profile. Fails if it's not in `profile_attributes` of `book.ron`
+    pub fn set_profile(&mut self, profile: &str) -> Result<()> {
+        ensure!(
+            self.book_ron.profile_attributes.contains_key(profile),
+            "Unknown profile `{}` (not in `profile_attributes` of `book.ron`)",
+            profile
+        );
+        self.profile = Some(profile.to_string());
+        Ok(())
+    }
+
+    /// If the source file is ignored (`ignore` in `book.ron`) or an excluded draft
+    pub fn is_excluded(&self, src_file: &Path) -> bool {
+        self.is_ignored(src_file) || self.is_excluded_draft(src_file)
+    }
+
+    /// If the file in the source directory matches the `ignore` patterns in `book.ron`
+    pub fn is_ignored(&self, src_file: &Path) -> bool {
+        if self.ignore.is_empty() {
+            return false;
+        }
+
+        let src_dir = self.src_dir_path();
+        let rel_path = src_file.strip_prefix(&src_dir).unwrap_or(src_file);
+        self.ignore.is_ignored(rel_path)
+    }
+
+    /// If the source file is a draft (`:draft:`) that is excluded from the build. A draft directory
+    /// summary excludes the whole directory
+    pub fn is_excluded_draft(&self, src_file: &Path) -> bool {
+        !self.drafts && crate::build::convert::is_draft_file(src_file)
+    }
+
+    /// Attributes of the active build profile
+    pub fn profile_attributes(&self) -> Option<&BTreeMap<String, String>> {
+        let profile = self.profile.as_ref()?;
+        self.book_ron.profile_attributes.get(profile)
+    }
+
+    /// Absolute paths to the `watch_extra` items in `book.ron`. Fails if any of them doesn't exist
+    pub fn watch_extra_paths(&self) -> Result<Vec<PathBuf>> {
+        self.book_ron
+            .watch_extra
+            .iter()
+            .map(|rel_path| {
+                let path = self.root.join(rel_path);
+                ensure!(
+                    path.exists(),
+                    "Non-existing path in `book.ron` watch_extra: {}",
+                    rel_path.display()
+                );
+                Ok(path)
+            })
+            .collect()
+    }
 }
 
 impl BookStructure {
     /// Tries to find `book.ron` going up the directories and parses it into a file structure
     pub fn from_dir(path: impl AsRef<Path>) -> Result<Self> {
-        let book_ron_path = self::find_root_book_ron(path)?;
+        Self::from_dir_with(path, RootSearch::Ancestors)
+    }
+
+    /// Tries to find `book.ron` in the directories and parses it into a file structure
+    pub fn from_dir_with(path: impl AsRef<Path>, search: RootSearch) -> Result<Self> {
+        let book_ron_path = self::find_root_book_ron(path, search)?;
         log::trace!("book.ron located at: {}", book_ron_path.display());
 
         let root = book_ron_path
@@ -125,10 +367,16 @@ impl BookStructure {
         log::trace!("root `book.ron` loaded");
         // log::trace!("{:?}", book_ron);
 
+        let ignore = IgnorePatterns::new(&book_ron.ignore)
+            .with_context(|| format!("Failed to load book.ron at: {}", book_ron_path.display()))?;
+
         let src_dir = root.join(&book_ron.src_dir);
 
         let (index, index_errors) = {
-            let index_path = src_dir.join(INDEX_RON);
+            let index_file = book_ron.index_file();
+            index::validate_index_file(index_file).context("Invalid `index_file` in `book.ron`")?;
+
+            let index_path = src_dir.join(index_file);
             let index_str = fs::read_to_string(&index_path).with_context(|| {
                 format!(
                     "Unable to read root `index.ron` at: {}",
@@ -141,24 +389,141 @@ impl BookStructure {
             })?;
             log::trace!("root `index.ron` loaded");
 
+            let opts = IndexLoadOptions {
+                exclude_dirs: book_ron
+                    .exclude_dirs
+                    .iter()
+                    .map(|rel_path| {
+                        let dir = src_dir.join(rel_path);
+                        dir.canonicalize().unwrap_or(dir)
+                    })
+                    .collect(),
+                max_depth: book_ron.max_depth.unwrap_or(index::DEFAULT_MAX_DEPTH),
+                index_file: index_file.to_string(),
+            };
+
             log::trace!("loading `index.ron`");
-            Index::from_index_ron_recursive(&index_ron, &src_dir)?
+            Index::from_index_ron_recursive(&index_ron, &src_dir, &opts)?
         };
 
         log::trace!("`index.ron` loaded");
 
         crate::utils::print_errors(&index_errors, "while parsing `index.ron`");
 
-        Ok(Self {
+        let home = match &book_ron.home {
+            Some(rel_path) => Some(self::locate_home(&src_dir, rel_path)?),
+            None => None,
+        };
+
+        let book = Self {
             root,
             book_ron,
             index,
-        })
+            home,
+            ignore,
+            profile: None,
+            drafts: false,
+        };
+
+        book.validate_dst_files()?;
+
+        Ok(book)
+    }
+
+    /// Checks the invariants of a (possibly hand-constructed) `BookStructure`
+    pub fn validate(&self) -> Result<()> {
+        ensure!(
+            self.root.is_absolute() && self.root.is_dir(),
+            "Book root is not an absolute path to a directory: {}",
+            self.root.display()
+        );
+
+        let src_dir = self.src_dir_path();
+        ensure!(
+            src_dir.is_dir(),
+            "Unable to find source directory: {}",
+            src_dir.display()
+        );
+
+        for src_file in walk::list_src_files(self).iter().chain(self.home.iter()) {
+            ensure!(
+                src_file.is_absolute() && src_file.starts_with(&src_dir),
+                "Source file is not an absolute path in the source directory: {}",
+                src_file.display()
+            );
+        }
+
+        // typos in `watch_extra` are reported before building, even without `adbook watch`
+        self.watch_extra_paths()?;
+
+        self.validate_dst_files()
+    }
+
+    /// Makes sure no two source files are mapped to the same output file (e.g. with `home` or
+    /// `trailing_slash`)
+    fn validate_dst_files(&self) -> Result<()> {
+        let mut dst_files = HashMap::<PathBuf, PathBuf>::new();
+
+        for src_file in walk::list_src_files(self) {
+            let dst_file = self.src_file_to_dst_rel(&src_file)?;
+
+            if let Some(other) = dst_files.get(&dst_file).filter(|other| **other != src_file) {
+                bail!(
+                    "`{}` and `{}` are both mapped to `{}`",
+                    other.display(),
+                    src_file.display(),
+                    dst_file.display(),
+                );
+            }
+
+            dst_files.insert(dst_file, src_file);
+        }
+
+        Ok(())
     }
 }
 
+/// Reads `:revdate:` in the header of a source file. Accepts `2020-10-23`, `2020/10/23`,
+/// `Oct 23, 2020` and `October 23, 2020`
+fn read_revdate(src_file: &Path) -> Option<NaiveDate> {
+    const FORMATS: &[&str] = &["%Y-%m-%d", "%Y/%m/%d", "%b %d, %Y", "%B %d, %Y"];
+
+    let text = fs::read_to_string(src_file).ok()?;
+
+    // the header ends with the first blank line
+    let value = text
+        .lines()
+        .skip_while(|ln| ln.trim().is_empty())
+        .take_while(|ln| !ln.trim().is_empty())
+        .find_map(|ln| ln.strip_prefix(":revdate:"))?
+        .trim();
+
+    FORMATS.iter().find_map(|fmt| {
+        NaiveDate::parse_from_str(value, fmt)
+            .ok()
+            // `2020-10-23T12:00:00` or `2020-10-23 12:00`
+            .or_else(|| NaiveDate::parse_from_str(value.get(..10)?, fmt).ok())
+    })
+}
+
+/// Tries to return a canonicalized path to the `home` source file in `book.ron`
+fn locate_home(src_dir: &Path, rel_path: &Path) -> Result<PathBuf> {
+    let path = src_dir.join(rel_path);
+
+    ensure!(
+        path.is_file(),
+        "Unable to locate `home` in `book.ron`: {}",
+        path.display()
+    );
+
+    self::ensure_src_file(&path).context("`home` in `book.ron` is not a buildable source file")?;
+
+    path.canonicalize()
+        .with_context(|| format!("Failed to canonicalize `home`: {}", path.display()))
+}
+
 /// Tries to return a canonicalized path to `book.ron` locating a root directory
-fn find_root_book_ron(path: impl AsRef<Path>) -> Result<PathBuf> {
+fn find_root_book_ron(path: impl AsRef<Path>, search: RootSearch) -> Result<PathBuf> {
     let path = path.as_ref().canonicalize().with_context(|| {
         format!(
             "Unable to find given directory path: {}",
@@ -169,13 +534,171 @@ fn find_root_book_ron(path: impl AsRef<Path>) -> Result<PathBuf> {
     ensure!(path.is_dir(), BookLoadError::GivenNonDirectoryPath);
 
     // go up the ancestors and find `book.ron`
+    let mut searched = Vec::new();
     for dir in path.ancestors() {
         let book_ron = dir.join("book.ron");
-        if !book_ron.is_file() {
-            continue;
+        if book_ron.is_file() {
+            return Ok(book_ron);
+        }
+
+        searched.push(dir.to_path_buf());
+
+        let is_boundary = match search {
+            RootSearch::Ancestors => dir.join(".git").exists(),
+            RootSearch::GivenDir => true,
+        };
+        if is_boundary {
+            break;
+        }
+    }
+
+    Err(BookLoadError::NotFoundRoot(searched).into())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::Path};
+
+    use super::{find_root_book_ron, BookLoadError, RootSearch};
+    use crate::book::{init, BookStructure};
+
+    #[test]
+    fn base_url_trailing_slash() {
+        let dir = std::env::temp_dir().join("adbook-test-base-url");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        init::gen_init_files(&dir).unwrap();
+
+        let book_ron = fs::read_to_string(dir.join("book.ron")).unwrap();
+        for (base_url, expected) in [
+            ("", "/article.html"),
+            ("/", "/article.html"),
+            ("/docs", "/docs/article.html"),
+            ("/docs/", "/docs/article.html"),
+        ] {
+            let text = book_ron.replace("base_url: \"\",", &format!("base_url: \"{}\",", base_url));
+            fs::write(dir.join("book.ron"), text).unwrap();
+
+            let book = BookStructure::from_dir(&dir).unwrap();
+            let article = book.src_dir_path().join("article.adoc");
+            assert_eq!(
+                book.src_file_to_url(&article).unwrap(),
+                expected,
+                "base_url: {:?}",
+                base_url
+            );
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_watch_extra() {
+        let dir = std::env::temp_dir().join("adbook-test-watch-extra");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
         }
-        return Ok(book_ron);
+        fs::create_dir_all(dir.join("scss")).unwrap();
+        init::gen_init_files(&dir).unwrap();
+
+        let mut book = BookStructure::from_dir(&dir).unwrap();
+        book.book_ron.watch_extra = vec!["scss".into()];
+        assert!(book.validate().is_ok());
+        assert_eq!(
+            book.watch_extra_paths().unwrap(),
+            vec![book.root.join("scss")]
+        );
+
+        // a typo is reported without building or watching
+        book.book_ron.watch_extra.push("sccs".into());
+        let err = book.validate().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Non-existing path in `book.ron` watch_extra: sccs"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
     }
 
-    Err(BookLoadError::NotFoundRoot.into())
+    #[test]
+    fn root_search() {
+        let dir = std::env::temp_dir().join("adbook-test-root-search");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        let nested = dir.join("repo/nested");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(dir.join("book.ron"), "").unwrap();
+        let dir = dir.canonicalize().unwrap();
+
+        // found in an ancestor
+        assert_eq!(
+            find_root_book_ron(&nested, RootSearch::Ancestors).unwrap(),
+            dir.join("book.ron")
+        );
+
+        // the given directory only
+        let err = find_root_book_ron(&nested, RootSearch::GivenDir).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<BookLoadError>(),
+            Some(BookLoadError::NotFoundRoot(searched)) if searched == &[dir.join("repo/nested")]
+        ));
+
+        // stops at the repository root
+        fs::create_dir_all(dir.join("repo/.git")).unwrap();
+        let err = find_root_book_ron(&nested, RootSearch::Ancestors).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<BookLoadError>(),
+            Some(BookLoadError::NotFoundRoot(searched))
+                if searched == &[dir.join("repo/nested"), dir.join("repo")]
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn explicit_output_path() {
+        let dir = std::env::temp_dir().join("adbook-test-output-path");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        init::gen_init_files(&dir).unwrap();
+
+        let src_dir = dir.join("src");
+        fs::write(src_dir.join("getting-started.adoc"), "= Getting started\n").unwrap();
+        fs::write(src_dir.join("escape.adoc"), "= Escape\n").unwrap();
+        fs::write(
+            src_dir.join("index.ron"),
+            r#"summary: ("", "index.adoc"), items: [
+                File("", "article.adoc"),
+                File("", "getting-started.adoc", "guide/intro.html"),
+                File("", "escape.adoc", "../escape.html"),
+            ]"#,
+        )
+        .unwrap();
+
+        let book = BookStructure::from_dir(&dir).unwrap();
+        let src_dir = book.src_dir_path();
+
+        let article = src_dir.join("article.adoc");
+        assert_eq!(
+            book.src_file_to_dst_rel(&article).unwrap(),
+            Path::new("article.html")
+        );
+
+        let guide = src_dir.join("getting-started.adoc");
+        assert_eq!(
+            book.src_file_to_dst_rel(&guide).unwrap(),
+            Path::new("guide/intro.html")
+        );
+        assert_eq!(book.src_file_to_url(&guide).unwrap(), "/guide/intro.html");
+
+        // invalid output paths are rejected along with the items
+        assert_eq!(book.index.items.len(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
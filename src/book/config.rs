@@ -6,42 +6,308 @@ See the [demo files] to know the details.
 [demo files]: https://github.com/toyboot4e/adbook/tree/gh-pages
 */
 
-use std::path::PathBuf;
+use std::{collections::BTreeMap, path::PathBuf};
 
 use serde::{Deserialize, Serialize};
 
 /// Deserialized from `book.ron` in the root of an `adbook` project
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct BookRon {
-    /// Use it to supply absolute paths (use `{base_url}/path` instead of `/path`)
-    // TODO: remove the trailing slash on deserializing
+    /// Use it to supply absolute paths (use `{base_url}/path` instead of `/path`). The trailing
+    /// slash is removed on deserializing (`"/docs/"` is `"/docs"` and `"/"` is `""`)
+    #[serde(deserialize_with = "deserialize_base_url")]
     pub base_url: String,
+    /// URL prefix of static assets (stylesheets, images and scripts) such as a CDN. `None` falls
+    /// back to `base_url`. Page links always use `base_url`
+    #[serde(default)]
+    pub asset_url: Option<String>,
     /// The source directory
     pub src_dir: PathBuf,
     /// The destination directory where source files are converted
     pub site_dir: PathBuf,
+    /// File name of the index files in the source directory and its sub directories. `None` is
+    /// `index.ron`
+    #[serde(default)]
+    pub index_file: Option<String>,
+    /// Maximum depth of nested `index.ron`s (the root is `0`). `None` is
+    /// [`DEFAULT_MAX_DEPTH`](crate::book::index::DEFAULT_MAX_DEPTH)
+    #[serde(default)]
+    pub max_depth: Option<usize>,
+    /// Directories (relative to the source directory) skipped while loading `index.ron`s. They're
+    /// not built nor listed in the sidebar
+    #[serde(default)]
+    pub exclude_dirs: Vec<PathBuf>,
     /// Authors of the book
     pub authors: Vec<String>,
     /// Title of the book
     pub title: String,
-    // TODO: Support collapsible sidebar
-    /// Sidebar items up to this level is open by default
+    /// `author` of the articles that don't set it (`a_author` in Handlebars templates)
+    #[serde(default)]
+    pub default_author: Option<String>,
+    /// `revdate` of the articles that don't set it (`a_revdate` in Handlebars templates)
+    #[serde(default)]
+    pub default_revdate: Option<String>,
+    /// Sidebar items shallower than this level are open by default (`None` opens all). The active
+    /// item and its ancestors are always open
     #[serde(default)]
     pub fold_level: Option<usize>,
+    /// Maximum section level of `page_toc` in Handlebars templates (`Some(1)` lists `<h2>`s only).
+    /// `None` lists all the section headings
+    #[serde(default)]
+    pub page_toc_depth: Option<usize>,
     /// Generate `all.adoc` or not. Include `all.adoc` if you use it
     pub generate_all: bool,
     /// Relative path from `src/` that are copied to `site/`
     #[serde(default)]
     pub includes: Vec<PathBuf>,
+    /// Glob patterns of files/directories in `src/` excluded from the build and `includes`. See
+    /// [`crate::book::ignore`] for the syntax
+    #[serde(default)]
+    pub ignore: Vec<String>,
     /// File/directory copies
     #[serde(default)]
     pub copies: Vec<(PathBuf, PathBuf)>,
-    /// Whether we copy and use the default `src/theme` directory or not
+    /// Whether we copy and use the default `src/theme` directory or not. An `hbs` attribute in an
+    /// article overrides it
     pub use_default_theme: bool,
+    /// Shared Handlebars partial directories (relative to the root), registered in order before
+    /// the template's own `partials` directory. Later directories override same-named partials
+    #[serde(default)]
+    pub partial_dirs: Vec<PathBuf>,
+    /// Raw HTML inserted into `<head>` of every article (`head_extra` in Handlebars templates).
+    /// It's NOT escaped
+    #[serde(default)]
+    pub head_html: Option<String>,
+    /// Whether we supply the build time (`build_time`) to Handlebars templates or not. It's off by
+    /// default so that builds are reproducible
+    #[serde(default)]
+    pub embed_build_time: bool,
+    /// Whether we supply the date of the last git commit touching the source file as `a_revdate`
+    /// to Handlebars templates when the article doesn't set `revdate`. It takes precedence over
+    /// `default_revdate`
+    #[serde(default)]
+    pub revdate_from_git: bool,
+    /// Math renderer loaded in HTML `<head>`. The `stem` attribute is set if it's not specified.
+    /// Defaults to `Some(MathJax)`, which the default theme always loaded before; `None` disables
+    /// it
+    #[serde(default = "default_math")]
+    pub math: Option<MathRenderer>,
+    /// Whether we key cached outputs by the source content hash or not
+    #[serde(default)]
+    pub cache_by_hash: bool,
+    /// Whether we write `pages.json`, the list of article metadata, to the site directory or not
+    #[serde(default)]
+    pub metadata_json: bool,
+    /// Whether we write `search_index.json` (URLs, titles and plain texts of the articles) to the
+    /// site directory or not
+    #[serde(default)]
+    pub generate_search_index: bool,
+    /// Whether we write `structure.json` (the `index.ron` tree with titles and URLs) to the site
+    /// directory or not
+    #[serde(default)]
+    pub generate_structure_json: bool,
+    /// Whether we minify the HTML outputs (collapsing whitespaces and removing comments) or not.
+    /// Contents of `<pre>` and `<code>` are preserved
+    #[serde(default)]
+    pub minify: bool,
+    /// What to do with a source file that failed to convert
+    #[serde(default)]
+    pub on_convert_error: OnConvertError,
+    /// Whether we fail on `asciidoctor` outputs that are empty for non-empty sources or not (they
+    /// are warned by default). Files with the `allow-empty` attribute are never flagged
+    #[serde(default)]
+    pub fail_on_empty: bool,
+    /// Whether we write `.adbook-cache/manifest.json`, the map of source files to output files
+    /// with hashes, or not
+    #[serde(default)]
+    pub manifest: bool,
+    /// Whether we check internal links in the generated HTML files or not
+    #[serde(default)]
+    pub check_links: bool,
+    /// Whether we check that assets (stylesheets, scripts and images) referenced by the generated
+    /// HTML files exist in the site directory or not
+    #[serde(default)]
+    pub check_assets: bool,
+    /// Publish directory summaries at `<dir>/index.html` and link to them as `<dir>/`
+    #[serde(default)]
+    pub trailing_slash: bool,
+    /// How source files are mapped to output files (mirrors the source directory by default)
+    #[serde(default)]
+    pub output_scheme: OutputScheme,
+    /// Source file mapped to the site-root `index.html` instead of the root summary. The root
+    /// summary is not built then
+    #[serde(default)]
+    pub home: Option<PathBuf>,
+    /// Converts source files in chunks of this size, writing and dropping the outputs of each
+    /// chunk before the next one to bound the peak memory. `None` converts all files at once
+    #[serde(default)]
+    pub chunk_size: Option<usize>,
     /// Files to convert, but not included in the sidebar. Typically `404.adoc`
     pub converts: Vec<PathBuf>,
+    /// `asciidoctor` attributes supplied as `-a name=value`. Placeholder strings and `${ENV_VAR}`
+    /// in the values are expanded. Attributes also specified with `-a` in `adoc_opts` are
+    /// overridden by `adoc_opts`
+    #[serde(default)]
+    pub attributes: BTreeMap<String, String>,
+    /// Book-wide attribute defaults, the lowest layer below `adoc_opts`/`attributes` and the
+    /// document attributes. They're supplied as soft-set `-a name=value@`, so pages can override
+    /// them. `None` is [`DEFAULT_ATTRIBUTES`] and `Some({})` disables the defaults
+    #[serde(default)]
+    pub default_attributes: Option<BTreeMap<String, String>>,
+    /// Attributes of build profiles (`adbook build --profile <name>`). The attributes of the active
+    /// profile override the same attributes in `attributes` and `adoc_opts`
+    #[serde(default)]
+    pub profile_attributes: BTreeMap<String, BTreeMap<String, String>>,
+    /// Prefix of the attributes supplied to Handlebars templates as `custom` (`:hbs-banner: warning`
+    /// is `{{custom.banner}}`). `None` is `hbs-`
+    #[serde(default)]
+    pub hbs_var_prefix: Option<String>,
     /// `asciidoctor` options
     pub adoc_opts: CmdOptions,
+    /// `asciidoctor` command: a name in `PATH` or a path (e.g. `asciidoctor.bat` or an rbenv shim).
+    /// `None` is `asciidoctor`
+    #[serde(default)]
+    pub adoc_cmd: Option<String>,
+    /// Arguments placed right after `adoc_cmd` (e.g. `["exec", "asciidoctor"]` with
+    /// `adoc_cmd: Some("bundle")`)
+    #[serde(default)]
+    pub adoc_args_prefix: Vec<String>,
+    /// `asciidoctor -d` (`article`, `book`, `manpage` or `inline`). `None` is `article`. Articles can
+    /// override it with the `doctype` attribute
+    #[serde(default)]
+    pub doctype: Option<String>,
+    /// `asciidoctor -b` (backend). `None` is `html5`. Handlebars templates are applied to HTML
+    /// outputs only
+    #[serde(default)]
+    pub backend: Option<String>,
+    /// `asciidoctor --failure-level` (`INFO`, `WARN`, `ERROR` or `FATAL`). `asciidoctor` exits
+    /// with nonzero on logs at or above the level and the conversion fails. It applies regardless
+    /// of `adbook build --strict`, which only covers the checks made by `adbook` itself (unresolved
+    /// includes, broken links, etc.)
+    #[serde(default)]
+    pub failure_level: Option<String>,
+    /// Convert the files to build with one `asciidoctor` process per directory instead of one
+    /// process per file
+    #[serde(default)]
+    pub batch_convert: bool,
+    /// Let `asciidoctor` write output files directly to the site directory. Handlebars templates
+    /// are not applied in this mode
+    #[serde(default)]
+    pub direct_output: bool,
+    /// Additional paths (relative to the root) watched beyond the source directory. A change to
+    /// any of them triggers a full rebuild. They have to exist
+    #[serde(default)]
+    pub watch_extra: Vec<PathBuf>,
+}
+
+/// Strips the trailing slashes so that `{base_url}/path` doesn't have double slashes
+fn deserialize_base_url<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let base_url = String::deserialize(deserializer)?;
+    Ok(base_url.trim_end_matches('/').to_string())
+}
+
+/// MathJax is loaded unless `math: None` is given explicitly
+fn default_math() -> Option<MathRenderer> {
+    Some(MathRenderer::MathJax)
+}
+
+/// Built-in `default_attributes`
+pub const DEFAULT_ATTRIBUTES: &[(&str, &str)] =
+    &[("icons", "font"), ("sectanchors", ""), ("experimental", "")];
+
+impl BookRon {
+    /// `asset_url` or `base_url`
+    pub fn asset_url(&self) -> &str {
+        self.asset_url.as_deref().unwrap_or(&self.base_url)
+    }
+
+    /// `adoc_cmd` or `asciidoctor`
+    pub fn adoc_cmd(&self) -> &str {
+        self.adoc_cmd.as_deref().unwrap_or("asciidoctor")
+    }
+
+    /// `hbs_var_prefix` or `hbs-`
+    pub fn hbs_var_prefix(&self) -> &str {
+        self.hbs_var_prefix.as_deref().unwrap_or("hbs-")
+    }
+
+    /// `index_file` or `index.ron`
+    pub fn index_file(&self) -> &str {
+        self.index_file
+            .as_deref()
+            .unwrap_or(crate::book::index::INDEX_RON)
+    }
+
+    /// If `asciidoctor` outputs HTML or not
+    pub fn is_html_backend(&self) -> bool {
+        self::is_html_backend(self.backend.as_deref())
+    }
+
+    /// Extension of output files
+    pub fn output_extension(&self) -> &'static str {
+        self::backend_extension(self.backend.as_deref())
+    }
+}
+
+/// If the `asciidoctor` backend outputs HTML or not. `None` is `html5`
+pub fn is_html_backend(backend: Option<&str>) -> bool {
+    matches!(
+        backend,
+        None | Some("html") | Some("html5") | Some("xhtml") | Some("xhtml5")
+    )
+}
+
+/// Extension of output files of an `asciidoctor` backend. `None` is `html5`
+pub fn backend_extension(backend: Option<&str>) -> &'static str {
+    if self::is_html_backend(backend) {
+        return "html";
+    }
+
+    match backend.unwrap_or_default() {
+        "docbook" | "docbook5" => "xml",
+        "manpage" => "man",
+        "pdf" => "pdf",
+        "epub3" => "epub",
+        // unknown backends such as the ones added by extensions
+        _ => "out",
+    }
+}
+
+/// Source → output path policy
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputScheme {
+    /// `src/a/b.adoc` → `site/a/b.html`
+    #[default]
+    Mirror,
+    /// `src/a/b.adoc` → `site/b.html`. Directory summaries are output as `site/<dir name>.html`
+    Flat,
+    /// `src/a/b.adoc` → `site/2020/10/b.html` using the `:revdate:` of the file. Files without
+    /// (valid) `:revdate:` fall back to [`OutputScheme::Mirror`]
+    DateBased,
+}
+
+/// Handling of source files that failed to convert
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnConvertError {
+    /// Report the error and leave the page missing
+    #[default]
+    Abort,
+    /// Write a page saying that it failed to build (with the error) at the URL
+    Placeholder,
+    /// Leave the page missing and report the error as a warning
+    Skip,
+}
+
+/// Math renderer for `stem` blocks and macros
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MathRenderer {
+    /// Supports `latexmath` only
+    KaTeX,
+    /// Supports both `latexmath` and `asciimath`
+    MathJax,
 }
 
 /// Deserialized from `index.ron` in sub directories in a source directory of an `adbook` project
@@ -51,18 +317,84 @@ pub struct IndexRon {
     pub summary: (String, PathBuf),
     /// Child items
     pub items: Vec<IndexRonItem>,
+    /// Order of the child items
+    #[serde(default)]
+    pub order_by: OrderBy,
+    /// Handlebars template for the files in this directory without their own `hbs` attribute,
+    /// inherited by sub directories. The path is relative to the source directory as the `hbs`
+    /// attribute
+    #[serde(default)]
+    pub default_hbs: Option<PathBuf>,
 }
 
-/// `File` | `Dir`
+/// Order of items in an `index.ron`
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OrderBy {
+    /// As listed in `index.ron`
+    #[default]
+    Manual,
+    /// By the leading number of file/directory names (`01-intro.adoc`, `02_setup.adoc`, ..),
+    /// which is stripped from the sidebar titles. Items without the number come last
+    FilenamePrefix,
+    /// By the sidebar titles
+    Title,
+}
+
+/// `File` | `Dir` | `Link`
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub enum IndexRonItem {
-    /// `(title, url)`. If `title` is left as empty (`""`), the sidebar title is extracted from the
-    /// source file.
-    File(String, PathBuf),
+    /// `(title, url)` or `(title, url, output)`. If `title` is left as empty (`""`), the sidebar
+    /// title is extracted from the source file.
+    ///
+    /// `output` is an optional path to the output file relative to the site directory (e.g.
+    /// `"guide/intro.html"`), which overrides the path derived from `output_scheme`. Its extension
+    /// is replaced with the one of the `asciidoctor` backend.
+    File(
+        String,
+        PathBuf,
+        #[serde(default, deserialize_with = "deserialize_output_path")] Option<PathBuf>,
+    ),
     Dir(PathBuf),
+    /// `(title, url)`. External link in the sidebar, which has no source file
+    Link(String, String),
+}
+
+/// Deserializes the optional output path of `File` from a plain string (not `Some("..")`)
+fn deserialize_output_path<'de, D>(deserializer: D) -> Result<Option<PathBuf>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    PathBuf::deserialize(deserializer).map(Some)
 }
 
 /// Arguments to a command
 ///
 /// `[("--one-option", ["a", "b"]), ("--another", []), ..]`.
 pub type CmdOptions = Vec<(String, Vec<String>)>;
+
+#[cfg(test)]
+mod test {
+    use super::{BookRon, MathRenderer};
+
+    const BOOK_RON: &str = r#"
+base_url: "",
+src_dir: "src",
+site_dir: "site",
+authors: [],
+title: "math",
+generate_all: false,
+use_default_theme: true,
+converts: [],
+adoc_opts: [],
+"#;
+
+    #[test]
+    fn math_defaults_to_mathjax() {
+        let book_ron: BookRon = crate::utils::load_ron(BOOK_RON).unwrap();
+        assert_eq!(book_ron.math, Some(MathRenderer::MathJax));
+
+        let book_ron: BookRon =
+            crate::utils::load_ron(&format!("{}math: None,", BOOK_RON)).unwrap();
+        assert_eq!(book_ron.math, None);
+    }
+}
@@ -0,0 +1,141 @@
+//! `ignore` patterns in `book.ron`
+//!
+//! Patterns are globs relative to the source directory, delimited with `/` (see [`globset`] for
+//! the syntax):
+//!
+//! * `*` matches any characters in a path segment and `?` matches one character.
+//! * `**` matches any number of path segments.
+//! * `[ab]` matches one of the characters and `{a,b}` matches one of the alternatives.
+//! * A pattern without `/` matches a file or directory name at any depth (`*.tmp`, `scratch`).
+//! * Otherwise the pattern is anchored to the source directory (`drafts/**`, `notes/todo.adoc`).
+//!
+//! Matching a directory ignores everything under it.
+
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::*;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+
+/// Compiled `ignore` patterns
+#[derive(Debug, Clone)]
+pub struct IgnorePatterns {
+    set: GlobSet,
+}
+
+impl Default for IgnorePatterns {
+    fn default() -> Self {
+        Self {
+            set: GlobSet::empty(),
+        }
+    }
+}
+
+impl IgnorePatterns {
+    /// Compiles the patterns. Fails on the first invalid pattern
+    pub fn new(patterns: &[String]) -> Result<Self> {
+        let mut builder = GlobSetBuilder::new();
+
+        for pattern in patterns {
+            let glob = pattern.trim_start_matches('/').trim_end_matches('/');
+            if glob.is_empty() {
+                continue;
+            }
+
+            // file or directory name at any depth
+            let glob = if glob.contains('/') {
+                glob.to_string()
+            } else {
+                format!("**/{}", glob)
+            };
+
+            let glob = GlobBuilder::new(&glob)
+                .literal_separator(true)
+                .build()
+                .with_context(|| {
+                    format!("Invalid `ignore` pattern in `book.ron`: `{}`", pattern)
+                })?;
+            builder.add(glob);
+        }
+
+        let set = builder
+            .build()
+            .context("Unable to compile `ignore` patterns in `book.ron`")?;
+        Ok(Self { set })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty()
+    }
+
+    /// If the path relative to the source directory or any of its ancestor directories matches
+    /// the patterns
+    pub fn is_ignored(&self, rel_path: &Path) -> bool {
+        if self.set.is_empty() {
+            return false;
+        }
+
+        let mut path = PathBuf::new();
+        for c in rel_path.components() {
+            if let Component::Normal(seg) = c {
+                path.push(seg);
+                if self.set.is_match(&path) {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::IgnorePatterns;
+
+    fn compile(patterns: &[&str]) -> IgnorePatterns {
+        let patterns = patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        IgnorePatterns::new(&patterns).unwrap()
+    }
+
+    #[test]
+    fn patterns() {
+        let patterns = self::compile(&["*.tmp", "drafts/**"]);
+        let ignored = |path: &str| patterns.is_ignored(Path::new(path));
+
+        assert!(ignored("a.tmp"));
+        assert!(ignored("sub/b.tmp"));
+        assert!(!ignored("a.tmp.adoc"));
+        assert!(!ignored("a.adoc"));
+
+        assert!(ignored("drafts/a.adoc"));
+        assert!(ignored("drafts/sub/b.adoc"));
+        assert!(!ignored("sub/drafts/a.adoc"));
+        assert!(!ignored("drafts.adoc"));
+
+        // directory names and anchored paths
+        let patterns = self::compile(&["scratch", "notes/t?do.adoc"]);
+        let ignored = |path: &str| patterns.is_ignored(Path::new(path));
+
+        assert!(ignored("scratch/a.adoc"));
+        assert!(ignored("sub/scratch/a.adoc"));
+        assert!(ignored("notes/todo.adoc"));
+        assert!(!ignored("sub/notes/todo.adoc"));
+        assert!(!ignored("notes/a.adoc"));
+
+        // character classes and alternatives
+        let patterns = self::compile(&["[ab].adoc", "img/*.{psd,xcf}"]);
+        let ignored = |path: &str| patterns.is_ignored(Path::new(path));
+
+        assert!(ignored("a.adoc"));
+        assert!(ignored("sub/b.adoc"));
+        assert!(!ignored("c.adoc"));
+        assert!(ignored("img/a.psd"));
+        assert!(ignored("img/a.xcf"));
+        assert!(!ignored("img/a.png"));
+
+        // invalid patterns are reported
+        assert!(IgnorePatterns::new(&["a[".to_string()]).is_err());
+    }
+}
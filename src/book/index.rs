@@ -6,15 +6,21 @@ It's recursive and makes up a book file structure.
 
 use {
     std::{
-        fmt, fs, io,
-        path::{Path, PathBuf},
+        collections::BTreeMap,
+        fmt, fs,
+        io::{self, BufRead},
+        path::{Component, Path, PathBuf},
     },
     thiserror::Error,
 };
 
-use crate::book::config::{IndexRon, IndexRonItem};
+use crate::book::config::{IndexRon, IndexRonItem, OrderBy};
+
+/// Default file name of index files (`index_file` in `book.ron`)
+pub const INDEX_RON: &str = "index.ron";
 
-const INDEX_RON: &'static str = "index.ron";
+/// Default maximum depth of nested `index.ron`s
+pub const DEFAULT_MAX_DEPTH: usize = 32;
 
 /// Error when loading `index.ron`
 #[derive(Debug, Error)]
@@ -27,12 +33,24 @@ pub enum IndexLoadError {
     FailedToLocateSummary(PathBuf, PathBuf),
     #[error("Unexpected item with path: {0}")]
     FoundOddItem(PathBuf),
-    #[error("Found directory without `index.ron`: {0}")]
-    FoundDirectoryWithoutIndexRon(PathBuf),
+    /// (relative_path_to_the_file, book_ron_directory_path)
+    #[error("{}", crate::book::UnsupportedSourceFormat(.1.join(.0)))]
+    UnsupportedSourceFile(PathBuf, PathBuf),
+    /// (directory_path, index_file_name)
+    #[error("Found directory without `{1}`: {0}")]
+    FoundDirectoryWithoutIndexRon(PathBuf, String),
     #[error("Failed to read `index.ron` at: {0}. IO error: {1}")]
     FailedToReadIndexRon(PathBuf, io::Error),
     #[error("Failed to parse `index.ron` at: {0}")]
     FailedToParseIndexRon(PathBuf, ron::Error),
+    /// (directory_path, max_depth)
+    #[error("Directory `{0}` exceeds the maximum depth of nested `index.ron`s ({1})")]
+    MaxDepthExceeded(PathBuf, usize),
+    #[error("Index file name has to be a plain file name: `{0}`")]
+    InvalidIndexFileName(String),
+    /// (output_path, book_ron_directory_path)
+    #[error("Output path `{0}` in `{1}` has to be a relative path within the site directory")]
+    InvalidOutputPath(PathBuf, PathBuf),
     #[error("Errors in sub `index.ron`: {0}")]
     FoundErrorsInSubIndex(Box<SubIndexLoadErrors>),
 }
@@ -52,6 +70,40 @@ impl fmt::Display for SubIndexLoadErrors {
     }
 }
 
+/// Options for loading `index.ron`s
+#[derive(Debug, Clone)]
+pub struct IndexLoadOptions {
+    /// Absolute paths to directories that are skipped along with their contents
+    pub exclude_dirs: Vec<PathBuf>,
+    /// Directories deeper than this are not loaded (the root is `0`)
+    pub max_depth: usize,
+    /// File name of index files (`index.ron` by default)
+    pub index_file: String,
+}
+
+impl Default for IndexLoadOptions {
+    fn default() -> Self {
+        Self {
+            exclude_dirs: vec![],
+            max_depth: DEFAULT_MAX_DEPTH,
+            index_file: INDEX_RON.to_string(),
+        }
+    }
+}
+
+/// Fails if the index file name is not a plain file name (e.g. `sub/index.ron` or `..`)
+pub fn validate_index_file(name: &str) -> Result<&str, IndexLoadError> {
+    let is_plain = !name.is_empty()
+        && !name.contains(['/', '\\'])
+        && Path::new(name).file_name() == Some(name.as_ref());
+
+    if !is_plain {
+        return Err(IndexLoadError::InvalidIndexFileName(name.to_string()));
+    }
+
+    Ok(name)
+}
+
 /// The recursive book structure, corresponds to `mod.rs` in Rust
 #[derive(Debug, Clone)]
 pub struct Index {
@@ -62,6 +114,13 @@ pub struct Index {
     pub summary: PathBuf,
     /// Items
     pub items: Vec<IndexItem>,
+    /// Order of the items (they're already sorted)
+    pub order_by: OrderBy,
+    /// (absolute_path, output_path) of the file items with explicit output paths. Output paths
+    /// are relative to the site directory
+    pub outputs: BTreeMap<PathBuf, PathBuf>,
+    /// `default_hbs` of this directory or the inherited one
+    pub default_hbs: Option<PathBuf>,
 }
 
 #[derive(Debug, Clone)]
@@ -69,16 +128,74 @@ pub enum IndexItem {
     /// (name, absolute_path)
     File(String, PathBuf),
     Dir(Box<Index>),
+    /// (name, url)
+    Link(String, String),
 }
 
 impl Index {
+    /// Finds the directory that the file summarizes, recursively
+    pub fn find_summarized_dir(&self, file: &Path) -> Option<&Path> {
+        if self.summary == file {
+            return Some(&self.dir);
+        }
+
+        self.items.iter().find_map(|item| match item {
+            IndexItem::Dir(index) => index.find_summarized_dir(file),
+            _ => None,
+        })
+    }
+
+    /// Finds the explicit output path of a file, recursively
+    pub fn find_output(&self, file: &Path) -> Option<&Path> {
+        if let Some(output) = self.outputs.get(file) {
+            return Some(output);
+        }
+
+        self.items.iter().find_map(|item| match item {
+            IndexItem::Dir(index) => index.find_output(file),
+            _ => None,
+        })
+    }
+
+    /// Finds the default Handlebars template of a file (its directory's `default_hbs`),
+    /// recursively
+    pub fn find_default_hbs(&self, file: &Path) -> Option<&Path> {
+        let is_own_file = self.summary == file
+            || self
+                .items
+                .iter()
+                .any(|item| matches!(item, IndexItem::File(_name, f) if f == file));
+        if is_own_file {
+            return self.default_hbs.as_deref();
+        }
+
+        self.items.iter().find_map(|item| match item {
+            IndexItem::Dir(index) => index.find_default_hbs(file),
+            _ => None,
+        })
+    }
+
     /// Loads `index.ron` recursively. Invalid items are excluded
     pub fn from_index_ron_recursive(
         ix_ron: &IndexRon,
         ix_ron_dir: &Path,
+        opts: &IndexLoadOptions,
+    ) -> Result<(Self, Vec<IndexLoadError>), IndexLoadError> {
+        Self::load_recursive(ix_ron, ix_ron_dir, opts, 0, None)
+    }
+
+    fn load_recursive(
+        ix_ron: &IndexRon,
+        ix_ron_dir: &Path,
+        opts: &IndexLoadOptions,
+        depth: usize,
+        parent_hbs: Option<&Path>,
     ) -> Result<(Self, Vec<IndexLoadError>), IndexLoadError> {
+        let default_hbs = ix_ron.default_hbs.as_deref().or(parent_hbs);
+
         let mut errors = vec![];
         let mut items = vec![];
+        let mut outputs = BTreeMap::new();
 
         // trace!("parsing `index.ron` at directory `{}`", index_ron_dir.display());
 
@@ -91,14 +208,23 @@ impl Index {
                     ix_ron_dir.to_owned(),
                 ));
             }
+            if !crate::book::is_src_file(&file) {
+                return Err(IndexLoadError::UnsupportedSourceFile(
+                    ix_ron.summary.1.to_owned(),
+                    ix_ron_dir.to_owned(),
+                ));
+            }
             file.canonicalize().unwrap()
         };
 
         for item in &ix_ron.items {
             match item {
-                IndexRonItem::File(name, rel_path) => {
+                IndexRonItem::File(name, rel_path, output) => {
                     let path = {
                         let path = ix_ron_dir.join(rel_path);
+                        if self::is_excluded(&path, &opts.exclude_dirs) {
+                            continue;
+                        }
                         if !path.exists() {
                             errors.push(IndexLoadError::FailedToLocateItem(
                                 rel_path.into(),
@@ -106,14 +232,36 @@ impl Index {
                             ));
                             continue;
                         }
+                        if !crate::book::is_src_file(&path) {
+                            errors.push(IndexLoadError::UnsupportedSourceFile(
+                                rel_path.into(),
+                                ix_ron_dir.to_path_buf(),
+                            ));
+                            continue;
+                        }
                         path.canonicalize().unwrap()
                     };
 
+                    if let Some(output) = output {
+                        if !self::is_valid_output_path(output) {
+                            errors.push(IndexLoadError::InvalidOutputPath(
+                                output.to_path_buf(),
+                                ix_ron_dir.to_path_buf(),
+                            ));
+                            continue;
+                        }
+                        outputs.insert(path.clone(), output.to_path_buf());
+                    }
+
                     items.push(IndexItem::File(name.to_string(), path));
                 }
                 IndexRonItem::Dir(rel_path) => {
                     let path = {
                         let path = ix_ron_dir.join(rel_path);
+                        if self::is_excluded(&path, &opts.exclude_dirs) {
+                            log::trace!("excluded directory: {}", path.display());
+                            continue;
+                        }
                         if !path.exists() {
                             errors.push(IndexLoadError::FailedToLocateItem(
                                 rel_path.into(),
@@ -124,11 +272,19 @@ impl Index {
                         path.canonicalize().unwrap()
                     };
 
+                    if depth + 1 > opts.max_depth {
+                        errors.push(IndexLoadError::MaxDepthExceeded(path, opts.max_depth));
+                        continue;
+                    }
+
                     let (index, index_errors) = {
                         let nested_index_ron = {
-                            let file = path.join(INDEX_RON);
+                            let file = path.join(&opts.index_file);
                             if !file.is_file() {
-                                errors.push(IndexLoadError::FoundDirectoryWithoutIndexRon(path));
+                                errors.push(IndexLoadError::FoundDirectoryWithoutIndexRon(
+                                    path,
+                                    opts.index_file.clone(),
+                                ));
                                 continue;
                             }
                             file
@@ -155,7 +311,8 @@ impl Index {
                             }
                         };
 
-                        match Index::from_index_ron_recursive(&index_ron, &path) {
+                        match Index::load_recursive(&index_ron, &path, opts, depth + 1, default_hbs)
+                        {
                             Ok((a, b)) => (a, b),
                             Err(err) => {
                                 errors.push(err);
@@ -174,17 +331,581 @@ impl Index {
 
                     items.push(IndexItem::Dir(Box::new(index)));
                 }
+                IndexRonItem::Link(name, url) => {
+                    items.push(IndexItem::Link(name.to_string(), url.to_string()));
+                }
             }
         }
 
+        self::sort_items(&mut items, ix_ron.order_by);
+
         Ok((
             Self {
                 dir: ix_ron_dir.to_path_buf(),
                 name: ix_ron.summary.0.to_owned(),
                 summary: preface,
                 items,
+                order_by: ix_ron.order_by,
+                outputs,
+                default_hbs: default_hbs.map(Path::to_path_buf),
             },
             errors,
         ))
     }
 }
+
+/// Output paths have to be relative paths without `..`
+fn is_valid_output_path(path: &Path) -> bool {
+    path.file_name().is_some() && path.components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+impl IndexItem {
+    /// Sidebar title: the name in `index.ron` or the document title of the source file
+    pub fn title(&self) -> Option<String> {
+        let (name, file) = match self {
+            IndexItem::File(name, file) => (name, file),
+            IndexItem::Dir(index) => (&index.name, &index.summary),
+            IndexItem::Link(name, _url) => return Some(name.to_string()),
+        };
+
+        if !name.is_empty() {
+            return Some(name.to_string());
+        }
+
+        self::peek_title(file)
+    }
+
+    /// File or directory name
+    fn file_name(&self) -> Option<&str> {
+        match self {
+            IndexItem::File(_name, file) => file.file_name()?.to_str(),
+            IndexItem::Dir(index) => index.dir.file_name()?.to_str(),
+            IndexItem::Link(_name, _url) => None,
+        }
+    }
+}
+
+/// Reads the first line of a file and if it starts with `= ` it is the title. Plain text files
+/// are titled with the file stem
+pub fn peek_title(file: &Path) -> Option<String> {
+    if crate::book::is_text_file(file) {
+        return Some(file.file_stem()?.to_string_lossy().into_owned());
+    }
+
+    let f = fs::File::open(file).ok()?;
+    let mut buf = String::with_capacity(200);
+    io::BufReader::new(f).read_line(&mut buf).ok()?;
+
+    buf.strip_prefix("= ").map(|title| title.trim().to_string())
+}
+
+/// Parses the leading number of a file name: `01-intro.adoc`, `01_intro`, `01.intro` or `1 intro`
+pub fn numeric_prefix(name: &str) -> Option<(u64, &str)> {
+    let n_digits = name.chars().take_while(|c| c.is_ascii_digit()).count();
+    if n_digits == 0 {
+        return None;
+    }
+
+    let number = name[..n_digits].parse().ok()?;
+    let rest = name[n_digits..].trim_start_matches(['-', '_', '.', ' ']);
+    Some((number, rest))
+}
+
+/// Strips the leading number of a title (`01-Introduction` -> `Introduction`)
+pub fn strip_numeric_prefix(title: &str) -> &str {
+    match self::numeric_prefix(title) {
+        Some((_number, rest)) if !rest.is_empty() => rest,
+        _ => title,
+    }
+}
+
+/// Appends `File("", "<rel_path>")` to the `items` of an `index.ron` text, keeping the other
+/// lines as they are. Returns `None` if the `items: [..]` list is not found
+pub fn append_file_item(index_ron: &str, rel_path: &str) -> Option<String> {
+    // byte offsets of the `[` and `]` of `items`
+    let (open, close) = self::find_items_list(index_ron)?;
+
+    let item = format!("File(\"\", \"{}\")", rel_path.escape_default());
+
+    // the end of the last item (or the `[`), ignoring whitespaces and comments
+    let last =
+        self::skip_comments_and_whitespaces(&index_ron[open + 1..close]).map(|end| open + 1 + end);
+
+    let mut out = String::with_capacity(index_ron.len() + item.len() + 8);
+    match last {
+        Some(end) => {
+            let line_start = index_ron[..end].rfind('\n').map_or(0, |pos| pos + 1);
+            let indent: String = index_ron[line_start..]
+                .chars()
+                .take_while(|c| *c == ' ' || *c == '\t')
+                .collect();
+            let comma = if index_ron[..end].ends_with(',') {
+                ""
+            } else {
+                ","
+            };
+
+            out.push_str(&index_ron[..end]);
+            out.push_str(&format!("{}\n{}{},", comma, indent, item));
+            out.push_str(&index_ron[end..]);
+        }
+        None => {
+            // empty list
+            out.push_str(&index_ron[..open + 1]);
+            out.push_str(&format!("\n    {},\n", item));
+            out.push_str(index_ron[close..].trim_start_matches([' ', '\t']));
+        }
+    }
+
+    Some(out)
+}
+
+/// Finds the `[` and `]` of `items: [..]` skipping strings and comments
+fn find_items_list(text: &str) -> Option<(usize, usize)> {
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    let mut open = None;
+    let mut depth = 0usize;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => {
+                // skip the string
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    if bytes[i] == b'\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                i += text[i..].find('\n').unwrap_or(text.len() - i);
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i += text[i..].find("*/").map_or(text.len() - i, |end| end + 1);
+            }
+            b'[' if open.is_some() => depth += 1,
+            b'[' => {
+                // `items` `:` `[`
+                let field = text[..i]
+                    .trim_end()
+                    .strip_suffix(':')
+                    .map(|before| before.trim_end())
+                    .and_then(|before| before.strip_suffix("items"));
+                match field {
+                    Some(before)
+                        if !before.ends_with(|c: char| c.is_alphanumeric() || c == '_') =>
+                    {
+                        open = Some(i);
+                    }
+                    _ => depth += 1,
+                }
+            }
+            b']' if depth > 0 => depth -= 1,
+            b']' => {
+                if let Some(open) = open {
+                    return Some((open, i));
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    None
+}
+
+/// Byte offset of the end of the last token in the text, ignoring whitespaces and comments
+fn skip_comments_and_whitespaces(text: &str) -> Option<usize> {
+    let mut last = None;
+    let mut offset = 0;
+
+    for line in text.split_inclusive('\n') {
+        // NOTE: comment marks in strings are not considered; file names don't contain `//`
+        let code = match line.find("//") {
+            Some(pos) => &line[..pos],
+            None => line,
+        };
+        let code = code.trim_end();
+        if !code.is_empty() {
+            last = Some(offset + code.len());
+        }
+        offset += line.len();
+    }
+
+    last
+}
+
+/// Sorts items stably
+fn sort_items(items: &mut [IndexItem], order_by: OrderBy) {
+    match order_by {
+        OrderBy::Manual => {}
+        OrderBy::FilenamePrefix => {
+            // items without the number come last
+            items.sort_by_key(|item| {
+                item.file_name()
+                    .and_then(self::numeric_prefix)
+                    .map(|(number, _rest)| number)
+                    .unwrap_or(u64::MAX)
+            });
+        }
+        OrderBy::Title => {
+            items.sort_by_cached_key(|item| item.title().unwrap_or_default().to_lowercase());
+        }
+    }
+}
+
+/// If the path is in one of the excluded directories
+fn is_excluded(path: &Path, exclude_dirs: &[PathBuf]) -> bool {
+    if exclude_dirs.is_empty() {
+        return false;
+    }
+
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    exclude_dirs.iter().any(|dir| path.starts_with(dir))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    use super::{
+        append_file_item, strip_numeric_prefix, validate_index_file, Index, IndexItem,
+        IndexLoadError, IndexLoadOptions,
+    };
+    use crate::book::config::{IndexRon, IndexRonItem, OrderBy};
+
+    #[test]
+    fn unsupported_extensions() {
+        let dir = std::env::temp_dir().join("adbook-test-unsupported-extensions");
+        fs::create_dir_all(&dir).unwrap();
+        for file in &["index.adoc", "article.adoc", "slides.pdf", "noext"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+
+        let index_ron = IndexRon {
+            summary: ("".to_string(), PathBuf::from("index.adoc")),
+            items: vec![
+                IndexRonItem::File("".to_string(), PathBuf::from("article.adoc"), None),
+                IndexRonItem::File("".to_string(), PathBuf::from("slides.pdf"), None),
+                IndexRonItem::File("".to_string(), PathBuf::from("noext"), None),
+            ],
+            order_by: OrderBy::Manual,
+            default_hbs: None,
+        };
+
+        let (index, errors) =
+            Index::from_index_ron_recursive(&index_ron, &dir, &Default::default()).unwrap();
+
+        assert_eq!(index.items.len(), 1);
+        assert!(matches!(&index.items[0], IndexItem::File(_, p) if p.ends_with("article.adoc")));
+
+        assert_eq!(errors.len(), 2);
+        assert!(
+            matches!(&errors[0], IndexLoadError::UnsupportedSourceFile(p, _) if p == &PathBuf::from("slides.pdf"))
+        );
+        assert!(
+            matches!(&errors[1], IndexLoadError::UnsupportedSourceFile(p, _) if p == &PathBuf::from("noext"))
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn excluded_dirs() {
+        let dir = std::env::temp_dir().join("adbook-test-excluded-dirs");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("_drafts")).unwrap();
+        fs::create_dir_all(dir.join("part")).unwrap();
+        for file in &["index.adoc", "part/index.adoc", "_drafts/wip.adoc"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        // `_drafts/` has no proper `index.ron`
+        fs::write(
+            dir.join("part/index.ron"),
+            r#"summary: ("Part", "index.adoc"), items: []"#,
+        )
+        .unwrap();
+
+        let index_ron = IndexRon {
+            summary: ("".to_string(), PathBuf::from("index.adoc")),
+            items: vec![
+                IndexRonItem::Dir(PathBuf::from("part")),
+                IndexRonItem::Dir(PathBuf::from("_drafts")),
+                IndexRonItem::File("".to_string(), PathBuf::from("_drafts/wip.adoc"), None),
+            ],
+            order_by: OrderBy::Manual,
+            default_hbs: None,
+        };
+
+        let dir = dir.canonicalize().unwrap();
+        let opts = IndexLoadOptions {
+            exclude_dirs: vec![dir.join("_drafts")],
+            ..Default::default()
+        };
+        let (index, errors) = Index::from_index_ron_recursive(&index_ron, &dir, &opts).unwrap();
+
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(index.items.len(), 1);
+        assert!(matches!(&index.items[0], IndexItem::Dir(ix) if ix.dir.ends_with("part")));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn default_hbs() {
+        let dir = std::env::temp_dir().join("adbook-test-default-hbs");
+        let _ = fs::remove_dir_all(&dir);
+        for sub in &["a", "a/nested", "b"] {
+            fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        for file in &[
+            "index.adoc",
+            "top.adoc",
+            "a/index.adoc",
+            "a/nested/index.adoc",
+            "a/nested/x.adoc",
+            "b/index.adoc",
+        ] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        fs::write(
+            dir.join("a/index.ron"),
+            r#"summary: ("", "index.adoc"), items: [Dir("nested")], default_hbs: Some("a.hbs")"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("a/nested/index.ron"),
+            r#"summary: ("", "index.adoc"), items: [File("", "x.adoc")]"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("b/index.ron"),
+            r#"summary: ("", "index.adoc"), items: []"#,
+        )
+        .unwrap();
+
+        let index_ron = IndexRon {
+            summary: ("".to_string(), PathBuf::from("index.adoc")),
+            items: vec![
+                IndexRonItem::File("".to_string(), PathBuf::from("top.adoc"), None),
+                IndexRonItem::Dir(PathBuf::from("a")),
+                IndexRonItem::Dir(PathBuf::from("b")),
+            ],
+            order_by: OrderBy::Manual,
+            default_hbs: None,
+        };
+
+        let dir = dir.canonicalize().unwrap();
+        let (index, errors) =
+            Index::from_index_ron_recursive(&index_ron, &dir, &Default::default()).unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        assert_eq!(index.find_default_hbs(&dir.join("top.adoc")), None);
+        assert_eq!(index.find_default_hbs(&dir.join("b/index.adoc")), None);
+        // set in `a/index.ron` and inherited by `a/nested`
+        let a_hbs = Some(Path::new("a.hbs"));
+        assert_eq!(index.find_default_hbs(&dir.join("a/index.adoc")), a_hbs);
+        assert_eq!(index.find_default_hbs(&dir.join("a/nested/x.adoc")), a_hbs);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn custom_index_file() {
+        let dir = std::env::temp_dir().join("adbook-test-index-file");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("part")).unwrap();
+        for file in &["index.adoc", "part/index.adoc"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        fs::write(
+            dir.join("part/toc.ron"),
+            r#"summary: ("Part", "index.adoc"), items: []"#,
+        )
+        .unwrap();
+
+        let index_ron = IndexRon {
+            summary: ("".to_string(), PathBuf::from("index.adoc")),
+            items: vec![IndexRonItem::Dir(PathBuf::from("part"))],
+            order_by: OrderBy::Manual,
+            default_hbs: None,
+        };
+
+        let dir = dir.canonicalize().unwrap();
+
+        // `index.ron` is not there
+        let (_index, errors) =
+            Index::from_index_ron_recursive(&index_ron, &dir, &Default::default()).unwrap();
+        assert!(matches!(
+            &errors[..],
+            [IndexLoadError::FoundDirectoryWithoutIndexRon(_, name)] if name == "index.ron"
+        ));
+
+        let opts = IndexLoadOptions {
+            index_file: "toc.ron".to_string(),
+            ..Default::default()
+        };
+        let (index, errors) = Index::from_index_ron_recursive(&index_ron, &dir, &opts).unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert!(matches!(&index.items[0], IndexItem::Dir(ix) if ix.name == "Part"));
+
+        assert!(validate_index_file("toc.ron").is_ok());
+        assert!(validate_index_file("sub/toc.ron").is_err());
+        assert!(validate_index_file("..").is_err());
+        assert!(validate_index_file("").is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn max_depth() {
+        let dir = std::env::temp_dir().join("adbook-test-max-depth");
+        let _ = fs::remove_dir_all(&dir);
+
+        // `a/b/c`, each with `index.ron` listing the next directory
+        let mut sub = dir.clone();
+        for name in &["", "a", "b", "c"] {
+            sub = sub.join(name);
+            fs::create_dir_all(&sub).unwrap();
+            fs::write(sub.join("index.adoc"), "").unwrap();
+        }
+        for (parent, child) in &[("a", "b"), ("a/b", "c")] {
+            fs::write(
+                dir.join(parent).join("index.ron"),
+                format!(r#"summary: ("", "index.adoc"), items: [Dir("{}")]"#, child),
+            )
+            .unwrap();
+        }
+        fs::write(
+            dir.join("a/b/c/index.ron"),
+            r#"summary: ("", "index.adoc"), items: []"#,
+        )
+        .unwrap();
+
+        let index_ron = IndexRon {
+            summary: ("".to_string(), PathBuf::from("index.adoc")),
+            items: vec![IndexRonItem::Dir(PathBuf::from("a"))],
+            order_by: OrderBy::Manual,
+            default_hbs: None,
+        };
+
+        let opts = IndexLoadOptions {
+            max_depth: 2,
+            ..Default::default()
+        };
+        let (index, errors) = Index::from_index_ron_recursive(&index_ron, &dir, &opts).unwrap();
+
+        // `a` (depth 1) and `a/b` (depth 2) are loaded, but not `a/b/c` (depth 3)
+        let a = match &index.items[0] {
+            IndexItem::Dir(a) => a,
+            _ => panic!("expected directory"),
+        };
+        let b = match &a.items[0] {
+            IndexItem::Dir(b) => b,
+            _ => panic!("expected directory"),
+        };
+        assert!(b.items.is_empty());
+
+        assert_eq!(errors.len(), 1);
+        let sub_errors = match &errors[0] {
+            IndexLoadError::FoundErrorsInSubIndex(errors) => &errors.errors,
+            err => panic!("unexpected error: {}", err),
+        };
+        assert!(matches!(
+            &sub_errors[0],
+            IndexLoadError::FoundErrorsInSubIndex(errors)
+                if matches!(&errors.errors[0], IndexLoadError::MaxDepthExceeded(p, 2) if p.ends_with("c"))
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn filename_prefix_order() {
+        let dir = std::env::temp_dir().join("adbook-test-filename-prefix");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for file in &[
+            "index.adoc",
+            "10.last.adoc",
+            "2_setup.adoc",
+            "01-intro.adoc",
+            "misc.adoc",
+        ] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+
+        let index_ron = IndexRon {
+            summary: ("".to_string(), PathBuf::from("index.adoc")),
+            items: ["misc.adoc", "10.last.adoc", "2_setup.adoc", "01-intro.adoc"]
+                .iter()
+                .map(|file| IndexRonItem::File("".to_string(), PathBuf::from(file), None))
+                .collect(),
+            order_by: OrderBy::FilenamePrefix,
+            default_hbs: None,
+        };
+
+        let (index, errors) =
+            Index::from_index_ron_recursive(&index_ron, &dir, &Default::default()).unwrap();
+        assert!(errors.is_empty());
+
+        let names = index
+            .items
+            .iter()
+            .map(|item| match item {
+                IndexItem::File(_, path) => path.file_name().unwrap().to_str().unwrap(),
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec!["01-intro.adoc", "2_setup.adoc", "10.last.adoc", "misc.adoc"]
+        );
+
+        assert_eq!(strip_numeric_prefix("01-Introduction"), "Introduction");
+        assert_eq!(strip_numeric_prefix("2. Setup"), "Setup");
+        assert_eq!(strip_numeric_prefix("2020"), "2020");
+        assert_eq!(strip_numeric_prefix("Intro"), "Intro");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn append_item() {
+        // the init file: the item is added after the last item, before the comments
+        let text = std::str::from_utf8(crate::book::init::files::src::INDEX_RON).unwrap();
+        let appended = append_file_item(text, "new.adoc").unwrap();
+        assert!(appended.contains(
+            "    File(\"\", \"article.adoc\"),\n    File(\"\", \"new.adoc\"),\n    // Dir(\"path\"),"
+        ));
+        let index: IndexRon = crate::utils::load_ron(&appended).unwrap();
+        assert_eq!(index.items.len(), 2);
+
+        // without trailing comma
+        let text = "summary: (\"\", \"index.adoc\"),\nitems: [\n  Dir(\"sub\")\n],\n";
+        assert_eq!(
+            append_file_item(text, "a.adoc").unwrap(),
+            "summary: (\"\", \"index.adoc\"),\nitems: [\n  Dir(\"sub\"),\n  File(\"\", \"a.adoc\"),\n],\n"
+        );
+
+        // empty list
+        let text = "summary: (\"\", \"index.adoc\"),\nitems: [],\n";
+        assert_eq!(
+            append_file_item(text, "a.adoc").unwrap(),
+            "summary: (\"\", \"index.adoc\"),\nitems: [\n    File(\"\", \"a.adoc\"),\n],\n"
+        );
+
+        // `items` in strings and comments are not the field
+        let text = "// items: [\nsummary: (\"items: [\", \"index.adoc\"),\nitems: [\n    File(\"\", \"x.adoc\"),\n]";
+        let appended = append_file_item(text, "a.adoc").unwrap();
+        let index: IndexRon = crate::utils::load_ron(&appended).unwrap();
+        assert_eq!(index.items.len(), 2);
+
+        assert!(append_file_item("summary: (\"\", \"index.adoc\")", "a.adoc").is_none());
+    }
+}
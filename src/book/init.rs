@@ -4,7 +4,12 @@ Files/directories that are created when initializing a book directory
 TODO: Auto gen with `build.rs`
 */
 
-use std::{fs, io, path::Path};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::book::{index, BookStructure};
 
 pub mod files {
     //! Init files in bytes
@@ -108,6 +113,39 @@ static THEME_ITEMS: &'static [(&str, &[u8])] = {
     ]
 };
 
+/// Preset files printed with `adbook preset`: `(name, aliases, bytes)`
+pub static PRESETS: &[(&str, &[&str], &[u8])] = &[
+    ("book.ron", &["b", "book"], files::BOOK),
+    ("index.ron", &["i", "index"], files::src::INDEX_RON),
+    ("article.adoc", &["a", "article"], files::src::ARTICLE),
+];
+
+/// Embedded themes. There's only the default theme for now
+pub static THEMES: &[&str] = &["default"];
+
+/// Finds a preset file by name or alias
+pub fn find_preset(name: &str) -> Option<&'static [u8]> {
+    PRESETS
+        .iter()
+        .find(|(preset, aliases, _bytes)| *preset == name || aliases.contains(&name))
+        .map(|(_preset, _aliases, bytes)| *bytes)
+}
+
+/// Files in an embedded theme, relative to the source directory
+pub fn theme_files(theme: &str) -> Option<Vec<&'static str>> {
+    if theme != "default" {
+        return None;
+    }
+
+    let files = THEME_ITEMS
+        .iter()
+        .filter(|(_path, bytes)| !bytes.is_empty())
+        .map(|(path, _bytes)| *path)
+        .collect();
+
+    Some(files)
+}
+
 /// Non-recursive directory creation
 fn gen_dir(path: &Path) -> io::Result<bool> {
     if !path.exists() {
@@ -149,6 +187,67 @@ pub fn gen_init_files(base_dir: &Path) -> std::io::Result<()> {
     Ok(())
 }
 
+/// Creates a new article from the template and appends it to the nearest `index.ron` in the
+/// ancestor directories (within the source directory). Returns the path to the article
+///
+/// * `rel_path`: path to the article relative to the source directory
+pub fn gen_article(book: &BookStructure, rel_path: &str) -> anyhow::Result<PathBuf> {
+    use anyhow::{ensure, Context};
+
+    let src_dir = book.src_dir_path();
+    let file = src_dir.join(rel_path);
+    crate::book::ensure_src_file(&file)?;
+    ensure!(!file.exists(), "File already exists: {}", file.display());
+
+    let dir = file
+        .parent()
+        .with_context(|| format!("Unable to get parent directory of {}", file.display()))?;
+
+    // nearest `index.ron`
+    let index_file = book.book_ron.index_file();
+    let index_dir = dir
+        .ancestors()
+        .take_while(|d| d.starts_with(&src_dir))
+        .find(|d| d.join(index_file).is_file())
+        .with_context(|| {
+            format!(
+                "Unable to find `{}` in the ancestor directories of {}",
+                index_file,
+                file.display()
+            )
+        })?;
+    let index_path = index_dir.join(index_file);
+
+    // path relative to the `index.ron`, delimited with `/`
+    let item_path = file
+        .strip_prefix(index_dir)?
+        .components()
+        .map(|c| c.as_os_str().to_str())
+        .collect::<Option<Vec<_>>>()
+        .with_context(|| format!("Unable to stringify path {}", file.display()))?
+        .join("/");
+
+    let index_str = fs::read_to_string(&index_path)
+        .with_context(|| format!("Unable to read {}", index_path.display()))?;
+    let index_str = index::append_file_item(&index_str, &item_path)
+        .with_context(|| format!("Unable to find `items: [..]` in {}", index_path.display()))?;
+    crate::utils::load_ron::<crate::book::config::IndexRon>(&index_str).with_context(|| {
+        format!(
+            "Unable to add the article to {} (edit it manually)",
+            index_path.display()
+        )
+    })?;
+
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Unable to create directory {}", dir.display()))?;
+    fs::write(&file, files::src::ARTICLE)
+        .with_context(|| format!("Unable to write {}", file.display()))?;
+    fs::write(&index_path, index_str)
+        .with_context(|| format!("Unable to write {}", index_path.display()))?;
+
+    Ok(file)
+}
+
 pub fn copy_default_theme(target_dir: &Path) -> std::io::Result<()> {
     // create `theme` directory
     let path = target_dir.join("theme");
@@ -167,3 +266,100 @@ pub fn copy_default_theme(target_dir: &Path) -> std::io::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::Path};
+
+    use super::{gen_article, gen_init_files, LIST, THEME_ITEMS};
+    use crate::book::BookStructure;
+
+    #[test]
+    fn init_index_adoc() {
+        let dir = std::env::temp_dir().join("adbook-test-init");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        gen_init_files(&dir).unwrap();
+
+        // an AsciiDoc document, not the RON table of contents
+        let index = fs::read_to_string(dir.join("src/index.adoc")).unwrap();
+        assert!(index.starts_with("= "), "{}", index);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn new_article_nearest_index() {
+        let dir = std::env::temp_dir().join("adbook-test-new-article");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        gen_init_files(&dir).unwrap();
+
+        let src_dir = dir.join("src");
+        fs::create_dir_all(src_dir.join("guide")).unwrap();
+        fs::write(src_dir.join("guide/index.adoc"), "= Guide\n").unwrap();
+        fs::write(
+            src_dir.join("guide/index.ron"),
+            r#"summary: ("", "index.adoc"), items: []"#,
+        )
+        .unwrap();
+        let root_index = fs::read_to_string(src_dir.join("index.ron")).unwrap();
+
+        let book = BookStructure::from_dir(&dir).unwrap();
+
+        // `guide/drafts` has no `index.ron` and doesn't exist yet
+        let file = gen_article(&book, "guide/drafts/x.adoc").unwrap();
+        assert!(file.is_file());
+        let guide_index = fs::read_to_string(src_dir.join("guide/index.ron")).unwrap();
+        assert!(
+            guide_index.contains(r#"File("", "drafts/x.adoc")"#),
+            "{}",
+            guide_index
+        );
+        assert_eq!(
+            fs::read_to_string(src_dir.join("index.ron")).unwrap(),
+            root_index
+        );
+
+        // falls back to the root `index.ron`
+        gen_article(&book, "notes/y.adoc").unwrap();
+        let root_index = fs::read_to_string(src_dir.join("index.ron")).unwrap();
+        assert!(
+            root_index.contains(r#"File("", "notes/y.adoc")"#),
+            "{}",
+            root_index
+        );
+
+        assert!(gen_article(&book, "guide/drafts/x.adoc").is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn init_files_match_paths() {
+        let init_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("init");
+
+        let items = LIST
+            .iter()
+            .map(|(path, bytes)| (init_dir.join(path), bytes));
+        let theme_items = THEME_ITEMS
+            .iter()
+            .map(|(path, bytes)| (init_dir.join("src").join(path), bytes));
+
+        for (path, bytes) in items.chain(theme_items) {
+            if bytes.is_empty() {
+                continue;
+            }
+            assert_eq!(
+                &fs::read(&path).unwrap()[..],
+                *bytes,
+                "wrong bytes for {}",
+                path.display()
+            );
+        }
+    }
+}
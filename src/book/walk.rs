@@ -6,9 +6,12 @@ use std::{
     fmt,
     path::{Path, PathBuf},
     sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
+use futures::stream::{self, StreamExt};
 use indicatif::{ProgressBar, ProgressStyle};
+use serde::Serialize;
 
 use crate::book::{
     index::{Index, IndexItem},
@@ -34,6 +37,26 @@ pub type BuildResult = std::result::Result<BuildOutput, BuildError>;
 pub struct BuildOutput {
     pub string: String,
     pub src_file: PathBuf,
+    pub metadata: PageMetadata,
+    /// If the output was copied from the cache
+    pub cache_hit: bool,
+}
+
+/// Metadata of a converted article. This is an element of `pages.json`
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct PageMetadata {
+    /// Document title
+    pub title: Option<String>,
+    /// URL of the output file: `{base_url}/path/to/article.html`
+    pub url: String,
+    /// `author` attribute
+    pub author: Option<String>,
+    /// `revdate` attribute
+    pub date: Option<String>,
+    /// `description` attribute
+    pub description: Option<String>,
+    /// `noindex` attribute. Such pages are excluded from sitemaps and search indices
+    pub noindex: bool,
 }
 
 /// Error + metadata
@@ -42,6 +65,13 @@ pub struct BuildError {
     pub err: anyhow::Error,
     /// Absolute path to the source file
     pub src_file: PathBuf,
+    /// If the file is skipped on purpose (`on_convert_error: Skip`). It's reported as a warning
+    pub skipped: bool,
+    /// Exit code of the converter process if it failed. `None` if it didn't run (e.g. not
+    /// installed) or was terminated by a signal
+    pub exit_code: Option<i32>,
+    /// Raw stderr of the converter process if it failed
+    pub stderr: Option<String>,
 }
 
 impl fmt::Display for BuildError {
@@ -66,23 +96,63 @@ pub fn can_skip_whole_build(book: &BookStructure, builder: &impl BookBuilder) ->
     !can_skip_all || src_files.is_empty()
 }
 
+/// Default number of conversions running at a time: the number of CPUs
+pub fn default_jobs() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
 pub fn walk_book_await_collect<V: BookBuilder + 'static>(
     builder: &mut V,
     book: &BookStructure,
+    jobs: usize,
     log: bool,
 ) -> Vec<BuildOutput> {
-    let results = futures::executor::block_on(walk_book_async(builder, &book, log));
+    let results = futures::executor::block_on(walk_book_async(builder, &book, jobs, log));
+    self::collect_outputs(results)
+}
+
+/// Walks a root [`Index`] converting `chunk_size` files at a time. Outputs of each chunk are
+/// passed to `on_chunk` and dropped before converting the next chunk, so the peak memory is
+/// bounded regardless of the book size.
+pub fn walk_book_chunked<V: BookBuilder + 'static>(
+    builder: &mut V,
+    book: &BookStructure,
+    chunk_size: usize,
+    jobs: usize,
+    log: bool,
+    mut on_chunk: impl FnMut(Vec<BuildOutput>) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let src_files = self::list_src_files(book);
+    let src_dir = book.src_dir_path();
+    let pb = self::progress_bar(src_files.len());
+
+    for chunk in src_files.chunks(chunk_size.max(1)) {
+        let results = futures::executor::block_on(self::convert_files_async(
+            builder, chunk, &src_dir, jobs, &pb,
+        ));
+        on_chunk(self::collect_outputs(results))?;
+    }
+
+    self::finish_progress_bar(&pb, log);
 
+    Ok(())
+}
+
+/// Prints errors and returns the outputs
+fn collect_outputs(results: Vec<BuildResult>) -> Vec<BuildOutput> {
     let mut outputs = Vec::new();
     let mut errors = Vec::new();
+    let mut skips = Vec::new();
 
     for res in results {
         match res {
             Ok(output) => outputs.push(output),
+            Err(err) if err.skipped => skips.push(err.err),
             Err(err) => errors.push(err.err),
         }
     }
 
+    crate::utils::print_warnings(&skips, "while building the book (skipped)");
     crate::utils::print_errors(&errors, "while building the book");
 
     outputs
@@ -94,57 +164,36 @@ pub fn walk_book_await_collect<V: BookBuilder + 'static>(
 pub async fn walk_book_async<V: BookBuilder + 'static>(
     builder: &mut V,
     book: &BookStructure,
+    jobs: usize,
     log: bool,
 ) -> Vec<BuildResult> {
-    let src_files_unfiltered = self::list_src_files(&book);
-
-    let mut can_skip_all = false;
-
-    let src_files = src_files_unfiltered
-        .into_iter()
-        .map(|src_file| {
-            can_skip_all |= !builder.can_skip_build(&src_file);
-            src_file
-        })
-        .collect::<Vec<_>>();
+    let src_files = self::list_src_files(&book);
+    let pb = self::progress_bar(src_files.len());
 
-    // progress bar
-    let pb = {
-        let pb = ProgressBar::new(src_files.len() as u64);
+    let results =
+        self::convert_files_async(builder, &src_files, &book.src_dir_path(), jobs, &pb).await;
 
-        pb.set_style(
-            ProgressStyle::default_bar()
-                .template("[{elapsed}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}")
-                .progress_chars("##-"),
-        );
+    self::finish_progress_bar(&pb, log);
 
-        // show progress bar
-        pb.inc(0);
-
-        Arc::new(Mutex::new(pb))
-    };
+    results
+}
 
-    let results = {
-        let tasks = src_files
-            .into_iter()
-            .map(|src_file| {
-                let mut builder = builder.clone();
-                let pb = Arc::clone(&pb);
+fn progress_bar(len: usize) -> Arc<Mutex<ProgressBar>> {
+    let pb = ProgressBar::new(len as u64);
 
-                async_std::task::spawn(async move {
-                    let res = builder.convert_file(&src_file);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("[{elapsed}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}")
+            .progress_chars("##-"),
+    );
 
-                    let pb = pb.lock().expect("unable to lock progress bar");
-                    pb.inc(1);
+    // show progress bar
+    pb.inc(0);
 
-                    res
-                })
-            })
-            .collect::<Vec<_>>();
-
-        futures::future::join_all(tasks).await
-    };
+    Arc::new(Mutex::new(pb))
+}
 
+fn finish_progress_bar(pb: &Mutex<ProgressBar>, log: bool) {
     let pb = pb.lock().expect("unable to lock progress bar");
     if log {
         let elasped = pb.elapsed();
@@ -153,26 +202,106 @@ pub async fn walk_book_async<V: BookBuilder + 'static>(
     } else {
         pb.finish();
     }
+}
 
-    results
+/// Converts source files in parallel, running at most `jobs` conversions at a time. The results
+/// are in the order of the source files
+async fn convert_files_async<V: BookBuilder + 'static>(
+    builder: &V,
+    src_files: &[PathBuf],
+    src_dir: &Path,
+    jobs: usize,
+    pb: &Arc<Mutex<ProgressBar>>,
+) -> Vec<BuildResult> {
+    // tasks are spawned lazily as the running ones complete
+    let mut results = stream::iter(src_files.iter().cloned().enumerate())
+        .map(|(i, src_file)| {
+            let mut builder = builder.clone();
+            let pb = Arc::clone(pb);
+            let src_dir = src_dir.to_path_buf();
+
+            async_std::task::spawn(async move {
+                let start = Instant::now();
+                let res = builder.convert_file(&src_file);
+                let elapsed = start.elapsed();
+
+                let pb = pb.lock().expect("unable to lock progress bar");
+
+                if log::log_enabled!(log::Level::Debug) {
+                    let rel_path = src_file.strip_prefix(&src_dir).unwrap_or(&src_file);
+                    let record = self::conversion_record(rel_path, &res, elapsed);
+                    // don't garble the progress bar
+                    if pb.is_hidden() {
+                        log::debug!("{}", record);
+                    } else {
+                        pb.println(record);
+                    }
+                }
+
+                pb.inc(1);
+
+                (i, res)
+            })
+        })
+        .buffer_unordered(jobs.max(1))
+        .collect::<Vec<_>>()
+        .await;
+
+    results.sort_by_key(|(i, _res)| *i);
+    results.into_iter().map(|(_i, res)| res).collect()
 }
 
-fn list_src_files(book: &BookStructure) -> Vec<PathBuf> {
+/// Structured log record of a conversion, which is easy to `grep`:
+///
+/// `[adbook:convert] src=<path> cache=<hit|miss> status=<ok|error> elapsed_ms=<ms>`
+fn conversion_record(rel_path: &Path, res: &BuildResult, elapsed: Duration) -> String {
+    let cache_hit = matches!(res, Result::Ok(output) if output.cache_hit);
+    let record = format!(
+        "[adbook:convert] src={} cache={} status={} elapsed_ms={:.1}",
+        rel_path.display(),
+        if cache_hit { "hit" } else { "miss" },
+        if res.is_ok() { "ok" } else { "error" },
+        elapsed.as_secs_f64() * 1000.0,
+    );
+
+    match res {
+        Err(BuildError {
+            exit_code: Some(code),
+            ..
+        }) => format!("{} exit_code={}", record, code),
+        _ => record,
+    }
+}
+
+/// Lists source files to build: `converts`, files in `index.ron`s and the `home` page
+///
+/// The root summary is not built if `home` is set to another file.
+///
+/// Files matching the `ignore` patterns are excluded. Drafts are excluded unless `adbook build
+/// --drafts` is given.
+pub(crate) fn list_src_files(book: &BookStructure) -> Vec<PathBuf> {
     // note that paths in `Index` are already canonicalized (can can be passed to visitors directly)
 
     /// [Depth-first] iteration
     ///
     /// [Depth-first]: https://en.wikipedia.org/wiki/Depth-first_search
-    fn list_files_rec(index: &Index, files: &mut Vec<PathBuf>) {
+    fn list_files_rec(book: &BookStructure, index: &Index, files: &mut Vec<PathBuf>) {
         files.push(index.summary.clone());
         for item in &index.items {
             match item {
                 IndexItem::File(_name, path) => {
-                    files.push(path.clone());
+                    if !book.is_excluded(path) {
+                        files.push(path.clone());
+                    }
                 }
                 IndexItem::Dir(index) => {
-                    list_files_rec(index, files);
+                    // an excluded summary excludes the whole directory
+                    if !book.is_excluded(&index.summary) {
+                        list_files_rec(book, index, files);
+                    }
                 }
+                // nothing to build
+                IndexItem::Link(_name, _url) => {}
             };
         }
     }
@@ -183,11 +312,23 @@ fn list_src_files(book: &BookStructure) -> Vec<PathBuf> {
     let src_dir = book.src_dir_path();
     for p in &book.book_ron.converts {
         let path = src_dir.join(p);
-        files.push(path);
+        if !book.is_excluded(&path) {
+            files.push(path);
+        }
     }
 
     // `index.ron` files
-    list_files_rec(&book.index, &mut files);
+    list_files_rec(book, &book.index, &mut files);
+
+    // home page. It replaces the root summary, which would be mapped to `index.html` as well
+    if let Some(home) = &book.home {
+        if *home != book.index.summary {
+            files.retain(|file| *file != book.index.summary);
+        }
+        if !files.contains(home) && !book.is_ignored(home) {
+            files.push(home.clone());
+        }
+    }
 
     files
 }
@@ -4,27 +4,86 @@ Book builder
 
 pub mod cache;
 pub mod convert;
+pub mod diff;
+pub mod doctor;
+pub mod dry_run;
+pub mod links;
+pub mod manifest;
+pub mod minify;
+pub mod outline;
+pub mod search;
+pub mod structure;
 pub mod visit;
 
-use std::{fs, path::Path};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
 
 use anyhow::*;
 
 use crate::{
-    book::{walk, BookStructure},
-    build::{cache::CacheIndex, visit::AdocBookBuilder},
+    book::{ignore::IgnorePatterns, walk, BookStructure},
+    build::{cache::CacheIndex, diff::DiffFormat, visit::AdocBookBuilder},
     utils,
 };
 
+/// Options for [`build_book`]
+#[derive(Debug, Clone, Default)]
+pub struct BuildOptions {
+    /// Clears cache and builds the whole book
+    pub force_rebuild: bool,
+    /// Prints verbose log
+    pub log: bool,
+    /// Turns warnings such as broken links into build failure
+    pub strict: bool,
+    /// Skips copying the default theme, assuming `site/theme` is already there
+    pub no_theme: bool,
+    /// Only converts the source files into the cache, without assembling the site directory
+    pub cache_only: bool,
+    /// Prints the output files that changed from the previous site directory
+    pub diff: Option<DiffFormat>,
+    /// Maximum number of conversions (and file copies) running at a time. `None` is the number of
+    /// CPUs
+    pub jobs: Option<usize>,
+    /// Checks internal links (even without `check_links` in `book.ron`) and fails on broken ones
+    pub deny_broken_links: bool,
+    /// Checks external `http(s)://` links, too
+    pub check_external_links: bool,
+}
+
 /// Builds an `adbook` structure into a site directory, making use of cache and parallelization
 ///
 /// `src` -> `tmp` -> `site`
-pub fn build_book(book: &BookStructure, force_rebuild: bool, log: bool) -> Result<()> {
+///
+/// The book doesn't have to be loaded from a directory. Any [`BookStructure`] that satisfies the
+/// invariants (see its documentation) can be built, and the paths are derived from its fields.
+///
+/// # Cache-only build
+///
+/// With [`BuildOptions::cache_only`] (`adbook build --cache-only`), the source files are
+/// converted into `.adbook-cache` and the cache index is updated, but the site directory is not
+/// assembled (no outputs, `includes`, `copies` nor theme). It splits a CI pipeline into two jobs:
+///
+/// 1. A cache job runs `adbook build --cache-only` and saves `.adbook-cache` as a CI cache.
+/// 2. A deploy job restores `.adbook-cache` and runs `adbook build`, which only copies the cached
+///    outputs (and converts files changed since then) before deploying the site directory.
+pub fn build_book(book: &BookStructure, opts: &BuildOptions) -> Result<()> {
+    let log = opts.log;
+
+    book.validate()?;
+
     let site_dir = book.site_dir_path();
     utils::validate_dir(&site_dir)
         .with_context(|| format!("Failed to create site directory at: {}", site_dir.display()))?;
 
-    let index = if force_rebuild {
+    // fail early rather than in the middle of the build
+    if !opts.cache_only {
+        utils::ensure_writable(&site_dir).context("Unable to write to the site directory")?;
+    }
+
+    let index = if opts.force_rebuild {
         CacheIndex::empty()
     } else {
         cache::CacheIndex::load(book)?
@@ -47,9 +106,23 @@ pub fn build_book(book: &BookStructure, force_rebuild: bool, log: bool) -> Resul
     //     }
     // }
 
+    // 1.5. validate `hbs` attributes before the long build
+    {
+        let acx = convert::AdocRunContext::from_book(book)?;
+        let errors = convert::find_missing_templates(book, &acx);
+
+        if opts.strict && !errors.is_empty() {
+            utils::print_errors(&errors, "while validating `hbs` attributes");
+            bail!("Found {} missing template(s)", errors.len());
+        }
+
+        utils::print_warnings(&errors, "while validating `hbs` attributes");
+    }
+
     // 2. build the project
-    let (mut builder, errors) = AdocBookBuilder::from_book(book, index.create_diff(book)?)?;
+    let (mut builder, errors) = AdocBookBuilder::from_book(book, index)?;
     utils::print_errors(&errors, "while creating AdocBookVisitor");
+    builder.set_strict(opts.strict);
 
     if walk::can_skip_whole_build(book, &builder) {
         if log {
@@ -58,126 +131,381 @@ pub fn build_book(book: &BookStructure, force_rebuild: bool, log: bool) -> Resul
         }
     }
 
-    // ensure `asciidoctor` is in user PATH
-    if which::which("asciidoctor").is_err() {
-        bail!("`asciidoctor` is not in PATH");
+    // ensure `asciidoctor` (or `adoc_cmd`) is in user PATH
+    doctor::ensure_adoc_cmd(&book.book_ron)?;
+
+    ensure!(
+        !(opts.cache_only && book.book_ron.direct_output),
+        "Cache-only build is not available in the direct output mode"
+    );
+
+    ensure!(
+        !(opts.diff.is_some() && book.book_ron.direct_output),
+        "Output diff is not available in the direct output mode"
+    );
+
+    // 2.4. convert the files at once
+    if book.book_ron.batch_convert && !book.book_ron.direct_output {
+        log::info!("---- Running batch conversion");
+        builder.batch_convert()?;
     }
 
-    log::info!("---- Running builders");
-    let outputs = walk::walk_book_await_collect(&mut builder, &book, log);
+    // 2.5. take the output hashes before the site directory is cleared
+    let snapshot = match opts.diff {
+        Some(_) => Some(diff::SiteSnapshot::take(book)?),
+        None => None,
+    };
 
-    // 3. copy the outputs to the site directory
-    log::info!("---- Writing to site directory");
-    {
+    // (shared by the conversion and the copies)
+    let jobs = opts.jobs.unwrap_or_else(walk::default_jobs);
+
+    // 3. prepare the site directory
+    if !opts.cache_only {
+        log::info!("---- Preparing site directory");
         let mut errors = Vec::new();
-        let res = self::create_site_directory(&outputs, book, &book.site_dir_path(), &mut errors);
+        let res = self::create_site_directory(book, &site_dir, opts, jobs, &mut errors);
         utils::print_errors(&errors, "while copying temporary files to site directory");
         res?;
+
+        if opts.strict && !errors.is_empty() {
+            bail!("Failed to copy {} item(s) in `includes`", errors.len());
+        }
     }
 
-    // 4. apply `copies` attribute
-    log::info!("---- Copying specified files");
+    // 3.1. convert the source files and write the outputs to the site directory and cache
+    // (in chunks if `chunk_size` is set, so that the outputs are not held all at once)
+    log::info!("---- Running builders");
+    let mut pages = Vec::new();
+    let mut built_files = Vec::new();
+    let mut output_hashes = BTreeMap::new();
+    let mut search_entries = Vec::new();
+    let mut minified_bytes = 0;
     {
-        let root = &book.root;
-
-        let mut errors = Vec::new();
-        let mut warns = Vec::new();
-
-        for (a, b) in &book.book_ron.copies {
-            let src = root.join(a);
-            let dst = root.join(b);
-
-            if !src.exists() {
-                warns.push(format!("Non-existing source file: {}", src.display()));
-                continue;
-            }
-
-            // create directory in destination
-            {
-                let dir = if src.is_file() {
-                    dst.parent()
-                } else if src.is_dir() {
-                    Some(dst.as_path())
-                } else {
-                    warns.push(format!("Unexpected kind of item: {}", src.display()));
-                    continue;
-                };
-
-                if let Some(dir) = dir {
-                    if !dir.exists() {
-                        if let Err(err) = fs::create_dir_all(&dir)
-                            .map_err(|err| anyhow!("{} (fs::create_dir({}))", err, dir.display()))
-                        {
-                            errors.push(err);
-                            continue;
-                        }
-                    }
+        let n_files = walk::list_src_files(book).len();
+        let chunk_size = book.book_ron.chunk_size.unwrap_or(n_files);
+        let cache_index = builder.cache_index.clone();
+
+        walk::walk_book_chunked(&mut builder, book, chunk_size, jobs, log, |mut outputs| {
+            // the outputs of the chunk are cached
+            cache_index.save(book)?;
+
+            if !opts.cache_only && book.book_ron.minify && book.book_ron.is_html_backend() {
+                for output in &mut outputs {
+                    let minified = minify::minify_html(&output.string);
+                    minified_bytes += output.string.len() - minified.len();
+                    output.string = minified;
                 }
             }
-
-            if src.is_file() {
-                if let Err(err) = fs::copy(&src, &dst).map_err(|err| {
-                    anyhow!("{} (fs::copy({}, {}))", err, src.display(), dst.display())
-                }) {
-                    errors.push(err);
-                }
-            } else if src.is_dir() {
-                if let Err(err) = utils::copy_items_rec(&src, &dst) {
-                    errors.push(err);
+            if !opts.cache_only {
+                self::write_outputs(book, &outputs)?;
+            }
+            if snapshot.is_some() {
+                for output in &outputs {
+                    output_hashes.insert(
+                        book.src_file_to_dst_rel(&output.src_file)?,
+                        cache::bytes_hash(output.string.as_bytes()),
+                    );
                 }
             }
+            if book.book_ron.generate_search_index {
+                search_entries.extend(outputs.iter().filter_map(search::SearchEntry::from_output));
+            }
+            built_files.extend(outputs.iter().map(|o| o.src_file.clone()));
+            pages.extend(outputs.into_iter().map(|o| o.metadata));
+            Ok(())
+        })?;
+    }
+
+    // 3.2. report the output diff
+    if let (Some(snapshot), Some(format)) = (&snapshot, opts.diff) {
+        diff::OutputDiff::create(snapshot, &output_hashes).print(format)?;
+    }
+
+    // (cache-only build stops here, leaving the site directory untouched)
+    if opts.cache_only {
+        log::info!("---- Updating build cache");
+        builder.cache_index.save(book)?;
+        return Ok(());
+    }
+
+    // 3.5. write `pages.json`
+    if book.book_ron.metadata_json {
+        log::info!("---- Writing `pages.json`");
+        self::write_pages_json(&pages, &site_dir)?;
+    }
+
+    // 3.6. write the search index
+    if book.book_ron.generate_search_index {
+        if book.book_ron.is_html_backend() && !book.book_ron.direct_output {
+            log::info!("---- Writing `search_index.json`");
+            search::write_search_index(&search_entries, &site_dir)?;
+        } else {
+            log::warn!(
+                "`generate_search_index` is only available for HTML outputs captured by `adbook` (not in the direct output mode)"
+            );
         }
+    }
+
+    // 3.7. write the book structure
+    if book.book_ron.generate_structure_json {
+        log::info!("---- Writing `structure.json`");
+        let errors = structure::write_structure_json(book, &site_dir)?;
+        utils::print_errors(&errors, "while writing `structure.json`");
+    }
+
+    // 3.8. write the build manifest
+    if book.book_ron.manifest {
+        log::info!("---- Writing build manifest");
+        manifest::Manifest::create(book, &built_files)?.write(book)?;
+    }
+
+    // 4. apply `copies` attribute
+    log::info!("---- Copying specified files");
+    {
+        let mut errors = Vec::new();
+        let mut warns = Vec::new();
+
+        self::apply_copies(
+            &book.root,
+            &book.book_ron.copies,
+            jobs,
+            &mut errors,
+            &mut warns,
+        );
 
         utils::print_warnings(&warns, "while applying `copies` attribute");
         utils::print_errors(&errors, "while applying `copies` attribute");
     }
 
     // 5. apply `use_default_theme` attributes
-    if book.book_ron.use_default_theme {
+    if book.book_ron.use_default_theme && opts.no_theme {
+        if !site_dir.join("theme").is_dir() {
+            log::warn!(
+                "`--no-theme` is given but the theme is not in the site directory: {}",
+                site_dir.join("theme").display()
+            );
+        }
+    } else if book.book_ron.use_default_theme {
         log::info!("---- Copying default theme");
         crate::book::init::copy_default_theme(&site_dir)?;
     }
 
-    // 6. clean up and save cache
+    // 6. save cache
     log::info!("---- Updating build cache");
 
-    // copy outputs to the cache directory
-    {
-        let cache_dir = CacheIndex::locate_cache_dir(book)?;
-        let mut errors = Vec::new();
-        self::write_html_outputs(&mut errors, &book.src_dir_path(), &cache_dir, &outputs)?;
-        utils::print_errors(&errors, "while writing outputs to cache");
+    // (each built file is already recorded, so that an interrupted build can resume)
+    builder.cache_index.save(book)?;
+
+    // 7. check links
+    if book.book_ron.check_links || opts.deny_broken_links || opts.check_external_links {
+        log::info!("---- Checking internal links");
+        let mut broken = links::check_links(&site_dir, &book.book_ron.base_url)?;
+
+        if opts.check_external_links {
+            log::info!("---- Checking external links");
+            broken.extend(links::check_external_links(&site_dir)?);
+        }
+
+        if (opts.strict || opts.deny_broken_links) && !broken.is_empty() {
+            utils::print_errors(&broken, "while checking internal links");
+            bail!("Found {} broken link(s)", broken.len());
+        }
+
+        utils::print_warnings(&broken, "while checking internal links");
     }
 
-    index.update_cache_index(book, builder.cache_diff.into_new_cache_data())?;
+    // 8. check assets
+    if book.book_ron.check_assets {
+        log::info!("---- Checking assets");
+        let missing = links::check_assets(&site_dir, book.book_ron.asset_url())?;
+
+        if opts.strict && !missing.is_empty() {
+            utils::print_errors(&missing, "while checking assets");
+            bail!("Found {} missing asset(s)", missing.len());
+        }
+
+        utils::print_warnings(&missing, "while checking assets");
+    }
+
+    if book.book_ron.minify {
+        log::info!("Minified HTML outputs: {} bytes saved", minified_bytes);
+    }
+
+    Ok(())
+}
+
+/// Writes outputs to the site directory (they're already written to the cache directory by the
+/// builder)
+fn write_outputs(book: &BookStructure, outputs: &[walk::BuildOutput]) -> Result<()> {
+    let mut errors = Vec::new();
+    self::write_html_outputs(&mut errors, outputs, |src_file| {
+        book.src_file_to_dst_file(src_file)
+    })?;
+    utils::print_errors(&errors, "while writing outputs to site directory");
+
+    Ok(())
+}
+
+/// Writes `pages.json`, the list of article metadata, to the site directory
+///
+/// The schema is an array of [`walk::PageMetadata`] in the build order. Missing attributes are
+/// `null`:
+///
+/// ```json
+/// [
+///   {
+///     "title": "Article title",
+///     "url": "/base_url/path/to/article.html",
+///     "author": "someone",
+///     "date": "Oct 23, 2020",
+///     "description": null,
+///     "noindex": false
+///   }
+/// ]
+/// ```
+fn write_pages_json(pages: &[walk::PageMetadata], site_dir: &Path) -> Result<()> {
+    let json = serde_json::to_string_pretty(pages).context("Unable to serialize `pages.json`")?;
+
+    let path = site_dir.join("pages.json");
+    fs::write(&path, json).with_context(|| format!("Unable to write {}", path.display()))?;
 
     Ok(())
 }
 
 /// TODO: refactor
 fn create_site_directory(
-    outputs: &[walk::BuildOutput],
     book: &BookStructure,
     out_dir: &Path,
+    opts: &BuildOptions,
+    jobs: usize,
     errors: &mut Vec<Error>,
 ) -> Result<()> {
     let site_dir = book.site_dir_path();
 
     // clear most files in site directory
-    log::trace!("remove files in site directory");
-    utils::clear_directory_items(&site_dir, |path| {
-        if path == out_dir {
-            return true;
-        }
-        let name = match path.file_name().and_then(|s| s.to_str()) {
-            Some(name) => name,
-            None => return false,
-        };
-        name.starts_with(".")
-    })?;
+    // (except in the direct output mode, where `asciidoctor` has already written files to it)
+    if !book.book_ron.direct_output {
+        log::trace!("remove files in site directory");
+        utils::clear_directory_items(&site_dir, |path| {
+            if path == out_dir {
+                return true;
+            }
+            let name = match path.file_name().and_then(|s| s.to_str()) {
+                Some(name) => name,
+                None => return false,
+            };
+            // keep the theme copied by previous builds
+            if (opts.no_theme && name == "theme") || name.starts_with(".") {
+                return true;
+            }
+            // keep the `includes` copied by previous builds, which are copied incrementally
+            book.book_ron
+                .includes
+                .iter()
+                .any(|rel_path| site_dir.join(rel_path) == path)
+        })?;
+    }
 
     // copy the `includes` files in `book.ron` to the temporary output directory
-    for rel_path in &book.book_ron.includes {
+    self::copy_includes(
+        &book.src_dir_path(),
+        &site_dir,
+        &book.book_ron.includes,
+        &book.ignore,
+        jobs,
+        errors,
+    );
+
+    Ok(())
+}
+
+/// Copies the `copies` items in `book.ron`. Destinations have to be in the root directory
+///
+/// Files are copied in parallel (at most `jobs` at a time) unless the destinations are up to date.
+fn apply_copies(
+    root: &Path,
+    copies: &[(PathBuf, PathBuf)],
+    jobs: usize,
+    errors: &mut Vec<Error>,
+    warns: &mut Vec<String>,
+) {
+    let files = self::plan_copies(root, copies, errors, warns);
+    utils::copy_files_parallel(files, jobs, errors);
+}
+
+/// Lists the `(src, dst)` files to copy for the `copies` items in `book.ron`, without writing
+/// anything
+fn plan_copies(
+    root: &Path,
+    copies: &[(PathBuf, PathBuf)],
+    errors: &mut Vec<Error>,
+    warns: &mut Vec<String>,
+) -> Vec<(PathBuf, PathBuf)> {
+    let mut files = Vec::new();
+
+    for (a, b) in copies {
+        let src = root.join(a);
+        let dst = match utils::ensure_within(&root.join(b), root) {
+            Result::Ok(dst) => dst,
+            Err(err) => {
+                errors.push(err.context("Destination of `copies` out of the root directory"));
+                continue;
+            }
+        };
+
+        if !src.exists() {
+            warns.push(format!("Non-existing source file: {}", src.display()));
+            continue;
+        }
+
+        if src.is_file() {
+            files.push((src, dst));
+        } else if src.is_dir() {
+            if let Err(err) = utils::list_copies_rec(&src, &dst, &mut files) {
+                errors.push(err);
+            }
+        } else {
+            warns.push(format!("Unexpected kind of item: {}", src.display()));
+        }
+    }
+
+    files
+}
+
+/// Copies the `includes` items in `book.ron` from the source directory to the site directory
+///
+/// Files matching the `ignore` patterns are not copied, nor are files whose destinations are up to
+/// date. The others are copied in parallel (at most `jobs` at a time). Failures are pushed to
+/// `errors` and the remaining items are still copied. Returns the number of the copied files.
+fn copy_includes(
+    src_dir: &Path,
+    site_dir: &Path,
+    includes: &[PathBuf],
+    ignore: &IgnorePatterns,
+    jobs: usize,
+    errors: &mut Vec<Error>,
+) -> usize {
+    let files = self::plan_includes(src_dir, site_dir, includes, ignore, errors);
+    utils::copy_files_parallel(files, jobs, errors)
+}
+
+/// Lists the `(src, dst)` files to copy for the `includes` items in `book.ron`, without writing
+/// anything
+fn plan_includes(
+    src_dir: &Path,
+    site_dir: &Path,
+    includes: &[PathBuf],
+    ignore: &IgnorePatterns,
+    errors: &mut Vec<Error>,
+) -> Vec<(PathBuf, PathBuf)> {
+    let is_ignored = |path: &Path| {
+        let rel_path = path.strip_prefix(src_dir).unwrap_or(path);
+        ignore.is_ignored(rel_path)
+    };
+
+    let mut files = Vec::new();
+
+    for rel_path in includes {
         // ensure the given path is valid
         if !rel_path.is_relative() {
             errors.push(anyhow!(
@@ -187,8 +515,14 @@ fn create_site_directory(
             continue;
         }
 
-        let src_path = book.src_dir_path().join(rel_path);
-        let dst_path = book.site_dir_path().join(rel_path);
+        let src_path = src_dir.join(rel_path);
+        let dst_path = match utils::ensure_within(&site_dir.join(rel_path), site_dir) {
+            Result::Ok(dst_path) => dst_path,
+            Err(err) => {
+                errors.push(err.context("Destination of `includes` out of the site directory"));
+                continue;
+            }
+        };
 
         // ensure the source file/directory exists
         if !src_path.exists() {
@@ -199,76 +533,67 @@ fn create_site_directory(
             continue;
         }
 
-        // let's copy
-        if src_path.is_file() {
-            // case 1. file
-            let dir = src_path.parent().unwrap();
-
-            // create parent directory
-            if !dir.exists() {
-                fs::create_dir_all(dir).with_context(|| {
-                    format!(
-                        "Unable to create parent directory of included file: {}",
-                        src_path.display(),
-                    )
-                })?;
-            }
-
-            fs::copy(&src_path, &dst_path).with_context(|| {
-                format!(
-                    "Unable to copy source included file `{}` to `{}`",
-                    src_path.display(),
-                    dst_path.display()
-                )
-            })?;
-        } else if src_path.is_dir() {
-            // case 2. directory
-            if !dst_path.exists() {
-                fs::create_dir_all(&dst_path).with_context(|| {
-                    format!(
-                        "Unable to create parent directory:\nsrc: {}\ndst: {}",
-                        src_path.display(),
-                        dst_path.display(),
-                    )
-                })?;
-            }
+        if is_ignored(&src_path) {
+            log::debug!("Skipping ignored include: {}", rel_path.display());
+            continue;
+        }
 
-            utils::copy_items_rec(&src_path, &dst_path).with_context(|| {
-                format!(
-                    "Unable to copy included directory:\nsrc: {}\ndst: {}",
-                    src_path.display(),
-                    dst_path.display(),
-                )
-            })?;
-        } else {
-            // case 3. unexpected kind of file
-            errors.push(anyhow!(
-                "Unexpected kind of file to include in `book.ron`: {}",
-                src_path.display()
-            ));
+        if let Err(err) = self::list_include(&src_path, &dst_path, &is_ignored, &mut files) {
+            errors.push(err);
         }
     }
 
-    // finally, copy the output (HTML) files to the site directory
-    let src_dir = book.src_dir_path();
-    let site_dir = book.site_dir_path();
-    self::write_html_outputs(errors, &src_dir, &site_dir, outputs)?;
+    files
+}
+
+/// Lists the files to copy for an `includes` item
+fn list_include(
+    src_path: &Path,
+    dst_path: &Path,
+    is_ignored: &dyn Fn(&Path) -> bool,
+    files: &mut Vec<(PathBuf, PathBuf)>,
+) -> Result<()> {
+    if src_path.is_file() {
+        // case 1. file
+        files.push((src_path.to_path_buf(), dst_path.to_path_buf()));
+    } else if src_path.is_dir() {
+        // case 2. directory
+        utils::visit_files_rec(src_path, &mut |file| {
+            if is_ignored(file) {
+                return Ok(());
+            }
+
+            let dst_file = dst_path.join(file.strip_prefix(src_path)?);
+            files.push((file.to_path_buf(), dst_file));
+
+            Ok(())
+        })
+        .with_context(|| {
+            format!(
+                "Unable to list included directory:\nsrc: {}\ndst: {}",
+                src_path.display(),
+                dst_path.display(),
+            )
+        })?;
+    } else {
+        // case 3. unexpected kind of file
+        bail!(
+            "Unexpected kind of file to include in `book.ron`: {}",
+            src_path.display()
+        );
+    }
 
     Ok(())
 }
 
+/// Writes outputs to the paths mapped from the source files
 fn write_html_outputs(
     errors: &mut Vec<Error>,
-    src_dir: &Path,
-    out_dir: &Path,
     outputs: &[walk::BuildOutput],
+    dst_file: impl Fn(&Path) -> Result<PathBuf>,
 ) -> Result<()> {
     for output in outputs {
-        let dst_path = {
-            let src_file = output.src_file.with_extension("html");
-            let rel_path = src_file.strip_prefix(&src_dir).unwrap();
-            out_dir.join(rel_path)
-        };
+        let dst_path = dst_file(&output.src_file)?;
 
         println!("{}", dst_path.display());
 
@@ -295,3 +620,371 @@ fn write_html_outputs(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs,
+        path::PathBuf,
+        time::{Duration, SystemTime},
+    };
+
+    use super::{apply_copies, copy_includes, BuildOptions};
+    use crate::{
+        book::{
+            config::OrderBy,
+            ignore::IgnorePatterns,
+            index::{Index, IndexItem},
+            BookStructure,
+        },
+        utils,
+    };
+
+    #[test]
+    fn includes_continue_past_failures() {
+        let dir = std::env::temp_dir().join("adbook-test-includes");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+
+        let src_dir = dir.join("src");
+        let site_dir = dir.join("site");
+        fs::create_dir_all(src_dir.join("static")).unwrap();
+        fs::write(src_dir.join("a.css"), "a").unwrap();
+        fs::write(src_dir.join("bad.css"), "bad").unwrap();
+        fs::write(src_dir.join("static/c.png"), "c").unwrap();
+
+        // `bad.css` can't be copied: a directory is in the way
+        fs::create_dir_all(site_dir.join("bad.css")).unwrap();
+
+        let includes = ["a.css", "bad.css", "static"]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>();
+
+        let mut errors = Vec::new();
+        copy_includes(
+            &src_dir,
+            &site_dir,
+            &includes,
+            &IgnorePatterns::default(),
+            4,
+            &mut errors,
+        );
+
+        assert_eq!(errors.len(), 1);
+        assert!(site_dir.join("a.css").is_file());
+        assert!(site_dir.join("static/c.png").is_file());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn includes_skip_ignored() {
+        let dir = std::env::temp_dir().join("adbook-test-includes-ignore");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+
+        let src_dir = dir.join("src");
+        let site_dir = dir.join("site");
+        fs::create_dir_all(src_dir.join("static/drafts")).unwrap();
+        fs::write(src_dir.join("a.tmp"), "a").unwrap();
+        fs::write(src_dir.join("static/b.png"), "b").unwrap();
+        fs::write(src_dir.join("static/c.tmp"), "c").unwrap();
+        fs::write(src_dir.join("static/drafts/d.png"), "d").unwrap();
+
+        let includes = vec![PathBuf::from("a.tmp"), PathBuf::from("static")];
+        let ignore =
+            IgnorePatterns::new(&["*.tmp".to_string(), "static/drafts/**".to_string()]).unwrap();
+
+        let mut errors = Vec::new();
+        copy_includes(&src_dir, &site_dir, &includes, &ignore, 4, &mut errors);
+
+        assert!(errors.is_empty());
+        assert!(site_dir.join("static/b.png").is_file());
+        assert!(!site_dir.join("a.tmp").exists());
+        assert!(!site_dir.join("static/c.tmp").exists());
+        assert!(!site_dir.join("static/drafts").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn includes_copy_incrementally() {
+        let dir = std::env::temp_dir().join("adbook-test-includes-incremental");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+
+        let src_dir = dir.join("src");
+        let site_dir = dir.join("site");
+        fs::create_dir_all(src_dir.join("static/img")).unwrap();
+        fs::write(src_dir.join("a.css"), "a").unwrap();
+        fs::write(src_dir.join("static/b.png"), "b").unwrap();
+        fs::write(src_dir.join("static/img/c.png"), "c").unwrap();
+
+        let includes = vec![PathBuf::from("a.css"), PathBuf::from("static")];
+
+        let mut errors = Vec::new();
+        assert_eq!(
+            copy_includes(
+                &src_dir,
+                &site_dir,
+                &includes,
+                &IgnorePatterns::default(),
+                4,
+                &mut errors
+            ),
+            3
+        );
+        // the second pass performs no writes
+        assert_eq!(
+            copy_includes(
+                &src_dir,
+                &site_dir,
+                &includes,
+                &IgnorePatterns::default(),
+                4,
+                &mut errors
+            ),
+            0
+        );
+
+        // `copies` as well
+        let copied = dir.join("copied");
+        for n_copies in [3, 0] {
+            let mut files = Vec::new();
+            utils::list_copies_rec(&src_dir, &copied, &mut files).unwrap();
+            assert_eq!(files.len(), 3);
+            assert_eq!(utils::copy_files_parallel(files, 4, &mut errors), n_copies);
+        }
+
+        // modified files are copied again
+        let later = SystemTime::now() + Duration::from_secs(60);
+        let file = fs::File::options()
+            .write(true)
+            .open(src_dir.join("static/img/c.png"))
+            .unwrap();
+        file.set_modified(later).unwrap();
+        assert_eq!(
+            copy_includes(
+                &src_dir,
+                &site_dir,
+                &includes,
+                &IgnorePatterns::default(),
+                4,
+                &mut errors
+            ),
+            1
+        );
+
+        assert!(errors.is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn copies_stay_in_root() {
+        let dir = std::env::temp_dir().join("adbook-test-copies-escape");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+
+        let root = dir.join("book");
+        fs::create_dir_all(root.join("static")).unwrap();
+        fs::write(root.join("static/a.png"), "a").unwrap();
+
+        let copies = vec![
+            (PathBuf::from("static"), PathBuf::from("site/static")),
+            (PathBuf::from("static"), PathBuf::from("../escape")),
+            (PathBuf::from("static"), PathBuf::from("site/../../escape")),
+        ];
+
+        let (mut errors, mut warns) = (Vec::new(), Vec::new());
+        apply_copies(&root, &copies, 4, &mut errors, &mut warns);
+
+        assert_eq!(errors.len(), 2);
+        assert!(warns.is_empty());
+        assert!(root.join("site/static/a.png").is_file());
+        assert!(!dir.join("escape").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn includes_stay_in_site() {
+        let dir = std::env::temp_dir().join("adbook-test-includes-escape");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+
+        let src_dir = dir.join("book/src");
+        let site_dir = dir.join("book/site");
+        fs::create_dir_all(&src_dir).unwrap();
+        fs::create_dir_all(src_dir.join("../../escape")).unwrap();
+        fs::write(src_dir.join("../../escape/a.css"), "a").unwrap();
+
+        // `src/../../escape` -> `site/../../escape`
+        let includes = vec![PathBuf::from("../../escape")];
+
+        let mut errors = Vec::new();
+        copy_includes(
+            &src_dir,
+            &site_dir,
+            &includes,
+            &IgnorePatterns::default(),
+            4,
+            &mut errors,
+        );
+
+        assert_eq!(errors.len(), 1);
+        assert!(format!("{:#}", errors[0]).contains("out of the site directory"));
+        assert!(!site_dir.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_only_site_dir() {
+        use std::os::unix::fs::PermissionsExt;
+
+        use crate::book::init;
+
+        let dir = std::env::temp_dir().join("adbook-test-read-only");
+        if dir.exists() {
+            let _ = fs::set_permissions(dir.join("site"), fs::Permissions::from_mode(0o755));
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        init::gen_init_files(&dir).unwrap();
+
+        let book = BookStructure::from_dir(&dir).unwrap();
+        let site_dir = book.site_dir_path();
+        fs::create_dir_all(&site_dir).unwrap();
+        fs::set_permissions(&site_dir, fs::Permissions::from_mode(0o555)).unwrap();
+
+        // (the permission is ignored by the superuser)
+        if fs::File::create(site_dir.join("probe")).is_err() {
+            let err = super::build_book(&book, &BuildOptions::default()).unwrap_err();
+            assert!(format!("{:?}", err).contains("not writable"), "{:?}", err);
+        }
+
+        fs::set_permissions(&site_dir, fs::Permissions::from_mode(0o755)).unwrap();
+
+        // a symbolic link to a read-only mount
+        #[cfg(target_os = "linux")]
+        {
+            fs::remove_dir_all(&site_dir).unwrap();
+            std::os::unix::fs::symlink("/sys", &site_dir).unwrap();
+
+            let err = super::build_book(&book, &BuildOptions::default()).unwrap_err();
+            let msg = format!("{:?}", err);
+            assert!(msg.contains("not writable"), "{}", msg);
+            assert!(msg.contains("symbolic link to /sys"), "{}", msg);
+
+            fs::remove_file(&site_dir).unwrap();
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn in_memory_book() {
+        let dir = std::env::temp_dir().join("adbook-test-in-memory");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(dir.join("src")).unwrap();
+        let root = dir.canonicalize().unwrap();
+
+        let summary = root.join("src/index.adoc");
+        let article = root.join("src/article.adoc");
+        fs::write(&summary, "= Home\n\nHello.\n").unwrap();
+        fs::write(&article, "= Article\n\nWorld.\n").unwrap();
+
+        // no `book.ron` nor `index.ron` on disk
+        let book_ron = crate::utils::load_ron(
+            r#"
+base_url: "",
+src_dir: "src",
+site_dir: "site",
+authors: [],
+title: "in-memory",
+generate_all: false,
+use_default_theme: false,
+manifest: true,
+converts: [],
+adoc_opts: [],
+"#,
+        )
+        .unwrap();
+
+        let mut book = BookStructure {
+            root: root.clone(),
+            book_ron,
+            index: Index {
+                dir: root.join("src"),
+                name: "".to_string(),
+                summary: summary.clone(),
+                items: vec![IndexItem::File("".to_string(), article.clone())],
+                order_by: OrderBy::Manual,
+                outputs: Default::default(),
+                default_hbs: None,
+            },
+            home: None,
+            ignore: Default::default(),
+            profile: None,
+            drafts: false,
+        };
+        book.validate().unwrap();
+
+        if which::which("asciidoctor").is_ok() {
+            super::build_book(&book, &BuildOptions::default()).unwrap();
+            assert!(root.join("site/index.html").is_file());
+            assert!(root.join("site/article.html").is_file());
+            assert!(root.join(".adbook-cache/manifest.json").is_file());
+        }
+
+        // relative paths are rejected before building
+        book.root = PathBuf::from("relative");
+        assert!(super::build_book(&book, &BuildOptions::default()).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn home_page() {
+        if which::which("sh").is_err() {
+            return;
+        }
+
+        let dir = std::env::temp_dir().join("adbook-test-home");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        crate::book::init::gen_init_files(&dir).unwrap();
+
+        let mut book_ron = fs::read_to_string(dir.join("book.ron")).unwrap();
+        book_ron = book_ron.replace("home: None,", r#"home: Some("article.adoc"),"#);
+        fs::write(dir.join("book.ron"), book_ron).unwrap();
+
+        let mut book = BookStructure::from_dir(&dir).unwrap();
+        // fake `asciidoctor` that prints the title of the source file (`$0`)
+        book.book_ron.adoc_cmd = Some("sh".to_string());
+        book.book_ron.adoc_args_prefix = vec![
+            "-c".to_string(),
+            r#"sed -n 's/^= \(.*\)/<h1>\1<\/h1>/p' "$0""#.to_string(),
+        ];
+
+        super::build_book(&book, &BuildOptions::default()).unwrap();
+
+        let index = fs::read_to_string(book.site_dir_path().join("index.html")).unwrap();
+        assert!(index.contains("<h1>Article</h1>"), "{}", index);
+        assert!(!index.contains("<h1>Index</h1>"), "{}", index);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,7 +1,9 @@
 /*!
 Skip running `asciidoctor` if a file is not modofied since the last run
 
-TODO: rebuild the whole project when the number of source files or article title changes.
+Every page contains the sidebar, so all the files are rebuilt when the book structure (the file
+list and the titles in the sidebar) changes, e.g. when an article is added or removed. The cache
+index stores a hash of the structure for it.
 
 # Cache directory
 
@@ -10,20 +12,49 @@ TODO: rebuild the whole project when the number of source files or article title
 ├── a               # cached html files
 │   ├── 404.html
 │   └── index.html
-└── index           # cache index
+├── batch           # outputs of the batch conversion (`batch_convert`)
+│   ├── embedded
+│   └── standalone
+├── h               # cached html files keyed by source content hash (`cache_by_hash`)
+│   └── <sha256>.html
+├── index           # cache index
+└── manifest.json   # build manifest (`manifest` in `book.ron`)
 ```
+
+# Resuming
+
+Each file is recorded in the cache index as soon as its output is written to the cache directory
+(see [`SharedCacheIndex`]). The index is saved at most once per [`SAVE_INTERVAL`], after each
+chunk (`chunk_size` in `book.ron`) and at the end of the build. If a build is interrupted, the next
+build skips the completed files.
+
+The index is written to a temporary file and then renamed, so an interrupted save never leaves a
+truncated index.
+
+# Content-addressed store
+
+With `cache_by_hash` in `book.ron`, cached outputs are stored by the SHA-256 hash of the source
+file's relative path and content, plus a fingerprint of everything else that goes into a page (see
+[`output_fingerprint`]). A cached output is reused only if the cache index says the file is
+unchanged and the hash matches, so a file edited without changing its modification time, or a
+cache directory restored from another branch in CI, never serves stale pages.
 */
 
 use std::{
     fs, io,
     path::{Path, PathBuf},
-    time::SystemTime,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
 };
 
 use anyhow::*;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
-use crate::book::BookStructure;
+use crate::book::{
+    index::{self, Index, IndexItem},
+    BookStructure,
+};
 
 pub fn clear_cache(book: &BookStructure) -> io::Result<()> {
     let root = CacheIndex::locate_root(book);
@@ -34,9 +65,133 @@ pub fn clear_cache(book: &BookStructure) -> io::Result<()> {
     io::Result::Ok(())
 }
 
+/// Hex string of the SHA-256 hash of the file content
+pub fn content_hash(file: &Path) -> Result<String> {
+    let bytes =
+        fs::read(file).with_context(|| format!("Unable to read file: {}", file.display()))?;
+    Ok(self::bytes_hash(&bytes))
+}
+
+/// Hex string of the SHA-256 hash of the bytes
+pub fn bytes_hash(bytes: &[u8]) -> String {
+    let hash = Sha256::digest(bytes);
+    hash.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Hex string of the SHA-256 hash of the book structure that affects the sidebar: paths, titles
+/// and draft states of the items in `index.ron`s
+pub fn structure_hash(book: &BookStructure) -> String {
+    fn hash_index(hasher: &mut Sha256, index: &Index, src_dir: &Path) {
+        fn hash_file(hasher: &mut Sha256, name: &str, file: &Path, src_dir: &Path) {
+            let rel_path = file.strip_prefix(src_dir).unwrap_or(file);
+            let title = if name.is_empty() {
+                index::peek_title(file).unwrap_or_default()
+            } else {
+                name.to_string()
+            };
+            hasher.update(rel_path.to_string_lossy().as_bytes());
+            hasher.update(b"\0");
+            hasher.update(title.as_bytes());
+            hasher.update(b"\0");
+            if crate::build::convert::is_draft_file(file) {
+                hasher.update(b"draft\0");
+            }
+        }
+
+        hash_file(hasher, &index.name, &index.summary, src_dir);
+        if let Some(hbs) = &index.default_hbs {
+            hasher.update(b"hbs\0");
+            hasher.update(hbs.to_string_lossy().as_bytes());
+            hasher.update(b"\0");
+        }
+        for (file, output) in &index.outputs {
+            let rel_path = file.strip_prefix(src_dir).unwrap_or(file);
+            hasher.update(b"output\0");
+            hasher.update(rel_path.to_string_lossy().as_bytes());
+            hasher.update(b"\0");
+            hasher.update(output.to_string_lossy().as_bytes());
+            hasher.update(b"\0");
+        }
+        for item in &index.items {
+            match item {
+                IndexItem::File(name, file) => hash_file(hasher, name, file, src_dir),
+                IndexItem::Dir(index) => {
+                    hasher.update(b"dir\0");
+                    hash_index(hasher, index, src_dir);
+                    hasher.update(b"end\0");
+                }
+                IndexItem::Link(name, url) => {
+                    hasher.update(name.as_bytes());
+                    hasher.update(b"\0");
+                    hasher.update(url.as_bytes());
+                    hasher.update(b"\0");
+                }
+            }
+        }
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update([book.drafts as u8]);
+    for pattern in &book.book_ron.ignore {
+        hasher.update(pattern.as_bytes());
+        hasher.update(b"\0");
+    }
+    hash_index(&mut hasher, &book.index, &book.src_dir_path());
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Hex string of the SHA-256 hash of what goes into every page besides the source file: the book
+/// structure, `book.ron`, the active profile, the Handlebars templates in the source directory and
+/// the `adbook` version (the embedded theme)
+pub fn output_fingerprint(book: &BookStructure) -> Result<String> {
+    let mut hasher = Sha256::new();
+
+    hasher.update(env!("CARGO_PKG_VERSION").as_bytes());
+    hasher.update(b"\0");
+    hasher.update(self::structure_hash(book).as_bytes());
+    hasher.update(b"\0");
+    let book_ron = ron::to_string(&book.book_ron).context("Unable to serialize `book.ron`")?;
+    hasher.update(book_ron.as_bytes());
+    hasher.update(b"\0");
+    if let Some(profile) = &book.profile {
+        hasher.update(profile.as_bytes());
+    }
+    hasher.update(b"\0");
+
+    let src_dir = book.src_dir_path();
+    let mut templates = Vec::new();
+    crate::utils::visit_files_rec(&src_dir, &mut |file| {
+        if matches!(file.extension().and_then(|s| s.to_str()), Some("hbs")) {
+            templates.push(file.to_path_buf());
+        }
+        Ok(())
+    })?;
+    templates.sort();
+
+    for file in &templates {
+        let rel_path = file.strip_prefix(&src_dir).unwrap_or(file);
+        hasher.update(rel_path.to_string_lossy().as_bytes());
+        hasher.update(b"\0");
+        hasher.update(self::content_hash(file)?.as_bytes());
+        hasher.update(b"\0");
+    }
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct CacheIndexData {
     entries: Vec<CacheIndexEntry>,
+    /// [`structure_hash`] of the book when the entries are built
+    structure_hash: String,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -48,13 +203,16 @@ pub struct CacheIndexEntry {
 
 impl Default for CacheIndexData {
     fn default() -> Self {
-        Self { entries: vec![] }
+        Self::empty()
     }
 }
 
 impl CacheIndexData {
     pub fn empty() -> Self {
-        Self { entries: vec![] }
+        Self {
+            entries: vec![],
+            structure_hash: String::new(),
+        }
     }
 
     /// Create s cache from the source directory of a book
@@ -73,7 +231,10 @@ impl CacheIndexData {
             });
             Ok(())
         })?;
-        Ok(Self { entries })
+        Ok(Self {
+            entries,
+            structure_hash: self::structure_hash(book),
+        })
     }
 
     pub fn find_cache(&self, rel_path: &Path) -> Option<&CacheIndexEntry> {
@@ -84,41 +245,88 @@ impl CacheIndexData {
         }
         None
     }
+
+    /// Adds or replaces the entry of the same path
+    fn upsert(&mut self, entry: CacheIndexEntry) {
+        match self.entries.iter_mut().find(|e| e.path == entry.path) {
+            Some(e) => *e = entry,
+            None => self.entries.push(entry),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct CacheIndexDiff {
     old: Option<CacheIndexData>,
     new: CacheIndexData,
+    /// [`output_fingerprint`] of the book. Computed only with `cache_by_hash`
+    fingerprint: Option<String>,
 }
 
 impl CacheIndexDiff {
     fn create(book: &BookStructure, old_cache: Option<CacheIndexData>) -> Result<Self> {
         let now = CacheIndexData::create_new_cache(book)?;
+        let fingerprint = if book.book_ron.cache_by_hash {
+            Some(self::output_fingerprint(book)?)
+        } else {
+            None
+        };
         Ok(Self {
             old: old_cache,
             new: now,
+            fingerprint,
         })
     }
 
+    /// `.cache_dir/h/<hash>.html`; path to the cached output of a source file in the
+    /// content-addressed store. The hash covers the relative path, the content and the
+    /// [`output_fingerprint`]
+    pub fn locate_hashed_output(&self, book: &BookStructure, src_file: &Path) -> Result<PathBuf> {
+        let rel_path = Self::rel_path(book, src_file);
+        let content = fs::read(src_file)
+            .with_context(|| format!("Unable to read file: {}", src_file.display()))?;
+
+        let mut key = Vec::with_capacity(content.len() + 128);
+        key.extend_from_slice(rel_path.to_string_lossy().as_bytes());
+        key.push(b'\0');
+        key.extend_from_slice(&content);
+        key.push(b'\0');
+        key.extend_from_slice(self.fingerprint.as_deref().unwrap_or_default().as_bytes());
+
+        let hash_dir = CacheIndex::locate_hash_dir(book)?;
+        Ok(hash_dir.join(self::bytes_hash(&key)).with_extension("html"))
+    }
+
     pub fn into_new_cache_data(self) -> CacheIndexData {
         self.new
     }
 
+    /// Relative path from the source directory
+    fn rel_path<'a>(book: &BookStructure, src_path: &'a Path) -> &'a Path {
+        if src_path.is_absolute() {
+            src_path.strip_prefix(book.src_dir_path()).unwrap()
+        } else {
+            src_path
+        }
+    }
+
     /// If the file needs to be rebuilt
     ///
     /// * `src_path`: Either absolute path or relative path from the source directory
     pub fn need_build(&self, book: &BookStructure, src_path: &Path) -> bool {
-        let rel_path = if src_path.is_absolute() {
-            &src_path.strip_prefix(book.src_dir_path()).unwrap()
-        } else {
-            src_path
-        };
+        let rel_path = Self::rel_path(book, src_path);
 
-        let current_entry = self
-            .new
-            .find_cache(rel_path)
-            .unwrap_or_else(|| panic!("given non-existing file in source directory"));
+        let current_entry = match self.new.find_cache(rel_path) {
+            Some(entry) => entry,
+            None => {
+                // e.g. deleted while building. Converting it reports the error for the file
+                log::warn!(
+                    "Not found in the source directory snapshot (rebuilding): {}",
+                    src_path.display()
+                );
+                return true;
+            }
+        };
 
         let last_entry = {
             let last = match self.old.as_ref() {
@@ -126,6 +334,11 @@ impl CacheIndexDiff {
                 None => return true,
             };
 
+            // the sidebar changed
+            if last.structure_hash != self.new.structure_hash {
+                return true;
+            }
+
             match last.find_cache(rel_path) {
                 Some(cache) => cache,
                 None => return true,
@@ -142,7 +355,8 @@ pub struct CacheIndex {
 }
 
 impl CacheIndex {
-    fn locate_root(book: &BookStructure) -> PathBuf {
+    /// `.adbook-cache`
+    pub(crate) fn locate_root(book: &BookStructure) -> PathBuf {
         let root_dir = book.root.join(".adbook-cache/");
         crate::utils::validate_dir(&root_dir).expect("Unable to locate cache directory");
         root_dir
@@ -189,16 +403,309 @@ impl CacheIndex {
         Ok(cache_dir)
     }
 
+    /// `.cache_dir/h`; files keyed by source content hash will be here
+    pub fn locate_hash_dir(book: &BookStructure) -> Result<PathBuf> {
+        let root_dir = Self::locate_root(book);
+        let hash_dir = root_dir.join("h");
+        crate::utils::validate_dir(&hash_dir)?;
+        Ok(hash_dir)
+    }
+
     /// Cleans up the temporary output directory and saves build cache
     pub fn update_cache_index(
         &self,
         book: &BookStructure,
         new_cache: CacheIndexData,
     ) -> Result<()> {
-        // save index
+        Self { cache: new_cache }.save(book)
+    }
+
+    /// Records that a source file is built and its output is cached
+    ///
+    /// * `src_path`: Either absolute path or relative path from the source directory
+    pub fn record_built(
+        &mut self,
+        diff: &CacheIndexDiff,
+        book: &BookStructure,
+        src_path: &Path,
+    ) -> Result<()> {
+        let rel_path = CacheIndexDiff::rel_path(book, src_path);
+        let entry = diff.new.find_cache(rel_path).ok_or_else(|| {
+            anyhow!(
+                "Given non-existing file in source directory: {}",
+                rel_path.display()
+            )
+        })?;
+
+        // entries built with another book structure are stale
+        if self.cache.structure_hash != diff.new.structure_hash {
+            self.cache.entries.clear();
+            self.cache.structure_hash = diff.new.structure_hash.clone();
+        }
+
+        self.cache.upsert(entry.clone());
+        Ok(())
+    }
+
+    /// Saves the cache index. It's written to a temporary file and renamed over the index, so
+    /// that an interrupted save doesn't leave a truncated index
+    pub fn save(&self, book: &BookStructure) -> Result<()> {
         let index = Self::locate_index(book);
-        let bin = bincode::serialize(&Self { cache: new_cache })?;
-        fs::write(&index, bin)?;
+        let tmp = index.with_extension("tmp");
+
+        let bin = bincode::serialize(self)?;
+        fs::write(&tmp, bin)
+            .with_context(|| format!("Unable to write cache index: {}", tmp.display()))?;
+        fs::rename(&tmp, &index)
+            .with_context(|| format!("Unable to replace cache index: {}", index.display()))?;
+
+        Ok(())
+    }
+}
+
+/// Minimum interval between the saves of [`SharedCacheIndex::record_built`]
+pub const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// [`CacheIndex`] shared among parallel builders. Every built file is recorded immediately and the
+/// index is saved at most once per [`SAVE_INTERVAL`], so an interrupted build resumes from the
+/// completed work.
+#[derive(Debug, Clone)]
+pub struct SharedCacheIndex {
+    state: Arc<Mutex<SharedState>>,
+}
+
+#[derive(Debug)]
+struct SharedState {
+    index: CacheIndex,
+    /// `None` until the first save
+    last_save: Option<Instant>,
+}
+
+impl SharedCacheIndex {
+    pub fn new(index: CacheIndex) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(SharedState {
+                index,
+                last_save: None,
+            })),
+        }
+    }
+
+    /// Records that a source file is built and its output is cached. The index is saved unless it
+    /// was saved within [`SAVE_INTERVAL`]
+    pub fn record_built(
+        &self,
+        diff: &CacheIndexDiff,
+        book: &BookStructure,
+        src_path: &Path,
+    ) -> Result<()> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| anyhow!("Unable to lock cache index"))?;
+        state.index.record_built(diff, book, src_path)?;
+
+        let due = match state.last_save {
+            Some(last_save) => last_save.elapsed() >= SAVE_INTERVAL,
+            None => true,
+        };
+        if due {
+            state.index.save(book)?;
+            state.last_save = Some(Instant::now());
+        }
+
+        Ok(())
+    }
+
+    /// Saves the cache index
+    pub fn save(&self, book: &BookStructure) -> Result<()> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| anyhow!("Unable to lock cache index"))?;
+        state.index.save(book)?;
+        state.last_save = Some(Instant::now());
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::{CacheIndex, SharedCacheIndex};
+    use crate::book::{init, BookStructure};
+
+    #[test]
+    fn resume_partial_build() {
+        let dir = std::env::temp_dir().join("adbook-test-resume");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        init::gen_init_files(&dir).unwrap();
+
+        let book = BookStructure::from_dir(&dir).unwrap();
+        let index_adoc = book.src_dir_path().join("index.adoc");
+        let article = book.src_dir_path().join("article.adoc");
+
+        // build `index.adoc` only and then get interrupted
+        {
+            let index = CacheIndex::load(&book).unwrap();
+            let diff = index.create_diff(&book).unwrap();
+            assert!(diff.need_build(&book, &index_adoc));
+            assert!(diff.need_build(&book, &article));
+
+            let shared = SharedCacheIndex::new(index);
+            shared.record_built(&diff, &book, &index_adoc).unwrap();
+        }
+
+        // the next build remembers the completed work
+        let index = CacheIndex::load(&book).unwrap();
+        let diff = index.create_diff(&book).unwrap();
+        assert!(!diff.need_build(&book, &index_adoc));
+        assert!(diff.need_build(&book, &article));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rebuild_on_structure_change() {
+        let dir = std::env::temp_dir().join("adbook-test-structure-change");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        init::gen_init_files(&dir).unwrap();
+
+        // build both files
+        {
+            let book = BookStructure::from_dir(&dir).unwrap();
+            let index = CacheIndex::load(&book).unwrap();
+            let diff = index.create_diff(&book).unwrap();
+
+            let shared = SharedCacheIndex::new(index);
+            for file in &["index.adoc", "article.adoc"] {
+                let src_file = book.src_dir_path().join(file);
+                shared.record_built(&diff, &book, &src_file).unwrap();
+            }
+
+            let index = CacheIndex::load(&book).unwrap();
+            let diff = index.create_diff(&book).unwrap();
+            assert!(!diff.need_build(&book, &book.src_dir_path().join("index.adoc")));
+        }
+
+        // remove `article.adoc`
+        fs::write(
+            dir.join("src/index.ron"),
+            r#"summary: ("", "index.adoc"), items: []"#,
+        )
+        .unwrap();
+        fs::remove_file(dir.join("src/article.adoc")).unwrap();
+
+        // the sidebar of the remaining file changed
+        let book = BookStructure::from_dir(&dir).unwrap();
+        let index = CacheIndex::load(&book).unwrap();
+        let diff = index.create_diff(&book).unwrap();
+        assert!(diff.need_build(&book, &book.src_dir_path().join("index.adoc")));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn need_build_missing_file() {
+        let dir = std::env::temp_dir().join("adbook-test-need-build-missing");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        init::gen_init_files(&dir).unwrap();
+
+        let book = BookStructure::from_dir(&dir).unwrap();
+        let index = CacheIndex::load(&book).unwrap();
+        let diff = index.create_diff(&book).unwrap();
+
+        // not in the new cache snapshot: rebuild instead of panicking
+        let missing = book.src_dir_path().join("missing.adoc");
+        assert!(diff.need_build(&book, &missing));
+        assert!(diff.need_build(&book, std::path::Path::new("missing.adoc")));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hashed_output_key() {
+        let dir = std::env::temp_dir().join("adbook-test-hashed-output");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        init::gen_init_files(&dir).unwrap();
+
+        let src_dir = dir.join("src");
+        fs::write(
+            src_dir.join("copy.adoc"),
+            fs::read(src_dir.join("article.adoc")).unwrap(),
+        )
+        .unwrap();
+        fs::write(src_dir.join("page.hbs"), "{{{a_article}}}").unwrap();
+
+        let key = |book: &BookStructure, file: &str| {
+            let diff = CacheIndex::empty().create_diff(book).unwrap();
+            diff.locate_hashed_output(book, &book.src_dir_path().join(file))
+                .unwrap()
+        };
+
+        let mut book = BookStructure::from_dir(&dir).unwrap();
+        book.book_ron.cache_by_hash = true;
+        let article = key(&book, "article.adoc");
+
+        // the same content at another path
+        assert_ne!(article, key(&book, "copy.adoc"));
+
+        // templates and `book.ron`
+        fs::write(src_dir.join("page.hbs"), "<main>{{{a_article}}}</main>").unwrap();
+        let article_2 = key(&book, "article.adoc");
+        assert_ne!(article, article_2);
+
+        book.book_ron.base_url = "/book".to_string();
+        assert_ne!(article_2, key(&book, "article.adoc"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn throttled_atomic_save() {
+        let dir = std::env::temp_dir().join("adbook-test-throttled-save");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        init::gen_init_files(&dir).unwrap();
+
+        let book = BookStructure::from_dir(&dir).unwrap();
+        let index_adoc = book.src_dir_path().join("index.adoc");
+        let article = book.src_dir_path().join("article.adoc");
+
+        let index = CacheIndex::load(&book).unwrap();
+        let diff = index.create_diff(&book).unwrap();
+        let shared = SharedCacheIndex::new(index);
+
+        // the first record is saved, but the next one within `SAVE_INTERVAL` is not
+        shared.record_built(&diff, &book, &index_adoc).unwrap();
+        shared.record_built(&diff, &book, &article).unwrap();
+
+        let diff = CacheIndex::load(&book).unwrap().create_diff(&book).unwrap();
+        assert!(!diff.need_build(&book, &index_adoc));
+        assert!(diff.need_build(&book, &article));
+
+        shared.save(&book).unwrap();
+        let diff = CacheIndex::load(&book).unwrap().create_diff(&book).unwrap();
+        assert!(!diff.need_build(&book, &article));
+
+        // no temporary file is left
+        assert!(!dir.join(".adbook-cache/index.tmp").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
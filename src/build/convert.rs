@@ -6,18 +6,43 @@ Converts AsciiDoc files using `asciidoctor` and Handlebars
 In `adbook`, `asciidoctor` options are supplied with the following placeholder strings:
 
 * `{base_url}`: base url in this form: `/base/url`. useful when supplying absolute path
+* `{asset_url}`: `asset_url` in `book.ron` (defaults to `base_url`). useful for static assets
 * `{src_dir}`: path to source directory
 * `{dst_dir}`: path to destination directory
 
 We can use them for document attributes:
 
 ```adoc
-:imagesdir: {base_url}/static/img
+:imagesdir: {asset_url}/static/img
 :imagesoutdir: {src_dir}/static/img
 ```
 
 Usually those paths are globally specified in `book.ron`.
 
+# Attributes in `book.ron`
+
+`attributes` in `book.ron` is a shorthand of `-a` options in `adoc_opts`:
+
+```ron
+attributes: {
+    "imagesdir": "{base_url}/static/img",
+},
+```
+
+They're merged into the `-a` options. An attribute also specified with `-a` in `adoc_opts` keeps
+the `adoc_opts` value, and attributes in the AsciiDoc files override both unless they're set with
+`@=`.
+
+`profile_attributes` declares attributes per build profile. With `adbook build --profile prod`, the
+attributes of `prod` override the same attributes in both `attributes` and `adoc_opts`:
+
+```ron
+profile_attributes: {
+    "prod": { "env": "prod" },
+    "dev": { "env": "dev" },
+},
+```
+
 # Handlebars attribute
 
 `adbook` specially treats `hbs` AsciiDoc attribute as the path to a Handlebars template file:
@@ -29,24 +54,172 @@ Usually those paths are globally specified in `book.ron`.
 ```
 
 `hbs` is always relative to the source directory and no base directory is supplied.
+
+Partials are loaded from `partials/` next to the template. Shared partial directories can be
+listed with `partial_dirs` in `book.ron` (relative to the root). They're registered in order and
+then the template's own `partials/` is registered, so a later directory overrides same-named
+partials in earlier ones.
+
+With `use_default_theme: true` in `book.ron`, the `hbs` attribute supplied with `adoc_opts` only
+enables templating and the default theme is used. An `hbs` attribute written in the article
+itself takes precedence over the default theme and the article is rendered with that template.
+
+An `index.ron` can set a template for the files in the directory (and its sub directories) with
+`default_hbs`. It's used as if it's written in each article, so an `hbs` attribute in the article
+still wins:
+
+```ron
+summary: ("", "index.adoc"),
+items: [File("", "article.adoc")],
+default_hbs: Some("theme/hbs/simple.hbs"),
+```
+
+# Extra `<head>` HTML
+
+`head_html` in `book.ron` is inserted into `<head>` of every article by the default theme, for
+example analytics snippets or verification meta tags. An article can replace it with the
+`head-extra` attribute:
+
+```adoc
+= Article
+:head-extra: <meta name="google-site-verification" content="..">
+```
+
+It's exposed as `head_extra` to Handlebars templates. The HTML is NOT escaped and it's the
+author's responsibility to keep it valid and safe.
+
+# Math
+
+Set `math: Some(KaTeX)` or `math: Some(MathJax)` in `book.ron` to load a math renderer in
+Handlebars templates (`math_head`). `-a stem@=latexmath` is supplied unless the `stem` attribute
+is given. Articles write math with the `stem` macro and block:
+
+```adoc
+:stem: latexmath
+
+Inline math: stem:[e^{i\pi} + 1 = 0]
+
+[stem]
+++++
+\sum_{k=1}^n k = \frac{n(n+1)}{2}
+++++
+```
+
+KaTeX supports `latexmath` only, while MathJax also supports `asciimath`.
+
+# Direct output mode
+
+With `direct_output: true` in `book.ron`, `asciidoctor` writes output files to the site directory
+by itself (`-R <src_dir> -D <dst_dir>`) instead of `adbook` capturing stdout. Side-effect files
+such as `asciidoctor-diagram` images land next to the outputs as `asciidoctor` expects.
+
+The tradeoffs:
+
+* No post-processing: the `hbs` attribute is ignored and no Handlebars template is applied.
+* The site directory is not cleared before writing, so run `adbook clear` to remove stale files.
+
+# Batch conversion
+
+With `batch_convert: true` in `book.ron`, the files to build are converted with one `asciidoctor`
+process per directory (`-R <src_dir> -D .adbook-cache/batch/..`) before the per-file pipeline, which
+then reads back the outputs instead of starting `asciidoctor` (and Ruby) for every file. Files that
+need a Handlebars template are converted in other processes in the embedded mode. The working
+directory is the directory of the files as in the per-file conversion, so that paths relative to
+it (e.g. `imagesoutdir` of `asciidoctor-diagram`) are the same in both modes.
+
+Files with the `doctype` attribute are still converted one by one, and so are all the files if the
+batch fails (e.g. with `failure_level`), so that errors are attributed to the source files.
+
+# Backends
+
+`backend: Some("docbook5")` in `book.ron` supplies `-b docbook5` to `asciidoctor`. Output files
+get the backend's extension (`.xml` for DocBook) instead of `.html`.
+
+These features are HTML-only and ignored with non-HTML backends:
+
+* Handlebars templates (the `hbs` attribute), including the sidebar and `math_head`
+* `use_default_theme` (the theme is copied but not applied)
+* `check_links` (only `.html` files are scanned)
+
+# Doctype
+
+`doctype: Some("book")` in `book.ron` supplies `-d book` to `asciidoctor`, so that `= Part` and
+`== Chapter` headings render as a book. An article can override it with the `doctype` attribute
+(`:doctype: article`). Known doctypes are `article` (default), `book`, `manpage` and `inline`.
+
+# Plain text
+
+`.txt` source files are not converted with `asciidoctor`. The content is HTML-escaped, wrapped in
+`<pre class="plain-text">` and then the Handlebars template is applied as usual. The title is the
+file stem. Other formats such as `.org` are rejected with the list of supported extensions.
+
+# Empty output guard
+
+`asciidoctor` can succeed with empty output, for example when a file is entirely comments or an
+`include::` directive is botched. Such files are reported as warnings, or as errors with
+`fail_on_empty: true` in `book.ron` (or `adbook build --fail-on-empty-output`). Add
+`:allow-empty:` to files that are intentionally empty.
+
+# Conversion failures
+
+`on_convert_error` in `book.ron` decides what happens to a file that fails to convert:
+
+* `Abort` (default): the error is reported and the page is missing.
+* `Placeholder`: a page saying "This page failed to build" with the error (including the
+  `asciidoctor` stderr) is written at the URL, so that links to it don't 404. It's not cached.
+* `Skip`: the page is missing and the error is reported as a warning.
+
+None of them stops the build of other files. On CI, `Abort` shows the failure in the log while
+the site still deploys without the page (`check_links` can catch links to it). `Placeholder`
+deploys a visible error page instead, and `Skip` makes the failure easy to overlook, so prefer
+`Abort` where broken pages must be noticed.
+
+# Unresolved includes
+
+`asciidoctor` doesn't fail on missing `include::` targets but emits `Unresolved directive ..`
+text into the output. Such includes are reported as warnings naming the source file and the
+target, or as errors with `adbook build --strict`.
 */
 
 mod adoc;
 mod adoc_all;
+mod git;
 
 pub mod hbs;
+pub mod plain;
 
-use std::{fmt::Write, fs, path::Path};
+use std::{
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
 
 use anyhow::*;
 
-pub use self::adoc::AdocRunContext;
+pub use self::adoc::{
+    adoc_command, is_draft_file, AdocAttr, AdocError, AdocMetadata, AdocRunContext, BatchOutputs,
+};
 pub use adoc_all::gen_all;
 
 use crate::book::BookStructure;
 
 use self::hbs::{HbsContext, HbsInput};
 
+/// Minimum capacity of an output buffer
+const MIN_BUF_CAPACITY: usize = 5 * 1024;
+
+/// HTML output is typically a few times larger than the AsciiDoc source
+const OUTPUT_SIZE_FACTOR: usize = 3;
+
+/// Estimates the capacity of an output buffer from the source file size so that the buffer
+/// doesn't grow repeatedly while writing large pages
+pub fn estimate_output_capacity(src_file: &Path) -> usize {
+    let src_len = fs::metadata(src_file)
+        .map(|m| m.len() as usize)
+        .unwrap_or(0);
+    (src_len * OUTPUT_SIZE_FACTOR).max(MIN_BUF_CAPACITY)
+}
+
 /// Converts an AsciiDoc file to an html string just by running `asciidoctor`
 ///
 /// * `opts`: options provided with `asciidoctor`
@@ -56,80 +229,441 @@ pub fn convert_adoc(
     hcx: &HbsContext,
     book: &BookStructure,
 ) -> Result<String> {
-    let mut buf = String::with_capacity(5 * 1024);
+    let mut buf = String::with_capacity(self::estimate_output_capacity(src_file));
     self::convert_adoc_buf(&mut buf, src_file, acx, hcx, book)?;
     Ok(buf)
 }
 
+/// Converts an AsciiDoc string to an html string just by running `asciidoctor`
+///
+/// The string is supplied via stdin. `include::` directives and images are resolved from
+/// `base_dir` (`asciidoctor -B`) as if the string was a file in it.
+pub fn convert_adoc_str(text: &str, base_dir: &Path, acx: &AdocRunContext) -> Result<String> {
+    let mut buf = String::with_capacity((text.len() * OUTPUT_SIZE_FACTOR).max(MIN_BUF_CAPACITY));
+    adoc::run_asciidoctor_str_buf(&mut buf, text, base_dir, acx)?;
+    Ok(buf)
+}
+
+/// Converts a single AsciiDoc file outside of the book build (`adbook convert`)
+///
+/// If the file is in a book, `adoc_opts` and `base_url` of the book are used. The Handlebars
+/// template `hbs_file` is applied to the embedded output. It needs the book for the template
+/// variables such as the sidebar.
+pub fn convert_single_file(
+    src_file: &Path,
+    book: Option<&BookStructure>,
+    hbs_file: Option<&Path>,
+) -> Result<String> {
+    let text = fs::read_to_string(src_file)
+        .with_context(|| format!("Unable to read source file: {}", src_file.display()))?;
+    let base_dir = src_file
+        .parent()
+        .with_context(|| format!("Unable to get parent directory of {}", src_file.display()))?;
+
+    let mut acx = match book {
+        Some(book) => AdocRunContext::from_book(book)?,
+        None => AdocRunContext::standalone(base_dir)?,
+    };
+
+    let hbs_file = match hbs_file {
+        Some(hbs_file) => hbs_file,
+        None => return self::convert_adoc_str(&text, base_dir, &acx),
+    };
+
+    let book = book.context("Handlebars template needs the file to be in a book (`book.ron`)")?;
+
+    acx.set_embedded_mode(true);
+    let html = self::convert_adoc_str(&text, base_dir, &acx)?;
+    let metadata = AdocMetadata::extract_with_base(&text, &acx);
+
+    let (mut sidebar, errors) = hbs::Sidebar::from_book(book);
+    crate::utils::print_warnings(&errors, "while creating the sidebar");
+    if let Result::Ok(url) = book.src_file_to_url(src_file) {
+        sidebar.set_active_url(&url);
+    }
+
+    let hbs_input = HbsInput::new(&html, &metadata, &book.book_ron, sidebar);
+
+    // use the template's own partials if any
+    let hbs_dir = hbs_file.parent().unwrap_or_else(|| Path::new("."));
+    let mut hbs = if hbs_dir.join("partials").is_dir() {
+        let partial_dirs = book
+            .book_ron
+            .partial_dirs
+            .iter()
+            .map(|dir| book.root.join(dir))
+            .collect::<Vec<_>>();
+        hbs::init_hbs_user(hbs_dir, &partial_dirs)?
+    } else {
+        hbs::init_hbs_default()?
+    };
+
+    let src_file_name = format!("{}", src_file.display());
+    hbs::render_hbs_user(&mut hbs, &hbs_input, &src_file_name, hbs_file)
+}
+
+/// Scans `hbs` attributes of all the source files and returns errors for missing template files
+///
+/// Templates are not used in the direct output mode or with non-HTML backends. With the default
+/// theme, only `hbs` attributes in the documents themselves are checked.
+pub fn find_missing_templates(book: &BookStructure, acx: &AdocRunContext) -> Vec<Error> {
+    if book.book_ron.direct_output || !book.book_ron.is_html_backend() {
+        return vec![];
+    }
+
+    let src_dir = book.src_dir_path();
+    let mut errors = Vec::new();
+
+    for src_file in crate::book::walk::list_src_files(book) {
+        let metadata = match self::extract_metadata(&src_file, acx, book) {
+            Result::Ok(metadata) => metadata,
+            Err(err) => {
+                errors.push(err.context(format!("Unable to read: {}", src_file.display())));
+                continue;
+            }
+        };
+
+        if self::uses_default_theme(book, &metadata) {
+            continue;
+        }
+
+        let hbs_name = match metadata.find_attr("hbs").and_then(|attr| attr.value()) {
+            Some(hbs_name) => hbs_name,
+            None => continue,
+        };
+
+        let hbs_file = src_dir.join(hbs_name);
+        if !hbs_file.is_file() {
+            errors.push(anyhow!(
+                "Missing `hbs` template `{}` for {}",
+                hbs_file.display(),
+                src_file.display()
+            ));
+        }
+    }
+
+    errors
+}
+
+/// If the article is rendered with the default theme: `use_default_theme` is on and the article
+/// doesn't specify its own `hbs` attribute
+pub fn uses_default_theme(book: &BookStructure, metadata: &AdocMetadata) -> bool {
+    book.book_ron.use_default_theme && metadata.find_own_attr("hbs").is_none()
+}
+
+/// Template applied to the article: `Some("<default>")` for the default theme, the `hbs` path for
+/// user templates or `None` if no template is applied
+pub fn template_name(
+    book: &BookStructure,
+    acx: &AdocRunContext,
+    metadata: &AdocMetadata,
+) -> Option<String> {
+    if acx.is_direct_output() || !acx.is_html_backend() {
+        return None;
+    }
+
+    let hbs_name = metadata.find_attr("hbs")?.value()?;
+
+    if self::uses_default_theme(book, metadata) {
+        Some("<default>".to_string())
+    } else {
+        Some(hbs_name.to_string())
+    }
+}
+
+/// Extracts metadata of an AsciiDoc file with fallback attributes from `asciidoctor` options
+///
+/// Plain text files only have the fallback attributes and the file stem as the title. See
+/// [`apply_default_hbs`] for the `hbs` attribute.
+pub fn extract_metadata(
+    src_file: &Path,
+    acx: &AdocRunContext,
+    book: &BookStructure,
+) -> Result<AdocMetadata> {
+    let mut metadata = if crate::book::is_text_file(src_file) {
+        self::text_metadata(src_file, acx)
+    } else {
+        let adoc_text = fs::read_to_string(src_file).context("Unable to read source file")?;
+        AdocMetadata::extract_with_base(&adoc_text, acx)
+    };
+
+    self::apply_default_hbs(&mut metadata, src_file, book);
+    Ok(metadata)
+}
+
+/// Sets the `default_hbs` of the directory in `index.ron` as the `hbs` attribute, unless the
+/// document has its own. It overrides `hbs` in `adoc_opts` and the default theme
+pub fn apply_default_hbs(metadata: &mut AdocMetadata, src_file: &Path, book: &BookStructure) {
+    if let Some(hbs) = book.index.find_default_hbs(src_file) {
+        metadata.set_own_attr_if_missing(AdocAttr::allow("hbs", hbs.to_string_lossy()));
+    }
+}
+
+fn text_metadata(src_file: &Path, acx: &AdocRunContext) -> AdocMetadata {
+    let mut metadata = AdocMetadata::extract_with_base("", acx);
+    metadata.title = Some(hbs::text_title(src_file));
+    metadata
+}
+
+/// Warns about attributes set more than once in the document
+fn warn_overridden_attrs(metadata: &AdocMetadata, src_file: &Path) {
+    let warns = metadata
+        .overridden_attrs()
+        .iter()
+        .map(|name| format!("`{}` is set more than once (the last one is used)", name))
+        .collect::<Vec<_>>();
+
+    let header = format!("in the header of {}", src_file.display());
+    crate::utils::print_warnings(&warns, &header);
+}
+
 /// Converts an AsciiDoc file to an html string and then applies a Handlebars template
 ///
-/// Be sure that the `buf` is always cleared.
+/// Be sure that the `buf` is always cleared. Returns the metadata extracted from the file.
 pub fn convert_adoc_buf(
     buf: &mut String,
     src_file: &Path,
     acx: &AdocRunContext,
     hcx: &HbsContext,
     book: &BookStructure,
-) -> Result<()> {
+) -> Result<AdocMetadata> {
     ensure!(
         src_file.is_file(),
         "Given invalid source file path: {}",
         src_file.display()
     );
 
+    if crate::book::is_text_file(src_file) {
+        return self::convert_text_buf(buf, src_file, acx, hcx, book);
+    }
+
     // extract metadata
-    let metadata = {
-        let adoc_text = fs::read_to_string(src_file).context("Unable to read source file")?;
-        adoc::AdocMetadata::extract_with_base(&adoc_text, &acx)
-    };
+    let adoc_text = fs::read_to_string(src_file).context("Unable to read source file")?;
+    let mut metadata = AdocMetadata::extract_with_base(&adoc_text, acx);
+    self::warn_overridden_attrs(&metadata, src_file);
+    self::apply_default_hbs(&mut metadata, src_file, book);
+
+    let hbs_attr = self::hbs_attr(&metadata, acx);
 
-    // we use "embedded mode" of `asciidoctor` if we'll apply Handlebars template later
+    // the `doctype` attribute overrides the one in `book.ron`, and we use "embedded mode" of
+    // `asciidoctor` if we'll apply Handlebars template later
     let mut acx = acx.clone();
-    if metadata.find_attr("hbs").is_some() {
+    if let Some(doctype) = metadata.find_attr("doctype").and_then(|attr| attr.value()) {
+        acx.set_doctype(doctype)
+            .with_context(|| format!("Invalid `doctype` in {}", src_file.display()))?;
+    }
+    if hbs_attr.is_some() {
         acx.set_embedded_mode(true);
     }
 
     // run `asciidoctor` and write the output to `buf`
     buf.clear();
+    buf.reserve(self::estimate_output_capacity(src_file));
     adoc::run_asciidoctor_buf(buf, src_file, &acx)?;
 
+    // guard against silent content loss
+    if !metadata.is_set("allow-empty") && adoc::is_empty_output(&adoc_text, buf) {
+        let err = adoc::AdocError::EmptyOutput(src_file.to_path_buf());
+        if book.book_ron.fail_on_empty {
+            bail!(err);
+        }
+        log::warn!("{}", err);
+    }
+
+    // `asciidoctor` doesn't fail on missing includes
+    for warning in adoc::check_unresolved_includes(src_file, buf, &acx)? {
+        log::warn!("{}", warning);
+    }
+
     // maybe apply Handlebars template
-    if let Some(hbs_attr) = metadata.find_attr("hbs") {
-        let src_file_name = format!("{}", src_file.display());
-        let src_dir = book.src_dir_path();
-        let base_url_str = &book.book_ron.base_url;
-
-        let hbs_file_path = {
-            let hbs_name = hbs_attr
-                .value()
-                .ok_or_else(|| anyhow!("`hbs` attribute without path"))?;
-            src_dir.join(hbs_name)
-        };
+    if let Some(hbs_attr) = hbs_attr {
+        self::apply_template(buf, src_file, &metadata, hbs_attr, hcx, book)?;
+    }
 
-        // `.hbs` files are always located just under `hbs_dir`
-        //     >>>> currently it's a mess! <<<<
-        let hbs_input = {
-            // FIXME: the API, the clarity of `src_dir` and `src_dir_path()`
-            let url = hbs::Sidebar::get_url(&src_dir, &src_dir.join(src_file), base_url_str)
-                .map_err(|err| anyhow!("Unable to get URL for file: {}", err))?;
+    Ok(metadata)
+}
 
-            let sidebar = hcx.sidebar_for_url(&url);
-            HbsInput::new(buf, &metadata, base_url_str, sidebar)
-        };
+/// The `hbs` attribute if a Handlebars template is applied to the output. Templates are not
+/// applied in the direct output mode or to non-HTML outputs
+fn hbs_attr<'a>(metadata: &'a AdocMetadata, acx: &AdocRunContext) -> Option<&'a AdocAttr> {
+    if acx.is_direct_output() || !acx.is_html_backend() {
+        None
+    } else {
+        metadata.find_attr("hbs")
+    }
+}
+
+/// Converts AsciiDoc files with one `asciidoctor` process per mode and directory (`batch_convert`
+/// in `book.ron`). The outputs are written to `out_dir` and read back by [`convert_adoc_buf`]
+///
+/// Files are grouped by whether a Handlebars template is applied (the embedded mode) or not.
+/// Plain text files and files with the `doctype` attribute are left to per-file conversion.
+pub fn batch_convert(
+    src_files: &[PathBuf],
+    out_dir: &Path,
+    acx: &AdocRunContext,
+    book: &BookStructure,
+) -> Result<BatchOutputs> {
+    let mut standalone = Vec::new();
+    let mut embedded = Vec::new();
 
-        let output = if book.book_ron.use_default_theme {
-            // use default theme
-            let mut hbs = hbs::init_hbs_default()?;
-            hbs::render_hbs_default(&mut hbs, &hbs_input, &src_file_name)?
+    for src_file in src_files {
+        if crate::book::is_text_file(src_file) {
+            continue;
+        }
+
+        let metadata = self::extract_metadata(src_file, acx, book)?;
+        if metadata.find_attr("doctype").is_some() {
+            continue;
+        }
+
+        if self::hbs_attr(&metadata, acx).is_some() {
+            embedded.push(src_file.clone());
         } else {
-            // use user theme
-            let mut hbs = hbs::init_hbs_user(hbs_file_path.parent().unwrap())?;
-            hbs::render_hbs_user(&mut hbs, &hbs_input, &src_file_name, &hbs_file_path)?
-        };
+            standalone.push(src_file.clone());
+        }
+    }
+
+    let mut outputs = adoc::run_asciidoctor_batch(&standalone, &out_dir.join("standalone"), acx)?;
 
-        buf.clear();
-        buf.write_str(&output)?;
+    let mut acx = acx.clone();
+    acx.set_embedded_mode(true);
+    outputs.extend(adoc::run_asciidoctor_batch(
+        &embedded,
+        &out_dir.join("embedded"),
+        &acx,
+    )?);
+
+    Ok(outputs)
+}
+
+/// Converts a plain text file to HTML-escaped `<pre>` block and then applies a Handlebars template
+fn convert_text_buf(
+    buf: &mut String,
+    src_file: &Path,
+    acx: &AdocRunContext,
+    hcx: &HbsContext,
+    book: &BookStructure,
+) -> Result<AdocMetadata> {
+    let text = fs::read_to_string(src_file).context("Unable to read source file")?;
+    let mut metadata = self::text_metadata(src_file, acx);
+    self::apply_default_hbs(&mut metadata, src_file, book);
+
+    buf.clear();
+    buf.reserve(self::estimate_output_capacity(src_file));
+    buf.push_str("<pre class=\"plain-text\">");
+    buf.push_str(&handlebars::html_escape(&text));
+    buf.push_str("</pre>\n");
+
+    if acx.is_direct_output() || !acx.is_html_backend() {
+        return Ok(metadata);
+    }
+
+    if let Some(hbs_attr) = metadata.find_attr("hbs") {
+        self::apply_template(buf, src_file, &metadata, hbs_attr, hcx, book)?;
+    }
+
+    Ok(metadata)
+}
+
+/// Writes a page saying that the file failed to build (`on_convert_error: Placeholder` in
+/// `book.ron`) and then applies a Handlebars template
+pub fn placeholder_buf(
+    buf: &mut String,
+    src_file: &Path,
+    err: &Error,
+    acx: &AdocRunContext,
+    hcx: &HbsContext,
+    book: &BookStructure,
+) -> Result<AdocMetadata> {
+    let mut metadata = self::extract_metadata(src_file, acx, book)
+        .unwrap_or_else(|_| AdocMetadata::extract_with_base("", acx));
+    self::apply_default_hbs(&mut metadata, src_file, book);
+    if metadata.title.is_none() {
+        metadata.title = Some(hbs::text_title(src_file));
     }
 
+    buf.clear();
+    writeln!(buf, "<div class=\"build-error\">")?;
+    writeln!(buf, "<p>This page failed to build.</p>")?;
+    writeln!(
+        buf,
+        "<pre>{}</pre>",
+        handlebars::html_escape(&format!("{:?}", err))
+    )?;
+    writeln!(buf, "</div>")?;
+
+    if acx.is_direct_output() || !acx.is_html_backend() {
+        return Ok(metadata);
+    }
+
+    if let Some(hbs_attr) = metadata.find_attr("hbs") {
+        self::apply_template(buf, src_file, &metadata, hbs_attr, hcx, book)?;
+    }
+
+    Ok(metadata)
+}
+
+/// Applies the Handlebars template to the converted HTML in `buf`
+fn apply_template(
+    buf: &mut String,
+    src_file: &Path,
+    metadata: &AdocMetadata,
+    hbs_attr: &AdocAttr,
+    hcx: &HbsContext,
+    book: &BookStructure,
+) -> Result<()> {
+    let src_file_name = format!("{}", src_file.display());
+    let src_dir = book.src_dir_path();
+
+    let hbs_file_path = {
+        let hbs_name = hbs_attr
+            .value()
+            .ok_or_else(|| anyhow!("`hbs` attribute without path"))?;
+        src_dir.join(hbs_name)
+    };
+
+    // `.hbs` files are always located just under `hbs_dir`
+    //     >>>> currently it's a mess! <<<<
+    let hbs_input = {
+        let url = book
+            .src_file_to_url(src_file)
+            .map_err(|err| anyhow!("Unable to get URL for file: {}", err))?;
+
+        let sidebar = hcx.sidebar_for_url(&url);
+        let (prev, next) = hcx.neighbors(&url);
+        let mut input = HbsInput::new(buf, metadata, &book.book_ron, sidebar);
+        input.math_head = hcx.math_head();
+        input.head_extra = metadata
+            .find_attr("head-extra")
+            .and_then(|attr| attr.value())
+            .or(book.book_ron.head_html.as_deref())
+            .map(|html| html.to_string());
+        input.prev = prev;
+        input.next = next;
+        input.breadcrumbs = hcx.breadcrumbs(&url);
+        input.includes = hcx.includes();
+        input.build_time = hcx.build_time().map(|time| time.to_string());
+        if metadata.find_attr("revdate").is_none() {
+            if let Some(date) = hcx.git_revdate(src_file) {
+                input.a_revdate = Some(date);
+            }
+        }
+        input
+    };
+
+    let output = if self::uses_default_theme(book, metadata) {
+        // use default theme
+        let mut hbs = hbs::init_hbs_default()?;
+        hbs::render_hbs_default(&mut hbs, &hbs_input, &src_file_name)?
+    } else {
+        // use user theme
+        let mut hbs = hbs::init_hbs_user(hbs_file_path.parent().unwrap(), hcx.partial_dirs())?;
+        hbs::render_hbs_user(&mut hbs, &hbs_input, &src_file_name, &hbs_file_path)?
+    };
+
+    buf.clear();
+    buf.write_str(&output)?;
+
     Ok(())
 }
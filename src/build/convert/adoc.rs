@@ -3,14 +3,21 @@
 */
 
 use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt, fs,
+    io::Write,
     path::{Path, PathBuf},
-    process::Command,
+    process::{Command, Stdio},
+    sync::Arc,
 };
 
-use anyhow::{bail, ensure, Context, Result};
+use anyhow::{anyhow, bail, ensure, Context, Result};
 use thiserror::Error;
 
-use crate::book::{config::CmdOptions, BookStructure};
+use crate::book::{
+    config::{self, CmdOptions, MathRenderer},
+    BookStructure,
+};
 
 // --------------------------------------------------------------------------------
 // `asciidoctor` runner
@@ -23,22 +30,129 @@ use crate::book::{config::CmdOptions, BookStructure};
 /// TODO: refactor and prefer it to anyhow::Error
 #[derive(Debug, Error, Clone)]
 pub enum AdocError {
-    #[error("Failed to convert file: {0}\nasciidoctor output\n--------------------------------\n{1}\n--------------------------------")]
-    FailedToConvert(PathBuf, String),
+    /// `adoc_cmd` in `book.ron` or `asciidoctor`
+    #[error("`{0}` is not in PATH")]
+    NotFound(String),
+    #[error("Failed to convert file: {}\n{}asciidoctor output\n--------------------------------\n{}\n--------------------------------", .src_file.display(), self::fmt_diagnostics(.diagnostics), .stderr)]
+    FailedToConvert {
+        src_file: PathBuf,
+        /// `None` if `asciidoctor` was terminated by a signal
+        exit_code: Option<i32>,
+        /// Diagnostics with line numbers parsed from the stderr
+        diagnostics: Vec<AdocDiagnostic>,
+        /// Raw stderr of `asciidoctor`
+        stderr: String,
+    },
+    #[error("`asciidoctor` produced empty output for non-empty source: {0}\n(add `:allow-empty:` to the file if it's intended)")]
+    EmptyOutput(PathBuf),
+    /// (source_file, include_target)
+    #[error("Unresolved `include::{1}[]` in {0}")]
+    UnresolvedInclude(PathBuf, String),
+}
+
+impl AdocError {
+    fn failed_to_convert(src_file: PathBuf, output: std::process::Output) -> Self {
+        let stderr = String::from_utf8(output.stderr)
+            .unwrap_or("<non-UTF8 stderr by `asciidoctor`>".to_string());
+        Self::FailedToConvert {
+            diagnostics: self::parse_diagnostics(&stderr, &src_file),
+            src_file,
+            exit_code: output.status.code(),
+            stderr,
+        }
+    }
+}
+
+/// Diagnostic of `asciidoctor` with a line number (`asciidoctor: ERROR: file.adoc: line N: ..`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdocDiagnostic {
+    /// `ERROR`, `WARNING`, ..
+    pub level: String,
+    /// File path as printed by `asciidoctor`
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+    /// The offending line in the file, if it could be read
+    pub source_line: Option<String>,
+}
+
+impl fmt::Display for AdocDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}:{}: {}",
+            self.level, self.file, self.line, self.message
+        )?;
+        if let Some(source_line) = &self.source_line {
+            write!(f, "\n{:>5} | {}", self.line, source_line)?;
+        }
+        Ok(())
+    }
+}
+
+fn fmt_diagnostics(diagnostics: &[AdocDiagnostic]) -> String {
+    diagnostics.iter().map(|d| format!("{}\n", d)).collect()
+}
+
+/// Parses the diagnostics with line numbers in the stderr of `asciidoctor` and reads the
+/// offending source lines
+///
+/// * `src_file`: the converted file (or directory in a batch conversion), against which relative
+///   paths in the diagnostics are resolved
+fn parse_diagnostics(stderr: &str, src_file: &Path) -> Vec<AdocDiagnostic> {
+    let base_dir = if src_file.is_dir() {
+        Some(src_file)
+    } else {
+        src_file.parent()
+    };
+
+    stderr
+        .lines()
+        .filter_map(|ln| {
+            let rest = ln.trim().strip_prefix("asciidoctor: ")?;
+            let (level, rest) = rest.split_once(": ")?;
+            if level.is_empty() || !level.chars().all(|c| c.is_ascii_uppercase()) {
+                return None;
+            }
+
+            let i = rest.find(": line ")?;
+            let file = &rest[..i];
+            let (line, message) = rest[i + ": line ".len()..].split_once(": ")?;
+            let line = line.parse::<usize>().ok()?;
+
+            let path = match base_dir {
+                Some(dir) => dir.join(file),
+                None => PathBuf::from(file),
+            };
+            let source_line = fs::read_to_string(&path)
+                .ok()
+                .and_then(|text| text.lines().nth(line.checked_sub(1)?).map(str::to_string));
+
+            Some(AdocDiagnostic {
+                level: level.to_string(),
+                file: file.to_string(),
+                line,
+                message: message.to_string(),
+                source_line,
+            })
+        })
+        .collect()
 }
 
 /// Context for running `asciidoctor`
 ///
 /// # String interpolation
 ///
-/// [`Self::replace_placeholder_strings`] does it.
+/// [`Self::replace_placeholder_strings`] does it. `${ENV_VAR}` reads the process environment and
+/// takes precedence over the `{base_url}`-style placeholders.
 ///
 /// # Asciidoctor options that are not used
 ///
 /// ## `asciidoctor -B`
 ///
 /// It's used to supply (virtual) directory, especially when the input is stdin. The
-/// directory path is used for the "safe mode".
+/// directory path is used for the "safe mode". File inputs use the source directory and string
+/// inputs ([`run_asciidoctor_str_buf`]) use the given base directory.
 ///
 /// ## `asciidoctor -D`
 ///
@@ -47,27 +161,177 @@ pub enum AdocError {
 /// ```sh
 /// $ asciidoctor -D out -R . '**/*.adoc'
 /// ```
+///
+/// It's only used in the direct output mode (`direct_output` in `book.ron`), where `asciidoctor`
+/// writes output files (and side-effect files such as diagram images) to the site directory by
+/// itself.
 #[derive(Debug, Clone)]
 pub struct AdocRunContext {
     /// Source directory
     src_dir: String,
+    /// Site directory
+    dst_dir: String,
     /// `asciidoctor -a` (attributes) or other options
     opts: CmdOptions,
     /// Used to modify `asciidoctor` attributes supplied to `.adoc` files
     base_url: String,
+    /// `asset_url` (or `base_url`) in `book.ron`, used for the same purpose
+    asset_url: String,
+    /// Let `asciidoctor` write output files directly instead of capturing stdout
+    direct_output: bool,
+    /// `adoc_cmd` in `book.ron` or `asciidoctor`
+    adoc_cmd: String,
+    /// `adoc_args_prefix` in `book.ron`
+    adoc_args_prefix: Vec<String>,
+    /// `asciidoctor -b`. `None` is `html5`
+    backend: Option<String>,
+    /// `asciidoctor -d`. `None` is `article`
+    doctype: Option<String>,
+    /// `asciidoctor --failure-level`
+    failure_level: Option<String>,
+    /// Turns warnings such as unresolved includes into conversion failures
+    strict: bool,
+    /// `default_attributes` in `book.ron`, supplied unless they're in `opts`
+    default_attrs: BTreeMap<String, String>,
+    /// Outputs of the batch conversion, read instead of running `asciidoctor` per file
+    batch: Option<Arc<BatchOutputs>>,
 }
 
 impl AdocRunContext {
     pub fn from_book(book: &BookStructure) -> Result<Self> {
         let src_dir = normalize(&book.src_dir_path())?;
+        let dst_dir = normalize(&book.site_dir_path())?;
+
+        let mut opts = book.book_ron.adoc_opts.clone();
+        self::merge_attributes(&mut opts, &book.book_ron.attributes);
+        if let Some(attributes) = book.profile_attributes() {
+            self::override_attributes(&mut opts, attributes);
+        }
+        if let Some(math) = book.book_ron.math {
+            self::ensure_stem_attr(&mut opts, math);
+        }
 
         Ok(Self {
             src_dir,
-            opts: book.book_ron.adoc_opts.clone(),
+            dst_dir,
+            opts,
             base_url: book.book_ron.base_url.to_string(),
+            asset_url: book.book_ron.asset_url().to_string(),
+            direct_output: book.book_ron.direct_output,
+            adoc_cmd: book.book_ron.adoc_cmd().to_string(),
+            adoc_args_prefix: book.book_ron.adoc_args_prefix.clone(),
+            backend: book.book_ron.backend.clone(),
+            doctype: match &book.book_ron.doctype {
+                Some(doctype) => Some(
+                    self::validate_doctype(doctype)
+                        .context("Invalid `doctype` in `book.ron`")?
+                        .to_string(),
+                ),
+                None => None,
+            },
+            failure_level: match &book.book_ron.failure_level {
+                Some(level) => Some(
+                    self::validate_failure_level(level)
+                        .context("Invalid `failure_level` in `book.ron`")?,
+                ),
+                None => None,
+            },
+            strict: false,
+            default_attrs: match &book.book_ron.default_attributes {
+                Some(attrs) => attrs.clone(),
+                None => config::DEFAULT_ATTRIBUTES
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect(),
+            },
+            batch: None,
         })
     }
 
+    /// Context for converting a file that is not in a book (`adbook convert`): no user options and
+    /// the default attributes. `{src_dir}` and `{dst_dir}` are the `base_dir`
+    pub fn standalone(base_dir: &Path) -> Result<Self> {
+        let base_dir = normalize(base_dir)?;
+
+        Ok(Self {
+            src_dir: base_dir.clone(),
+            dst_dir: base_dir,
+            opts: CmdOptions::new(),
+            base_url: String::new(),
+            asset_url: String::new(),
+            direct_output: false,
+            adoc_cmd: "asciidoctor".to_string(),
+            adoc_args_prefix: Vec::new(),
+            backend: None,
+            doctype: None,
+            failure_level: None,
+            strict: false,
+            default_attrs: config::DEFAULT_ATTRIBUTES
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            batch: None,
+        })
+    }
+
+    /// Sets up the `asciidoctor` command (`adoc_cmd` and `adoc_args_prefix` in `book.ron`) without
+    /// arguments for conversion
+    pub fn command(&self) -> Result<Command, AdocError> {
+        self::adoc_command(&self.adoc_cmd, &self.adoc_args_prefix)
+    }
+
+    /// If warnings such as unresolved includes are turned into conversion failures
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// If `asciidoctor` writes output files directly to the site directory
+    pub fn is_direct_output(&self) -> bool {
+        self.direct_output
+    }
+
+    /// If `asciidoctor` outputs HTML (the `html5` backend) or not
+    pub fn is_html_backend(&self) -> bool {
+        config::is_html_backend(self.backend.as_deref())
+    }
+
+    /// Path to the output file that `asciidoctor` writes in the direct output mode
+    pub fn direct_output_file(&self, src_file: &Path) -> Result<PathBuf> {
+        // NOTE: `src_dir` is normalized (not an UNC path on Windows)
+        let src_file = PathBuf::from(normalize(src_file)?);
+        let rel_path = src_file.strip_prefix(&self.src_dir).with_context(|| {
+            format!(
+                "Source file out of source directory: {}",
+                src_file.display()
+            )
+        })?;
+        Ok(Path::new(&self.dst_dir)
+            .join(rel_path)
+            .with_extension(config::backend_extension(self.backend.as_deref())))
+    }
+
+    /// Overrides the doctype (`asciidoctor -d`), typically with the `doctype` attribute of an
+    /// article
+    pub fn set_doctype(&mut self, doctype: &str) -> Result<()> {
+        self.doctype = Some(self::validate_doctype(doctype)?.to_string());
+        Ok(())
+    }
+
+    /// If the embedded mode is on
+    pub fn is_embedded_mode(&self) -> bool {
+        self.opts.iter().any(|(name, _values)| name == "--embedded")
+    }
+
+    /// Lets [`run_asciidoctor_buf`] read the outputs of a batch conversion instead of running
+    /// `asciidoctor`
+    pub fn set_batch_outputs(&mut self, batch: BatchOutputs) {
+        self.batch = Some(Arc::new(batch));
+    }
+
     /// Embedded mode: output without header (including title) and footer
     pub fn set_embedded_mode(&mut self, b: bool) {
         if b {
@@ -77,16 +341,41 @@ impl AdocRunContext {
                 .opts
                 .clone()
                 .into_iter()
-                .filter(|(name, _values)| name == "--embedded")
+                .filter(|(name, _values)| name != "--embedded")
                 .collect();
         }
     }
 
     /// Applies `asciidoctor` options defined in `book.ron`
-    pub fn apply_options(&self, cmd: &mut Command) {
-        // setup directory settings
-        cmd.current_dir(&self.src_dir).args(&["-B", &self.src_dir]);
+    ///
+    /// The working directory is set to the parent directory of the source file. `-B` is kept at
+    /// the source directory for the safe mode. Each [`Command`] owns its working directory, so
+    /// it's safe to run them in parallel.
+    pub fn apply_options(&self, cmd: &mut Command, src_file: &Path) {
+        // NOTE: The working directory only affects stdin input, where `asciidoctor` has no
+        // document directory (see `apply_options_stdin`). With file input, `include::` directives
+        // resolve against the file's own directory (`docdir`) whatever the working directory is.
+        let work_dir = src_file
+            .parent()
+            .map(|dir| dir.to_path_buf())
+            .unwrap_or_else(|| PathBuf::from(&self.src_dir));
+        cmd.current_dir(work_dir).args(&["-B", &self.src_dir]);
+
+        self.apply_user_options(cmd);
+    }
+
+    /// Applies `asciidoctor` options defined in `book.ron` for stdin input
+    ///
+    /// Both the working directory and `-B` are set to `base_dir`, so that `include::` directives
+    /// and images resolve as if the document was a file in `base_dir`.
+    pub fn apply_options_stdin(&self, cmd: &mut Command, base_dir: &Path) {
+        let base_dir = format!("{}", base_dir.display());
+        cmd.current_dir(&base_dir).args(["-B", &base_dir]);
+
+        self.apply_user_options(cmd);
+    }
 
+    fn apply_user_options(&self, cmd: &mut Command) {
         // setup user options
         for (opt, args) in &self.opts {
             // case 1. option without argument
@@ -102,27 +391,247 @@ impl AdocRunContext {
                 cmd.args(&[opt, &arg]);
             }
         }
+
+        // default attributes (soft set, so that documents can override them)
+        for (name, value) in self.default_attrs_unspecified() {
+            let value = self.replace_placeholder_strings(value);
+            cmd.args(["-a", &format!("{}={}@", name, value)]);
+        }
+    }
+
+    /// `default_attrs` that are not specified with `-a` options
+    fn default_attrs_unspecified(&self) -> impl Iterator<Item = (&String, &String)> {
+        let specified = self
+            .opts
+            .iter()
+            .filter(|(opt, _args)| opt == "-a")
+            .flat_map(|(_opt, args)| args.iter())
+            .map(|arg| AdocAttr::from_cmd_opt(arg).name().to_string())
+            .collect::<Vec<_>>();
+
+        self.default_attrs
+            .iter()
+            .filter(move |(name, _value)| !specified.contains(name))
     }
 
+    /// Expands `{base_url}`, `{asset_url}`, `{src_dir}`, `{dst_dir}` and `${ENV_VAR}`
+    ///
+    /// `${NAME}` always refers to the environment variable, so `${base_url}` is the environment
+    /// variable `base_url` and not the placeholder. The string is scanned once and expanded values
+    /// are not expanded again. Undefined environment variables are left as-is (see
+    /// [`Self::undefined_env_vars`]).
     pub fn replace_placeholder_strings(&self, arg: &str) -> String {
-        let arg = arg.replace(r#"{base_url}"#, &self.base_url);
-        let arg = arg.replace(r#"{src_dir}"#, &self.src_dir);
+        self::expand_placeholders(arg, &self.placeholders(), |name| std::env::var(name).ok()).0
+    }
+
+    fn placeholders(&self) -> [(&str, &str); 4] {
+        [
+            ("base_url", &self.base_url),
+            ("asset_url", &self.asset_url),
+            ("src_dir", &self.src_dir),
+            ("dst_dir", &self.dst_dir),
+        ]
+    }
+
+    /// Errors for `${ENV_VAR}` in `adoc_opts` and attributes that are not defined in the
+    /// environment
+    pub fn undefined_env_vars(&self) -> Vec<anyhow::Error> {
+        let args = self
+            .opts
+            .iter()
+            .flat_map(|(_opt, args)| args.iter())
+            .chain(self.default_attrs.values());
+
+        let mut names = BTreeSet::new();
+        for arg in args {
+            let (_expanded, undefined) =
+                self::expand_placeholders(arg, &[], |name| std::env::var(name).ok());
+            names.extend(undefined);
+        }
+
+        names
+            .into_iter()
+            .map(|name| {
+                anyhow!(
+                    "Undefined environment variable `${{{}}}` in `book.ron`",
+                    name
+                )
+            })
+            .collect()
+    }
+}
+
+/// Expands `{placeholder}` and `${ENV_VAR}` in a single pass. Returns the expanded string and the
+/// names of undefined environment variables, which are left as-is
+fn expand_placeholders(
+    arg: &str,
+    placeholders: &[(&str, &str)],
+    env: impl Fn(&str) -> Option<String>,
+) -> (String, Vec<String>) {
+    let mut out = String::with_capacity(arg.len());
+    let mut undefined = Vec::new();
+    let mut rest = arg;
+
+    'scan: while let Some(c) = rest.chars().next() {
+        // `${ENV_VAR}`
+        if let Some(after) = rest.strip_prefix("${") {
+            if let Some(end) = after.find('}') {
+                let name = &after[..end];
+                let is_name =
+                    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+                if is_name {
+                    match env(name) {
+                        Some(value) => out.push_str(&value),
+                        None => {
+                            out.push_str(&rest[..end + 3]);
+                            undefined.push(name.to_string());
+                        }
+                    }
+                    rest = &after[end + 1..];
+                    continue 'scan;
+                }
+            }
+        }
+
+        // `{placeholder}`
+        if let Some(after) = rest.strip_prefix('{') {
+            for (name, value) in placeholders {
+                if let Some(after) = after
+                    .strip_prefix(name)
+                    .and_then(|after| after.strip_prefix('}'))
+                {
+                    out.push_str(value);
+                    rest = after;
+                    continue 'scan;
+                }
+            }
+        }
+
+        out.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+
+    (out, undefined)
+}
+
+/// Doctypes known to `asciidoctor`
+pub const DOCTYPES: &[&str] = &["article", "book", "manpage", "inline"];
+
+/// Fails if the doctype is unknown to `asciidoctor`
+pub fn validate_doctype(doctype: &str) -> Result<&str> {
+    ensure!(
+        DOCTYPES.contains(&doctype),
+        "Unknown doctype `{}` (expected one of {:?})",
+        doctype,
+        DOCTYPES
+    );
+    Ok(doctype)
+}
+
+/// Failure levels known to `asciidoctor --failure-level`
+pub const FAILURE_LEVELS: &[&str] = &["INFO", "WARN", "WARNING", "ERROR", "FATAL"];
+
+/// Fails if the level is unknown to `asciidoctor --failure-level`. Returns the level in upper case
+pub fn validate_failure_level(level: &str) -> Result<String> {
+    let upper = level.to_uppercase();
+    ensure!(
+        FAILURE_LEVELS.contains(&upper.as_str()),
+        "Unknown failure level `{}` (expected one of {:?})",
+        level,
+        FAILURE_LEVELS
+    );
+    Ok(upper)
+}
+
+/// Adds `attributes` in `book.ron` as `-a name=value` options. Attributes already specified with
+/// `-a` in `adoc_opts` take precedence and are not added
+fn merge_attributes(opts: &mut CmdOptions, attributes: &BTreeMap<String, String>) {
+    let specified = opts
+        .iter()
+        .filter(|(opt, _args)| opt == "-a")
+        .flat_map(|(_opt, args)| args.iter())
+        .map(|arg| AdocAttr::from_cmd_opt(arg).name().to_string())
+        .collect::<Vec<_>>();
+
+    let args = attributes
+        .iter()
+        .filter(|(name, _value)| !specified.contains(name))
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>();
+
+    if args.is_empty() {
+        return;
+    }
 
-        arg
+    match opts.iter_mut().find(|(opt, _args)| opt == "-a") {
+        Some((_opt, a_args)) => a_args.extend(args),
+        None => opts.push(("-a".to_string(), args)),
     }
 }
 
-/// UNC path is not recognized by `asciidoctor`, so this is the hot fix:
+/// Adds attributes as `-a name=value` options, removing the `-a` options of the same names
+fn override_attributes(opts: &mut CmdOptions, attributes: &BTreeMap<String, String>) {
+    for (opt, args) in opts.iter_mut() {
+        if opt == "-a" {
+            args.retain(|arg| !attributes.contains_key(AdocAttr::from_cmd_opt(arg).name()));
+        }
+    }
+
+    self::merge_attributes(opts, attributes);
+}
+
+/// Adds `-a stem@=latexmath` unless the `stem` attribute is given
+fn ensure_stem_attr(opts: &mut CmdOptions, math: MathRenderer) {
+    let stem = opts
+        .iter()
+        .filter(|(opt, _args)| opt == "-a")
+        .flat_map(|(_opt, args)| args.iter())
+        .map(|arg| AdocAttr::from_cmd_opt(arg))
+        .find(|attr| attr.name() == "stem");
+
+    match stem {
+        None => {
+            opts.push(("-a".to_string(), vec!["stem@=latexmath".to_string()]));
+        }
+        Some(AdocAttr::Allow(_name, value))
+            if math == MathRenderer::KaTeX && value == "asciimath" =>
+        {
+            log::warn!("KaTeX doesn't support `asciimath`. Consider `stem=latexmath`");
+        }
+        Some(_) => {}
+    }
+}
+
+/// Canonicalizes a path into a string that `asciidoctor` accepts
+///
+/// `fs::canonicalize` returns a UNC path (`\\?\C:\..`) on Windows, which is not recognized by
+/// `asciidoctor`. See [`strip_unc_prefix`].
 fn normalize(path: &Path) -> Result<String> {
     let s = format!("{}", path.canonicalize()?.display());
-    let s = s
-        .strip_prefix(r#"\\?\"#)
-        .map(|s| {
-            // `\\?\C:\` → `c:\`
-            s.replace(r#"\\?\"#, "/")
-        })
-        .unwrap_or(s);
-    Ok(s)
+    Ok(self::strip_unc_prefix(&s))
+}
+
+/// `\\?\C:\foo\bar` → `C:/foo/bar`, `\\?\UNC\server\share` → `//server/share`. Other paths
+/// are returned as-is
+fn strip_unc_prefix(path: &str) -> String {
+    if let Some(s) = path.strip_prefix(r#"\\?\UNC\"#) {
+        format!("//{}", s.replace('\\', "/"))
+    } else if let Some(s) = path.strip_prefix(r#"\\?\"#) {
+        s.replace('\\', "/")
+    } else {
+        path.to_string()
+    }
+}
+
+/// Sets up the `asciidoctor` command (`adoc_cmd`) with the prefix arguments (`adoc_args_prefix`)
+pub fn adoc_command(adoc_cmd: &str, args_prefix: &[String]) -> Result<Command, AdocError> {
+    // NOTE: On windows `Command` did not find `asciidoctor`, so let's give absolute path to it.
+    let asciidoctor =
+        which::which(adoc_cmd).map_err(|_| AdocError::NotFound(adoc_cmd.to_string()))?;
+
+    let mut cmd = Command::new(format!("{}", asciidoctor.display()));
+    cmd.args(args_prefix);
+    Ok(cmd)
 }
 
 /// Sets up `asciidoctor` command
@@ -132,13 +641,30 @@ pub fn asciidoctor(src_file: &Path, acx: &AdocRunContext) -> Result<Command> {
         "Given non-existing file as conversion source"
     );
 
-    // NOTE: On windows `Command` did not find `asciidoctor`, so let's give absolute path to it.
-    let asciidoctor = which::which("asciidoctor").unwrap();
-    let mut cmd = Command::new(format!("{}", asciidoctor.display()));
+    let mut cmd = acx.command()?;
 
-    // output to stdout
     // NOTE: `fs::canonizalize` returns the carsed UNC path on Windows.
-    cmd.arg(&normalize(src_file)?).args(&["-o", "-"]);
+    cmd.arg(&normalize(src_file)?);
+
+    if acx.direct_output {
+        // mirror the source directory to the site directory
+        cmd.args(["-R", &acx.src_dir, "-D", &acx.dst_dir]);
+    } else {
+        // output to stdout
+        cmd.args(&["-o", "-"]);
+    }
+
+    if let Some(backend) = &acx.backend {
+        cmd.args(["-b", backend]);
+    }
+
+    if let Some(doctype) = &acx.doctype {
+        cmd.args(["-d", doctype]);
+    }
+
+    if let Some(level) = &acx.failure_level {
+        cmd.args(["--failure-level", level]);
+    }
 
     // require `asciidoctor-diagram`
     cmd.args(&["-r", "asciidoctor-diagram"]);
@@ -147,11 +673,159 @@ pub fn asciidoctor(src_file: &Path, acx: &AdocRunContext) -> Result<Command> {
     cmd.arg("--trace").arg("--verbose");
 
     // apply directory settings and user options (often ones defined in `book.ron`)
-    acx.apply_options(&mut cmd);
+    acx.apply_options(&mut cmd, src_file);
+
+    Ok(cmd)
+}
+
+/// Sets up `asciidoctor` command that converts multiple files at once
+///
+/// The outputs mirror the source directory in `out_dir` (`-R <src_dir> -D <out_dir>`). The files
+/// must be in the same directory, which is the working directory as in the per-file conversion
+/// ([`AdocRunContext::apply_options`]).
+pub fn asciidoctor_batch(
+    src_files: &[PathBuf],
+    out_dir: &Path,
+    acx: &AdocRunContext,
+) -> Result<Command> {
+    let work_dir = match src_files.first().and_then(|file| file.parent()) {
+        Some(dir) => dir.to_path_buf(),
+        None => PathBuf::from(&acx.src_dir),
+    };
+    ensure!(
+        src_files
+            .iter()
+            .all(|file| file.parent() == Some(&work_dir)),
+        "Given files in different directories for a batch conversion"
+    );
+
+    let mut cmd = acx.command()?;
+
+    for src_file in src_files {
+        cmd.arg(&normalize(src_file)?);
+    }
+
+    let out_dir = format!("{}", out_dir.display());
+    cmd.args(["-R", &acx.src_dir, "-D", &out_dir]);
+
+    if let Some(backend) = &acx.backend {
+        cmd.args(["-b", backend]);
+    }
+
+    if let Some(doctype) = &acx.doctype {
+        cmd.args(["-d", doctype]);
+    }
+
+    if let Some(level) = &acx.failure_level {
+        cmd.args(["--failure-level", level]);
+    }
+
+    cmd.args(["-r", "asciidoctor-diagram"]);
+    cmd.arg("--trace").arg("--verbose");
+
+    cmd.current_dir(work_dir).args(["-B", &acx.src_dir]);
+    acx.apply_user_options(&mut cmd);
 
     Ok(cmd)
 }
 
+/// Sets up `asciidoctor` command that reads the document from stdin and writes to stdout
+///
+/// * `base_dir`: directory that `include::` directives and images are resolved from
+pub fn asciidoctor_stdin(base_dir: &Path, acx: &AdocRunContext) -> Result<Command> {
+    ensure!(
+        base_dir.is_dir(),
+        "Given non-directory as base directory: {}",
+        base_dir.display()
+    );
+
+    let mut cmd = acx.command()?;
+
+    // stdin to stdout (the direct output mode doesn't apply)
+    cmd.args(["-", "-o", "-"]);
+
+    if let Some(backend) = &acx.backend {
+        cmd.args(["-b", backend]);
+    }
+
+    if let Some(doctype) = &acx.doctype {
+        cmd.args(["-d", doctype]);
+    }
+
+    if let Some(level) = &acx.failure_level {
+        cmd.args(["--failure-level", level]);
+    }
+
+    cmd.args(["-r", "asciidoctor-diagram"]);
+    cmd.arg("--trace").arg("--verbose");
+
+    acx.apply_options_stdin(&mut cmd, Path::new(&normalize(base_dir)?));
+
+    Ok(cmd)
+}
+
+/// Runs `asciidoctor` with a string input and writes the output to a string buffer
+///
+/// * `base_dir`: directory that `include::` directives and images are resolved from
+pub fn run_asciidoctor_str_buf(
+    buf: &mut String,
+    text: &str,
+    base_dir: &Path,
+    acx: &AdocRunContext,
+) -> Result<()> {
+    let mut cmd =
+        self::asciidoctor_stdin(base_dir, acx).context("when setting up `asciidoctor` options")?;
+
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| {
+            format!(
+                "when running `asciidoctor`:
+  cmd: {:?}",
+                cmd
+            )
+        })?;
+
+    // write stdin from another thread so that a full stdout pipe doesn't block us
+    let writer = {
+        let mut stdin = child.stdin.take().unwrap();
+        let text = text.to_string();
+        std::thread::spawn(move || stdin.write_all(text.as_bytes()))
+    };
+
+    let output = child
+        .wait_with_output()
+        .context("when waiting for `asciidoctor`")?;
+    writer
+        .join()
+        .map_err(|_| anyhow!("Panicked while writing stdin of `asciidoctor`"))?
+        .context("Unable to write stdin of `asciidoctor`")?;
+
+    let stdin_path = base_dir.join("<stdin>");
+
+    ensure!(
+        output.status.success(),
+        AdocError::failed_to_convert(stdin_path, output)
+    );
+
+    let text = std::str::from_utf8(&output.stdout)
+        .with_context(|| "Unable to decode stdout of `asciidoctor` as UTF8")?;
+    buf.push_str(text);
+
+    if !output.stderr.is_empty() {
+        log::warn!(
+            "Asciidoctor stderr while converting {}:\n{}",
+            stdin_path.display(),
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        );
+    }
+
+    Ok(())
+}
+
 /// Runs `asciidoctor` command and returns the output
 pub fn run_asciidoctor(src_file: &Path, acx: &AdocRunContext) -> Result<std::process::Output> {
     let mut cmd =
@@ -176,38 +850,210 @@ pub fn run_asciidoctor(src_file: &Path, acx: &AdocRunContext) -> Result<std::pro
 
 /// Runs `asciidoctor` command and writes the output to a string buffer
 pub fn run_asciidoctor_buf(buf: &mut String, src_file: &Path, acx: &AdocRunContext) -> Result<()> {
+    // the file is already converted in a batch
+    if let Some(out_file) = acx
+        .batch
+        .as_ref()
+        .and_then(|batch| batch.find(src_file, acx.is_embedded_mode()))
+    {
+        let text = fs::read_to_string(out_file).with_context(|| {
+            format!(
+                "Unable to read `asciidoctor` output file: {}",
+                out_file.display()
+            )
+        })?;
+        buf.push_str(&text);
+        return Ok(());
+    }
+
     let output = self::run_asciidoctor(src_file, acx)?;
 
     // ensure the conversion succeeded
     ensure!(
         output.status.success(),
         // ..or else report it as an error
-        AdocError::FailedToConvert(
-            src_file.to_path_buf(),
-            String::from_utf8(output.stderr)
-                .unwrap_or("<non-UTF8 stderr by `asciidoctor`>".to_string())
-        )
+        AdocError::failed_to_convert(src_file.to_path_buf(), output)
     );
 
     // finally output to the buffer
-    let text = std::str::from_utf8(&output.stdout)
-        .with_context(|| "Unable to decode stdout of `asciidoctor` as UTF8")?;
-    buf.push_str(text);
+    if acx.direct_output {
+        // read back the file `asciidoctor` wrote so that it's cached
+        let dst_file = acx.direct_output_file(src_file)?;
+        let text = fs::read_to_string(&dst_file).with_context(|| {
+            format!(
+                "Unable to read `asciidoctor` output file: {}",
+                dst_file.display()
+            )
+        })?;
+        buf.push_str(&text);
+    } else {
+        let text = std::str::from_utf8(&output.stdout)
+            .with_context(|| "Unable to decode stdout of `asciidoctor` as UTF8")?;
+        buf.push_str(text);
+    }
 
-    // stderr
+    // stderr of a successful conversion is just warnings (failures are reported with
+    // `AdocError::FailedToConvert`)
     if !output.stderr.is_empty() {
-        eprintln!(
-            "Asciidoctor stderr while converting {}:",
-            src_file.display()
+        log::warn!(
+            "Asciidoctor stderr while converting {}:\n{}",
+            src_file.display(),
+            String::from_utf8_lossy(&output.stderr).trim_end()
         );
-        let err = String::from_utf8(output.stderr)
-            .unwrap_or("<non-UTF8 stderr by `asciidoctor`>".to_string());
-        eprintln!("{}", &err);
     }
 
     Ok(())
 }
 
+/// Output files of a batch conversion (`batch_convert` in `book.ron`)
+#[derive(Debug, Clone, Default)]
+pub struct BatchOutputs {
+    /// Source file → (output file, if it's converted in the embedded mode)
+    files: HashMap<PathBuf, (PathBuf, bool)>,
+}
+
+impl BatchOutputs {
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    pub fn extend(&mut self, other: Self) {
+        self.files.extend(other.files);
+    }
+
+    /// Output file of a source file converted in the same mode
+    fn find(&self, src_file: &Path, embedded: bool) -> Option<&Path> {
+        self.files
+            .get(src_file)
+            .filter(|(out_file, e)| *e == embedded && out_file.is_file())
+            .map(|(out_file, _e)| out_file.as_path())
+    }
+}
+
+/// Runs `asciidoctor` once per directory for multiple files, writing the outputs to `out_dir`
+///
+/// Each directory is converted in its own process, so that the working directory is the same as
+/// in the per-file conversion.
+pub fn run_asciidoctor_batch(
+    src_files: &[PathBuf],
+    out_dir: &Path,
+    acx: &AdocRunContext,
+) -> Result<BatchOutputs> {
+    let mut dirs = BTreeMap::<&Path, Vec<PathBuf>>::new();
+    for src_file in src_files {
+        let dir = src_file.parent().unwrap_or_else(|| Path::new(""));
+        dirs.entry(dir).or_default().push(src_file.clone());
+    }
+
+    let mut outputs = BatchOutputs::default();
+    for files in dirs.values() {
+        outputs.extend(self::run_asciidoctor_batch_dir(files, out_dir, acx)?);
+    }
+
+    Ok(outputs)
+}
+
+/// Runs `asciidoctor` once for files in the same directory
+fn run_asciidoctor_batch_dir(
+    src_files: &[PathBuf],
+    out_dir: &Path,
+    acx: &AdocRunContext,
+) -> Result<BatchOutputs> {
+    if src_files.is_empty() {
+        return Ok(BatchOutputs::default());
+    }
+
+    fs::create_dir_all(out_dir)
+        .with_context(|| format!("Unable to create directory: {}", out_dir.display()))?;
+
+    let mut cmd = self::asciidoctor_batch(src_files, out_dir, acx)
+        .context("when setting up `asciidoctor` options")?;
+    let output = cmd
+        .output()
+        .with_context(|| format!("when running `asciidoctor`: {:?}", cmd))?;
+
+    ensure!(
+        output.status.success(),
+        AdocError::failed_to_convert(PathBuf::from(&acx.src_dir), output)
+    );
+
+    if !output.stderr.is_empty() {
+        log::warn!(
+            "Asciidoctor stderr while converting files in a batch:\n{}",
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        );
+    }
+
+    let ext = config::backend_extension(acx.backend.as_deref());
+    let embedded = acx.is_embedded_mode();
+
+    let mut files = HashMap::with_capacity(src_files.len());
+    for src_file in src_files {
+        let normalized = PathBuf::from(normalize(src_file)?);
+        let rel_path = normalized.strip_prefix(&acx.src_dir).with_context(|| {
+            format!(
+                "Source file out of source directory: {}",
+                src_file.display()
+            )
+        })?;
+        let out_file = out_dir.join(rel_path).with_extension(ext);
+        files.insert(src_file.to_path_buf(), (out_file, embedded));
+    }
+
+    Ok(BatchOutputs { files })
+}
+
+/// Targets of `include::` directives that `asciidoctor` couldn't resolve
+///
+/// `asciidoctor` doesn't fail on missing includes but emits `Unresolved directive in <file> -
+/// include::<target>[]` into the output.
+pub fn find_unresolved_includes(output: &str) -> Vec<&str> {
+    const MARKER: &str = "Unresolved directive in ";
+    const INCLUDE: &str = " - include::";
+
+    output
+        .match_indices(MARKER)
+        .filter_map(|(i, _)| {
+            let rest = &output[i + MARKER.len()..];
+            // the marker is on one line
+            let rest = &rest[..rest.find('\n').unwrap_or(rest.len())];
+            let rest = &rest[rest.find(INCLUDE)? + INCLUDE.len()..];
+            Some(&rest[..rest.find('[')?])
+        })
+        .collect()
+}
+
+/// Reports unresolved includes in the output as warnings, or fails in strict mode
+pub fn check_unresolved_includes(
+    src_file: &Path,
+    output: &str,
+    acx: &AdocRunContext,
+) -> Result<Vec<AdocError>> {
+    let errors = self::find_unresolved_includes(output)
+        .into_iter()
+        .map(|target| AdocError::UnresolvedInclude(src_file.to_path_buf(), target.to_string()))
+        .collect::<Vec<_>>();
+
+    if acx.is_strict() {
+        if let Some(err) = errors.into_iter().next() {
+            bail!(err);
+        }
+        return Ok(vec![]);
+    }
+
+    Ok(errors)
+}
+
+/// If `asciidoctor` produced empty (or whitespace-only) output for a non-empty source, which
+/// signals silent content loss such as a comment-only file or a botched `include::`
+pub fn is_empty_output(src_text: &str, output: &str) -> bool {
+    !src_text.trim().is_empty() && output.trim().is_empty()
+}
+
 // --------------------------------------------------------------------------------
 // Metadata extraction
 
@@ -253,12 +1099,39 @@ impl AdocAttr {
 
     /// "name" -> Allow("attr") | Deny("attr")
     pub fn from_name(name: &str) -> Self {
-        if name.starts_with('!') {
-            Self::deny(&name[1..])
+        if let Some(name) = name.strip_prefix('!') {
+            Self::deny(name)
+        } else if let Some(name) = name.strip_suffix('!') {
+            Self::deny(name)
         } else {
             Self::allow(name, "")
         }
     }
+
+    /// Parses an argument of `asciidoctor -a`
+    ///
+    /// * `attr` | `!attr` | `attr!`
+    /// * `name=value` | `name@=value` | `name=value@`
+    ///
+    /// The value is split at the first `=`, so it can contain `=` and spaces. `@` marks a soft set
+    /// in Asciidoctor, but we just ignore it; different from the original Asciidoctor, attributes
+    /// are always overridable by documents. A trailing `@` in the value is stripped only once, and
+    /// is kept if the name is already marked with `@`, so `name@=value@` and `name=value@@` are
+    /// `value@`.
+    pub fn from_cmd_opt(opt: &str) -> Self {
+        let (name, value) = match opt.split_once('=') {
+            Some((name, value)) if !name.is_empty() => (name, value),
+            _ => return Self::from_name(opt),
+        };
+
+        let value = match name.strip_suffix('@') {
+            Some(_) => value,
+            None => value.strip_suffix('@').unwrap_or(value),
+        };
+        let name = name.strip_suffix('@').unwrap_or(name);
+
+        Self::allow(name, value)
+    }
 }
 
 /// Asciidoctor metadata supplied to Handlebars data
@@ -269,12 +1142,15 @@ impl AdocAttr {
 pub struct AdocMetadata {
     pub title: Option<String>,
     attrs: Vec<AdocAttr>,
-    // TODO: supply base attribute set from `book.ron`
+    /// Fallback attributes: `asciidoctor` options and then `default_attributes` in `book.ron`
     base: Option<Box<Self>>,
+    /// Names of attributes set more than once in the document with different values
+    overridden: Vec<String>,
 }
 
 impl AdocMetadata {
-    /// Tries to find an attribute with name. Duplicates are not conisdered
+    /// Tries to find an attribute with name. Attributes in the document are deduplicated on
+    /// extraction (the last one wins)
     pub fn find_attr(&self, name: &str) -> Option<&AdocAttr> {
         // from self
         if let Some(attr) = self.attrs.iter().find(|a| a.name() == name) {
@@ -288,6 +1164,59 @@ impl AdocMetadata {
 
         None
     }
+
+    /// Names of attributes set more than once in the document with different values. Only the
+    /// last ones are used
+    pub fn overridden_attrs(&self) -> &[String] {
+        &self.overridden
+    }
+
+    /// Tries to find an attribute in the document itself, ignoring the base (`asciidoctor` options)
+    pub fn find_own_attr(&self, name: &str) -> Option<&AdocAttr> {
+        self.attrs.iter().find(|a| a.name() == name)
+    }
+
+    /// Sets an attribute as if it's in the document, unless the document has it by itself
+    pub fn set_own_attr_if_missing(&mut self, attr: AdocAttr) {
+        if self.find_own_attr(attr.name()).is_none() {
+            self.attrs.push(attr);
+        }
+    }
+
+    /// If the attribute is set (and not unset with `:!name:`)
+    pub fn is_set(&self, name: &str) -> bool {
+        matches!(self.find_attr(name), Some(AdocAttr::Allow(..)))
+    }
+
+    /// Attributes whose names start with the prefix, keyed by the names without the prefix. The
+    /// document overrides the base as in [`Self::find_attr`], and unset attributes (`:!name:`) are
+    /// omitted
+    pub fn attrs_with_prefix(&self, prefix: &str) -> HashMap<String, String> {
+        let mut seen = HashMap::<&str, Option<&str>>::new();
+
+        let mut meta = Some(self);
+        while let Some(m) = meta {
+            for attr in &m.attrs {
+                if attr.name().starts_with(prefix) {
+                    seen.entry(attr.name()).or_insert_with(|| attr.value());
+                }
+            }
+            meta = m.base.as_deref();
+        }
+
+        seen.into_iter()
+            .filter(|(name, _value)| name.len() > prefix.len())
+            .filter_map(|(name, value)| {
+                Some((name[prefix.len()..].to_string(), value?.to_string()))
+            })
+            .collect()
+    }
+
+    /// If the document itself has the `draft` attribute. Drafts are built only with `adbook build
+    /// --drafts`
+    pub fn is_draft(&self) -> bool {
+        matches!(self.find_own_attr("draft"), Some(AdocAttr::Allow(..)))
+    }
 }
 
 /// Parsers
@@ -298,11 +1227,12 @@ impl AdocMetadata {
     }
 
     /// Extracts metadata from AsciiDoc string and sets up fallback attributes from `asciidoctor`
-    /// command line options
+    /// command line options and then `default_attributes` in `book.ron`
     pub fn extract_with_base(adoc_text: &str, acx: &AdocRunContext) -> Self {
         let mut meta = Self::extract(adoc_text, acx);
 
-        let base = Self::from_cmd_opts(&acx.opts, acx);
+        let mut base = Self::from_cmd_opts(&acx.opts, acx);
+        base.derive(Self::from_default_attrs(acx));
         meta.derive(base);
 
         meta
@@ -318,6 +1248,15 @@ impl AdocMetadata {
     ///
     /// Replaces placeholder strings in attribute values.
     pub fn extract(text: &str, acx: &AdocRunContext) -> Self {
+        Self::extract_impl(text, |value| acx.replace_placeholder_strings(value))
+    }
+
+    /// Extracts metadata from AsciiDoc string without replacing placeholder strings
+    pub fn extract_header(text: &str) -> Self {
+        Self::extract_impl(text, |value| value.to_string())
+    }
+
+    fn extract_impl(text: &str, map_value: impl Fn(&str) -> String) -> Self {
         let mut lines = text
             .lines()
             .filter(|ln| !Self::is_line_to_skip(ln))
@@ -333,7 +1272,8 @@ impl AdocMetadata {
         };
 
         // :attribute: value
-        let mut attrs = Vec::with_capacity(10);
+        let mut attrs = Vec::<AdocAttr>::with_capacity(10);
+        let mut overridden = Vec::<String>::new();
         while let Some(line_str) = lines.next() {
             // locate two colons (`:`)
             let mut colons = line_str.bytes().enumerate().filter(|(_i, c)| *c == b':');
@@ -373,80 +1313,97 @@ impl AdocMetadata {
                 }
             };
 
-            if name.starts_with('!') {
+            let attr = if name.starts_with('!') {
                 // :!attribute:
-                attrs.push(AdocAttr::deny(&name[1..]));
+                AdocAttr::deny(&name[1..])
             } else {
                 // :attribute: value
-                let value = acx.replace_placeholder_strings(value);
-                attrs.push(AdocAttr::allow(name, value));
+                AdocAttr::allow(name, map_value(value))
+            };
+
+            // the last one wins as in AsciiDoc
+            if let Some(pos) = attrs.iter().position(|a| a.name() == attr.name()) {
+                let old = attrs.remove(pos);
+                if old != attr && !overridden.iter().any(|n| n == attr.name()) {
+                    overridden.push(attr.name().to_string());
+                }
             }
+            attrs.push(attr);
         }
 
         Self {
             title,
             attrs,
             base: None,
+            overridden,
         }
     }
 
     /// Extracts `asciidoctor` options that matches to `-a attr=value`
     pub fn from_cmd_opts(opts: &CmdOptions, acx: &AdocRunContext) -> Self {
-        let attr_opts = match opts.iter().find(|(opt_name, _attr_opts)| opt_name == "-a") {
-            Some((_opt_name, opts)) => opts,
-            None => {
-                return Self {
-                    title: None,
-                    attrs: vec![],
-                    base: None,
+        let attrs = opts
+            .iter()
+            .filter(|(opt_name, _attr_opts)| opt_name == "-a")
+            .flat_map(|(_opt_name, attr_opts)| attr_opts.iter())
+            .map(|opt| match AdocAttr::from_cmd_opt(opt) {
+                AdocAttr::Allow(name, value) => {
+                    AdocAttr::Allow(name, acx.replace_placeholder_strings(&value))
                 }
-            }
-        };
+                deny => deny,
+            })
+            .collect();
 
-        let mut attrs = Vec::with_capacity(10);
-
-        for opt in attr_opts.iter() {
-            let eq_pos = opt
-                .bytes()
-                .enumerate()
-                .find(|(_i, c)| *c == b'=')
-                .map(|(i, _c)| i)
-                .unwrap_or(0);
-
-            // attr | !attr
-            if eq_pos == 0 {
-                attrs.push(AdocAttr::from_name(opt));
-                continue;
-            }
-
-            // name=value | name@=value | name=value@
-            // we'll just ignore `@` symbols; different from the original Asciidoctor, attributes
-            // are always overridable by documents
-            let mut name = &opt[0..eq_pos];
-            if name.ends_with('@') {
-                name = &name[0..name.len() - 1];
-            }
-
-            let mut value = &opt[eq_pos + 1..];
-            if value.ends_with('@') {
-                value = &value[0..value.len() - 1];
-            }
-
-            let value = acx.replace_placeholder_strings(value);
-            attrs.push(AdocAttr::allow(name, &value));
+        Self {
+            title: None,
+            attrs,
+            base: None,
+            overridden: Vec::new(),
         }
+    }
+}
+
+impl AdocMetadata {
+    /// Metadata of `default_attributes` in `book.ron` that are not specified with `-a` options
+    pub fn from_default_attrs(acx: &AdocRunContext) -> Self {
+        let attrs = acx
+            .default_attrs_unspecified()
+            .map(|(name, value)| AdocAttr::allow(name, acx.replace_placeholder_strings(value)))
+            .collect();
 
         Self {
             title: None,
             attrs,
             base: None,
+            overridden: Vec::new(),
         }
     }
 }
 
+/// If the source file has the `draft` attribute in its header. Plain text files are never drafts
+pub fn is_draft_file(src_file: &Path) -> bool {
+    if crate::book::is_text_file(src_file) {
+        return false;
+    }
+
+    match fs::read_to_string(src_file) {
+        Ok(text) => AdocMetadata::extract_header(&text).is_draft(),
+        Err(_err) => false,
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::{AdocAttr, AdocMetadata, AdocRunContext};
+    use std::{collections::BTreeMap, fs, path::Path, process::Command};
+
+    use super::{
+        expand_placeholders, is_empty_output, merge_attributes, parse_diagnostics,
+        strip_unc_prefix, validate_doctype, validate_failure_level, AdocAttr, AdocError,
+        AdocMetadata, AdocRunContext,
+    };
+    use crate::{
+        book::{init, BookStructure},
+        build::convert::hbs::HbsContext,
+    };
 
     const ARTICLE: &str = r###"
 // ^ blank line
@@ -462,13 +1419,98 @@ mod test {
 First paragraph!
 "###;
 
+    #[test]
+    fn adoc_cmd() {
+        if which::which("sh").is_err() {
+            return;
+        }
+
+        let mut acx = AdocRunContext {
+            src_dir: ".".to_string(),
+            dst_dir: "site".to_string(),
+            opts: vec![],
+            base_url: "".to_string(),
+            asset_url: "".to_string(),
+            direct_output: false,
+            adoc_cmd: "sh".to_string(),
+            adoc_args_prefix: vec!["-c".to_string()],
+            backend: None,
+            doctype: None,
+            failure_level: None,
+            strict: false,
+            default_attrs: BTreeMap::new(),
+            batch: None,
+        };
+
+        let mut cmd = acx.command().unwrap();
+        let output = cmd.arg("echo $0").arg("prefixed").output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "prefixed");
+
+        acx.adoc_cmd = "adbook-no-such-asciidoctor".to_string();
+        assert!(matches!(acx.command(), Err(AdocError::NotFound(cmd)) if cmd == acx.adoc_cmd));
+    }
+
+    #[test]
+    fn duplicate_attrs() {
+        let meta = AdocMetadata::extract_header(
+            "= Title\n:revdate: Oct 23, 2020\n:author: someone\n:revdate: Oct 24, 2020\n:author: someone\n",
+        );
+
+        // the last one wins
+        assert_eq!(
+            meta.find_attr("revdate"),
+            Some(&AdocAttr::allow("revdate", "Oct 24, 2020"))
+        );
+        assert_eq!(meta.attrs.len(), 2);
+
+        // same values are not reported
+        assert_eq!(meta.overridden_attrs(), &["revdate".to_string()]);
+    }
+
+    #[test]
+    fn own_attr_if_missing() {
+        let mut meta = AdocMetadata::extract_header("= Title\n:hbs: own.hbs\n");
+        meta.set_own_attr_if_missing(AdocAttr::allow("hbs", "dir.hbs"));
+        assert_eq!(meta.find_own_attr("hbs").unwrap().value(), Some("own.hbs"));
+
+        let mut meta = AdocMetadata::extract_header("= Title\n");
+        meta.set_own_attr_if_missing(AdocAttr::allow("hbs", "dir.hbs"));
+        assert_eq!(meta.find_own_attr("hbs").unwrap().value(), Some("dir.hbs"));
+    }
+
+    #[test]
+    fn prefixed_attrs() {
+        let mut meta = AdocMetadata::extract_header(
+            "= Title\n:hbs-banner: warning\n:!hbs-hidden:\n:other: x\n",
+        );
+        meta.derive(AdocMetadata::extract_header(
+            ":hbs-banner: ignored\n:hbs-hidden: ignored\n:hbs-theme: dark\n",
+        ));
+
+        let attrs = meta.attrs_with_prefix("hbs-");
+        assert_eq!(attrs.len(), 2, "{:?}", attrs);
+        assert_eq!(attrs["banner"], "warning");
+        assert_eq!(attrs["theme"], "dark");
+    }
+
     #[test]
     fn simple_metadata() {
         // dummy
         let acx = AdocRunContext {
             src_dir: ".".to_string(),
+            dst_dir: "site".to_string(),
             opts: vec![],
             base_url: "".to_string(),
+            asset_url: "".to_string(),
+            direct_output: false,
+            adoc_cmd: "asciidoctor".to_string(),
+            adoc_args_prefix: vec![],
+            backend: None,
+            doctype: None,
+            failure_level: None,
+            strict: false,
+            default_attrs: BTreeMap::new(),
+            batch: None,
         };
 
         let metadata = AdocMetadata::extract(ARTICLE, &acx);
@@ -483,6 +1525,7 @@ First paragraph!
                     AdocAttr::deny("sectnums"),
                 ],
                 base: None,
+                overridden: vec![],
             }
         );
 
@@ -504,8 +1547,19 @@ First paragraph!
         // dummy
         let acx = AdocRunContext {
             src_dir: ".".to_string(),
+            dst_dir: "site".to_string(),
             opts: cmd_opts,
             base_url: "".to_string(),
+            asset_url: "".to_string(),
+            direct_output: false,
+            adoc_cmd: "asciidoctor".to_string(),
+            adoc_args_prefix: vec![],
+            backend: None,
+            doctype: None,
+            failure_level: None,
+            strict: false,
+            default_attrs: BTreeMap::new(),
+            batch: None,
         };
 
         let deriving = AdocMetadata::extract_with_base(ARTICLE, &acx);
@@ -520,4 +1574,536 @@ First paragraph!
             Some(&AdocAttr::allow("email", mail))
         );
     }
+
+    #[test]
+    fn work_dir_is_parent_of_source_file() {
+        let dir = std::env::temp_dir().join("adbook-test-work-dir");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        init::gen_init_files(&dir).unwrap();
+
+        let book = BookStructure::from_dir(&dir).unwrap();
+        let src_dir = book.src_dir_path();
+        let guide_dir = src_dir.join("guide");
+        fs::create_dir_all(&guide_dir).unwrap();
+
+        let article = guide_dir.join("article.adoc");
+        fs::write(&article, "= Article\n\ninclude::partial.adoc[]\n").unwrap();
+        fs::write(guide_dir.join("partial.adoc"), "Same-directory include\n").unwrap();
+
+        let acx = AdocRunContext::from_book(&book).unwrap();
+
+        let mut cmd = Command::new("asciidoctor");
+        acx.apply_options(&mut cmd, &article);
+
+        assert_eq!(cmd.get_current_dir(), Some(guide_dir.as_path()));
+
+        // while `-B` stays at the source directory
+        let args = cmd.get_args().collect::<Vec<_>>();
+        assert_eq!(args[0], "-B");
+        assert_eq!(args[1], src_dir.as_os_str());
+
+        // requires `asciidoctor`
+        if which::which("asciidoctor").is_ok() {
+            let (hcx, _errors) = HbsContext::from_book(&book);
+            let mut buf = String::new();
+            crate::build::convert::convert_adoc_buf(&mut buf, &article, &acx, &hcx, &book).unwrap();
+            assert!(buf.contains("Same-directory include"), "{}", buf);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn stdin_include() {
+        let base_dir = std::env::temp_dir().join("adbook-test-stdin-include");
+        fs::create_dir_all(&base_dir).unwrap();
+        fs::write(base_dir.join("partial.adoc"), "Included from stdin\n").unwrap();
+
+        let acx = AdocRunContext {
+            src_dir: "src".to_string(),
+            dst_dir: "site".to_string(),
+            opts: vec![],
+            base_url: "".to_string(),
+            asset_url: "".to_string(),
+            direct_output: false,
+            adoc_cmd: "asciidoctor".to_string(),
+            adoc_args_prefix: vec![],
+            backend: None,
+            doctype: None,
+            failure_level: None,
+            strict: false,
+            default_attrs: BTreeMap::new(),
+            batch: None,
+        };
+
+        let mut cmd = Command::new("asciidoctor");
+        acx.apply_options_stdin(&mut cmd, &base_dir);
+
+        // `include::` resolves from the base directory
+        assert_eq!(cmd.get_current_dir(), Some(base_dir.as_path()));
+        let args = cmd.get_args().collect::<Vec<_>>();
+        assert_eq!(args[0], "-B");
+        assert_eq!(args[1], base_dir.as_os_str());
+
+        // requires `asciidoctor`
+        if which::which("asciidoctor").is_ok() {
+            let mut buf = String::new();
+            let text = "= Article\n\ninclude::partial.adoc[]\n";
+            super::run_asciidoctor_str_buf(&mut buf, text, &base_dir, &acx).unwrap();
+            assert!(buf.contains("Included from stdin"));
+        }
+
+        fs::remove_dir_all(&base_dir).unwrap();
+    }
+
+    #[test]
+    fn unresolved_includes() {
+        // `asciidoctor` output for `include::missing.adoc[]` and `include::other.adoc[lines=1]`
+        let output = r#"<div class="paragraph">
+<p>Unresolved directive in article.adoc - include::missing.adoc[]</p>
+</div>
+<div class="paragraph">
+<p>Unresolved directive in article.adoc - include::sub/other.adoc[lines=1]</p>
+</div>"#;
+
+        assert_eq!(
+            super::find_unresolved_includes(output),
+            vec!["missing.adoc", "sub/other.adoc"]
+        );
+
+        let mut acx = AdocRunContext {
+            src_dir: "src".to_string(),
+            dst_dir: "site".to_string(),
+            opts: vec![],
+            base_url: "".to_string(),
+            asset_url: "".to_string(),
+            direct_output: false,
+            adoc_cmd: "asciidoctor".to_string(),
+            adoc_args_prefix: vec![],
+            backend: None,
+            doctype: None,
+            failure_level: None,
+            strict: false,
+            default_attrs: BTreeMap::new(),
+            batch: None,
+        };
+
+        let src_file = Path::new("src/article.adoc");
+        let warnings = super::check_unresolved_includes(src_file, output, &acx).unwrap();
+        assert_eq!(warnings.len(), 2);
+        assert!(matches!(
+            &warnings[0],
+            super::AdocError::UnresolvedInclude(p, target) if p == src_file && target == "missing.adoc"
+        ));
+
+        acx.set_strict(true);
+        assert!(super::check_unresolved_includes(src_file, output, &acx).is_err());
+
+        assert!(super::find_unresolved_includes("<p>include::a.adoc[]</p>").is_empty());
+    }
+
+    #[test]
+    fn diagnostics() {
+        let dir = std::env::temp_dir().join("adbook-test-diagnostics");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+
+        // malformed include directive on line 3
+        let src_file = dir.join("article.adoc");
+        fs::write(&src_file, "= Title\n\ninclude::missing.adoc[lines=a..b]\n").unwrap();
+
+        let stderr = format!(
+            "asciidoctor: ERROR: article.adoc: line 3: include file not found: {}\n\
+             asciidoctor: WARNING: dropping line containing reference to missing attribute: x\n",
+            dir.join("missing.adoc").display()
+        );
+
+        let diagnostics = parse_diagnostics(&stderr, &src_file);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].level, "ERROR");
+        assert_eq!(diagnostics[0].line, 3);
+        assert_eq!(
+            diagnostics[0].source_line.as_deref(),
+            Some("include::missing.adoc[lines=a..b]")
+        );
+
+        // run a fake `asciidoctor` that fails with the diagnostics
+        if which::which("sh").is_ok() {
+            let script = format!("printf '%s' '{}' >&2; exit 1", stderr);
+            let acx = AdocRunContext {
+                src_dir: dir.display().to_string(),
+                dst_dir: "site".to_string(),
+                opts: vec![],
+                base_url: "".to_string(),
+                asset_url: "".to_string(),
+                direct_output: false,
+                adoc_cmd: "sh".to_string(),
+                adoc_args_prefix: vec!["-c".to_string(), script],
+                backend: None,
+                doctype: None,
+                failure_level: None,
+                strict: false,
+                default_attrs: BTreeMap::new(),
+                batch: None,
+            };
+
+            let mut buf = String::new();
+            let err = super::run_asciidoctor_buf(&mut buf, &src_file, &acx).unwrap_err();
+            let msg = format!("{}", err.downcast_ref::<AdocError>().unwrap());
+            assert!(msg.contains("ERROR: article.adoc:3: include file not found"));
+            assert!(msg.contains("    3 | include::missing.adoc[lines=a..b]"));
+            // the full stderr follows
+            assert!(msg.contains("asciidoctor: WARNING: dropping line"));
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn batch_work_dir() {
+        if which::which("sh").is_err() {
+            return;
+        }
+
+        let dir = std::env::temp_dir().join("adbook-test-batch-work-dir");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(dir.join("src/sub")).unwrap();
+        let src_dir = dir.join("src").canonicalize().unwrap();
+        let src_file = src_dir.join("sub/a.adoc");
+        fs::write(&src_file, "= A\n").unwrap();
+
+        // fake `asciidoctor` that outputs the working directory
+        let script = r#"
+R=; D=; F=
+while [ $# -gt 0 ]; do
+    case "$1" in
+        -R) R=$2; shift 2 ;;
+        -D) D=$2; shift 2 ;;
+        -o|-B|-b|-d|-a|-r|--failure-level) shift 2 ;;
+        -*) shift ;;
+        *) F="$F $1"; shift ;;
+    esac
+done
+for f in $F; do
+    if [ -z "$D" ]; then
+        pwd
+    else
+        rel=${f#$R/}
+        mkdir -p "$D/$(dirname "$rel")"
+        pwd > "$D/${rel%.adoc}.html"
+    fi
+done
+"#;
+        let acx = AdocRunContext {
+            src_dir: src_dir.display().to_string(),
+            dst_dir: "site".to_string(),
+            opts: vec![],
+            base_url: "".to_string(),
+            asset_url: "".to_string(),
+            direct_output: false,
+            adoc_cmd: "sh".to_string(),
+            adoc_args_prefix: vec!["-c".to_string(), script.to_string(), "sh".to_string()],
+            backend: None,
+            doctype: None,
+            failure_level: None,
+            strict: false,
+            default_attrs: BTreeMap::new(),
+            batch: None,
+        };
+
+        let output = super::run_asciidoctor(&src_file, &acx).unwrap();
+        assert!(output.status.success());
+        let per_file = String::from_utf8(output.stdout).unwrap();
+
+        let out_dir = dir.join("batch");
+        let outputs =
+            super::run_asciidoctor_batch(std::slice::from_ref(&src_file), &out_dir, &acx).unwrap();
+        assert_eq!(outputs.len(), 1);
+        let batch = fs::read_to_string(out_dir.join("sub/a.html")).unwrap();
+
+        assert_eq!(per_file.trim(), src_dir.join("sub").display().to_string());
+        assert_eq!(batch, per_file);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn default_attributes() {
+        let acx = AdocRunContext {
+            src_dir: "src".to_string(),
+            dst_dir: "site".to_string(),
+            opts: vec![("-a".to_string(), vec!["icons=image".to_string()])],
+            base_url: "".to_string(),
+            asset_url: "".to_string(),
+            direct_output: false,
+            adoc_cmd: "asciidoctor".to_string(),
+            adoc_args_prefix: vec![],
+            backend: None,
+            doctype: None,
+            failure_level: None,
+            strict: false,
+            default_attrs: [("icons", "font"), ("sectanchors", ""), ("experimental", "")]
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            batch: None,
+        };
+
+        // document > `asciidoctor` options > defaults
+        let meta = AdocMetadata::extract_with_base("= Title\n:!experimental:\n", &acx);
+        assert_eq!(
+            meta.find_attr("icons"),
+            Some(&AdocAttr::allow("icons", "image"))
+        );
+        assert_eq!(
+            meta.find_attr("sectanchors"),
+            Some(&AdocAttr::allow("sectanchors", ""))
+        );
+        assert_eq!(
+            meta.find_attr("experimental"),
+            Some(&AdocAttr::deny("experimental"))
+        );
+
+        // defaults are soft set unless they're specified in the options
+        let mut cmd = Command::new("asciidoctor");
+        acx.apply_options(&mut cmd, Path::new("src/a.adoc"));
+        let args = cmd
+            .get_args()
+            .map(|arg| arg.to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert!(args.contains(&"sectanchors=@"));
+        assert!(args.contains(&"icons=image"));
+        assert!(!args.contains(&"icons=font@"));
+    }
+
+    #[test]
+    fn cmd_opt_attrs() {
+        let cases = [
+            ("sectnums", AdocAttr::allow("sectnums", "")),
+            ("!sectnums", AdocAttr::deny("sectnums")),
+            ("sectnums!", AdocAttr::deny("sectnums")),
+            (
+                "description=Hello=World",
+                AdocAttr::allow("description", "Hello=World"),
+            ),
+            ("title=Hello World", AdocAttr::allow("title", "Hello World")),
+            (
+                "title@=Hello World",
+                AdocAttr::allow("title", "Hello World"),
+            ),
+            ("email=someone@", AdocAttr::allow("email", "someone")),
+            ("email@=someone@", AdocAttr::allow("email", "someone@")),
+            ("email=someone@@", AdocAttr::allow("email", "someone@")),
+            ("email=a@b.c", AdocAttr::allow("email", "a@b.c")),
+        ];
+
+        for (opt, attr) in cases.iter() {
+            assert_eq!(&AdocAttr::from_cmd_opt(opt), attr, "{}", opt);
+        }
+    }
+
+    #[test]
+    fn empty_output() {
+        let comment_only = "// nothing but a comment\n\n////\ncomment block\n////\n";
+
+        // embedded mode output of the comment-only source
+        assert!(is_empty_output(comment_only, "\n"));
+        assert!(is_empty_output(comment_only, ""));
+
+        // empty source is not flagged
+        assert!(!is_empty_output("\n", ""));
+        assert!(!is_empty_output(ARTICLE, "<div class=\"paragraph\"></div>"));
+    }
+
+    #[test]
+    fn doctypes() {
+        assert!(validate_doctype("book").is_ok());
+        assert!(validate_doctype("manpage").is_ok());
+        assert!(validate_doctype("Book").is_err());
+        assert!(validate_doctype("chapter").is_err());
+    }
+
+    #[test]
+    fn embedded_mode() {
+        let mut acx = AdocRunContext {
+            src_dir: "src".to_string(),
+            dst_dir: "site".to_string(),
+            opts: vec![
+                ("-a".to_string(), vec!["linkcss".to_string()]),
+                ("--safe".to_string(), vec![]),
+            ],
+            base_url: "".to_string(),
+            asset_url: "".to_string(),
+            direct_output: false,
+            adoc_cmd: "asciidoctor".to_string(),
+            adoc_args_prefix: vec![],
+            backend: None,
+            doctype: None,
+            failure_level: None,
+            strict: false,
+            default_attrs: BTreeMap::new(),
+            batch: None,
+        };
+
+        acx.set_embedded_mode(true);
+        assert!(acx.opts.iter().any(|(name, _)| name == "--embedded"));
+
+        acx.set_embedded_mode(false);
+        let names = acx
+            .opts
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["-a", "--safe"]);
+    }
+
+    #[test]
+    fn failure_levels() {
+        assert_eq!(validate_failure_level("WARN").unwrap(), "WARN");
+        assert_eq!(validate_failure_level("error").unwrap(), "ERROR");
+        assert!(validate_failure_level("DEBUG").is_err());
+    }
+
+    #[test]
+    fn attributes_map() {
+        let mut opts = vec![(
+            "-a".to_string(),
+            vec!["sectnums".to_string(), "icons@=font".to_string()],
+        )];
+
+        let attributes = vec![("icons", "image"), ("imagesdir", "{base_url}/img")]
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<BTreeMap<_, _>>();
+
+        merge_attributes(&mut opts, &attributes);
+
+        // `adoc_opts` take precedence
+        assert_eq!(
+            opts,
+            vec![(
+                "-a".to_string(),
+                vec![
+                    "sectnums".to_string(),
+                    "icons@=font".to_string(),
+                    "imagesdir={base_url}/img".to_string(),
+                ]
+            )]
+        );
+    }
+
+    #[test]
+    fn profile_attributes() {
+        let dir = std::env::temp_dir().join("adbook-test-profile");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        init::gen_init_files(&dir).unwrap();
+
+        let mut book = BookStructure::from_dir(&dir).unwrap();
+        book.book_ron
+            .adoc_opts
+            .push(("-a".to_string(), vec!["env=base".to_string()]));
+        for profile in &["dev", "prod"] {
+            let attrs = vec![("env".to_string(), profile.to_string())];
+            book.book_ron
+                .profile_attributes
+                .insert(profile.to_string(), attrs.into_iter().collect());
+        }
+        assert!(book.set_profile("staging").is_err());
+
+        let article = book.src_dir_path().join("article.adoc");
+
+        // the same source gets the attribute of the active profile
+        for profile in &["dev", "prod"] {
+            book.set_profile(profile).unwrap();
+            let acx = AdocRunContext::from_book(&book).unwrap();
+
+            let mut cmd = Command::new("asciidoctor");
+            acx.apply_options(&mut cmd, &article);
+
+            let args = cmd
+                .get_args()
+                .map(|arg| arg.to_str().unwrap())
+                .collect::<Vec<_>>();
+            let env = format!("env={}", profile);
+            assert!(args.contains(&env.as_str()), "{:?}", args);
+            assert!(!args.contains(&"env=base"), "{:?}", args);
+
+            let meta = AdocMetadata::extract_with_base("= Title", &acx);
+            assert_eq!(
+                meta.find_attr("env"),
+                Some(&AdocAttr::allow("env", *profile))
+            );
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn env_vars() {
+        assert_eq!(
+            expand_placeholders(
+                "{base_url}/${HOME}/${base_url}",
+                &[("base_url", "/b")],
+                |name| { (name == "base_url").then(|| "env".to_string()) }
+            ),
+            ("/b/${HOME}/env".to_string(), vec!["HOME".to_string()])
+        );
+
+        let dir = std::env::temp_dir().join("adbook-test-env-vars");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        init::gen_init_files(&dir).unwrap();
+
+        std::env::set_var("ADBOOK_TEST_BUILD_NUMBER", "42");
+        std::env::remove_var("ADBOOK_TEST_UNDEFINED");
+
+        let mut book = BookStructure::from_dir(&dir).unwrap();
+        book.book_ron.attributes.insert(
+            "build".to_string(),
+            "#${ADBOOK_TEST_BUILD_NUMBER}".to_string(),
+        );
+
+        let acx = AdocRunContext::from_book(&book).unwrap();
+        assert!(acx.undefined_env_vars().is_empty());
+
+        let mut cmd = Command::new("asciidoctor");
+        acx.apply_options(&mut cmd, &book.src_dir_path().join("article.adoc"));
+        let args = cmd
+            .get_args()
+            .map(|arg| arg.to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert!(args.contains(&"build=#42"), "{:?}", args);
+
+        // undefined variables are errors
+        book.book_ron.attributes.insert(
+            "undefined".to_string(),
+            "${ADBOOK_TEST_UNDEFINED}".to_string(),
+        );
+        let acx = AdocRunContext::from_book(&book).unwrap();
+        assert_eq!(acx.undefined_env_vars().len(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unc_prefix() {
+        assert_eq!(strip_unc_prefix(r#"\\?\C:\foo\bar"#), "C:/foo/bar");
+        assert_eq!(
+            strip_unc_prefix(r#"\\?\UNC\server\share\a.adoc"#),
+            "//server/share/a.adoc"
+        );
+        assert_eq!(strip_unc_prefix("/home/user/book"), "/home/user/book");
+    }
 }
@@ -1,5 +1,18 @@
 /*!
 Generates `all.adoc`
+
+# ID namespacing
+
+Articles are merged into one document, so IDs generated from section titles would collide across
+articles. Each `include::` is preceded by `:idprefix: _<slug>_`, where the slug is made from the
+relative path of the article (`sub/article.adoc` -> `sub__article.adoc`), and the prefix is reset
+afterwards. Footnotes are numbered through the whole document.
+
+Different paths always make different slugs: `/` is written as `__` and other characters except
+alphanumerics, `-` and `.` are escaped as `_<hex>_` (`sub_article.adoc` -> `sub_5f_article.adoc`).
+
+Explicit IDs (`[[id]]`, `[#id]`) and named footnotes (`footnote:name[]`) are not namespaced and
+have to be unique across the book.
 */
 
 use std::{fmt::Write, path::Path};
@@ -12,8 +25,6 @@ use crate::book::{
 type Result<T> = std::result::Result<T, std::fmt::Error>;
 
 /// Generates `all.adoc`
-///
-/// TODO: footnote per article?
 pub fn gen_all(book: &BookStructure) -> Result<String> {
     let mut out = String::new();
 
@@ -21,32 +32,142 @@ pub fn gen_all(book: &BookStructure) -> Result<String> {
     writeln!(out, ":stylesheet: all.css")?;
     writeln!(out, "")?;
 
-    self::visit(&mut out, &book.index, 1)?;
+    self::visit(&mut out, &book.index, book, 1)?;
 
     Ok(out)
 }
 
-fn visit(out: &mut String, index: &Index, depth: usize) -> Result<()> {
-    self::write_file(out, &index.summary, depth)?;
+fn visit(out: &mut String, index: &Index, book: &BookStructure, depth: usize) -> Result<()> {
+    let src_dir = book.src_dir_path();
+    self::write_file(out, &index.summary, &src_dir, depth)?;
 
     let depth = depth + 1;
 
     for item in &index.items {
         match item {
-            IndexItem::File(_name, abs_path) => {
-                self::write_file(out, abs_path, depth)?;
+            IndexItem::File(_name, abs_path) if !book.is_excluded(abs_path) => {
+                self::write_file(out, abs_path, &src_dir, depth)?;
             }
-            IndexItem::Dir(index) => {
-                self::visit(out, index, depth)?;
+            IndexItem::File(_name, _abs_path) => {}
+            IndexItem::Dir(index) if !book.is_excluded(&index.summary) => {
+                self::visit(out, index, book, depth)?;
             }
+            IndexItem::Dir(_index) => {}
+            IndexItem::Link(_name, _url) => {}
         }
     }
 
     Ok(())
 }
 
-fn write_file(out: &mut String, file: &Path, depth: usize) -> Result<()> {
-    writeln!(out, "include::{}[leveloffset={}]", file.display(), depth)
+fn write_file(out: &mut String, file: &Path, src_dir: &Path, depth: usize) -> Result<()> {
+    if crate::book::is_text_file(file) {
+        // plain text as a literal block
+        writeln!(out, "....")?;
+        writeln!(out, "include::{}[]", file.display())?;
+        return writeln!(out, "....");
+    }
+
+    // namespace IDs generated in the article
+    writeln!(out, ":idprefix: _{}_", self::slug(file, src_dir))?;
+    writeln!(out, "include::{}[leveloffset={}]", file.display(), depth)?;
+    writeln!(out, ":idprefix: _")?;
+    writeln!(out)
+}
+
+/// `sub/article.adoc` -> `sub__article.adoc`
+fn slug(file: &Path, src_dir: &Path) -> String {
+    let rel_path = file.strip_prefix(src_dir).unwrap_or(file);
+
+    let mut slug = String::new();
+    for (i, c) in rel_path.components().enumerate() {
+        if i > 0 {
+            slug.push_str("__");
+        }
+
+        for c in c.as_os_str().to_string_lossy().chars() {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                slug.push(c);
+            } else {
+                // hex digits don't contain `_`, so escapes can't be confused with the separator
+                slug.push_str(&format!("_{:x}_", c as u32));
+            }
+        }
+    }
+
+    slug
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, fs};
+
+    use crate::book::{init, BookStructure};
+
+    #[test]
+    fn unique_id_prefixes() {
+        let dir = std::env::temp_dir().join("adbook-test-all-adoc");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        init::gen_init_files(&dir).unwrap();
+
+        // articles with the same section title and a footnote each, some of them with names that
+        // would make the same slug if separators and symbols were not escaped
+        let src_dir = dir.join("src");
+        fs::create_dir_all(src_dir.join("sub")).unwrap();
+        let articles = [
+            "article.adoc",
+            "sub/article.adoc",
+            "sub_article.adoc",
+            "a-b.adoc",
+            "a_b.adoc",
+        ];
+        for file in &articles {
+            fs::write(
+                src_dir.join(file),
+                "= Article\n\n== Notes\n\nText.footnote:[A footnote]\n",
+            )
+            .unwrap();
+        }
+        fs::write(src_dir.join("sub/index.adoc"), "= Sub\n").unwrap();
+        fs::write(
+            src_dir.join("sub/index.ron"),
+            r#"summary: ("", "index.adoc"), items: [File("", "article.adoc")]"#,
+        )
+        .unwrap();
+        fs::write(
+            src_dir.join("index.ron"),
+            r#"summary: ("", "index.adoc"), items: [
+    File("", "article.adoc"),
+    Dir("sub"),
+    File("", "sub_article.adoc"),
+    File("", "a-b.adoc"),
+    File("", "a_b.adoc"),
+]"#,
+        )
+        .unwrap();
+
+        let book = BookStructure::from_dir(&dir).unwrap();
+        let all = super::gen_all(&book).unwrap();
+
+        let prefixes = all
+            .lines()
+            .filter(|ln| ln.starts_with(":idprefix: _") && *ln != ":idprefix: _")
+            .collect::<Vec<_>>();
+
+        // two `index.adoc`s and the articles
+        assert_eq!(prefixes.len(), 7);
+        assert_eq!(prefixes.iter().collect::<HashSet<_>>().len(), 7);
+        assert!(prefixes.contains(&":idprefix: _article.adoc_"));
+        assert!(prefixes.contains(&":idprefix: _sub__article.adoc_"));
+        assert!(prefixes.contains(&":idprefix: _sub_5f_article.adoc_"));
+        assert!(prefixes.contains(&":idprefix: _a-b.adoc_"));
+        assert!(prefixes.contains(&":idprefix: _a_5f_b.adoc_"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
 
 // include::snowrl/summary.adoc[leveloffset=1]
@@ -0,0 +1,166 @@
+/*!
+Dates of the last git commits touching source files (`revdate_from_git` in `book.ron`)
+
+The dates of all the files are read with a single `git log --name-only` on the first lookup.
+*/
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::OnceLock,
+};
+
+/// Last commit dates read from `git log`, shared during a build
+#[derive(Debug)]
+pub struct GitRevdates {
+    /// Root directory of the book, where `git` runs
+    root: PathBuf,
+    /// Relative path from the root → `yyyy-mm-dd`. `None` if `git` is not available
+    dates: OnceLock<Option<HashMap<PathBuf, String>>>,
+}
+
+impl GitRevdates {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            dates: OnceLock::new(),
+        }
+    }
+
+    /// `yyyy-mm-dd` of the last commit touching the file. `None` if the file is not committed or
+    /// if `git` is not available (which is warned once)
+    pub fn revdate(&self, file: &Path) -> Option<String> {
+        let dates = self
+            .dates
+            .get_or_init(|| match self::last_commit_dates(&self.root) {
+                Ok(dates) => Some(dates),
+                Err(err) => {
+                    log::warn!("`revdate_from_git` is set but {}", err);
+                    None
+                }
+            });
+
+        let rel_path = file.strip_prefix(&self.root).unwrap_or(file);
+        dates.as_ref()?.get(rel_path).cloned()
+    }
+}
+
+/// Runs `git log --name-only` once and maps files (relative to the root) to the dates of their
+/// last commits
+fn last_commit_dates(root: &Path) -> Result<HashMap<PathBuf, String>, String> {
+    // date lines are marked with `\x01`
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(root)
+        .args([
+            "-c",
+            "core.quotePath=false",
+            "log",
+            "--format=%x01%cs",
+            "--name-only",
+            "--relative",
+        ])
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|err| format!("unable to run `git`: {}", err))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!(
+            "`git log` failed: {}",
+            stderr.lines().next().unwrap_or_default()
+        ));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut dates = HashMap::new();
+    let mut date = "";
+
+    // newer commits come first
+    for line in stdout.lines() {
+        if let Some(d) = line.strip_prefix('\x01') {
+            date = d.trim();
+        } else if !line.is_empty() {
+            dates
+                .entry(PathBuf::from(line))
+                .or_insert_with(|| date.to_string());
+        }
+    }
+
+    Ok(dates)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::Path, process::Command};
+
+    use super::GitRevdates;
+
+    fn git(dir: &Path, args: &[&str]) {
+        self::git_at(dir, args, "2020-01-02T03:04:05+00:00");
+    }
+
+    fn git_at(dir: &Path, args: &[&str], date: &str) {
+        let status = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args([
+                "-c",
+                "user.name=adbook",
+                "-c",
+                "user.email=adbook@example.com",
+            ])
+            .args(args)
+            .env("GIT_COMMITTER_DATE", date)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {:?}", args);
+    }
+
+    #[test]
+    fn revdates() {
+        if which::which("git").is_err() {
+            return;
+        }
+
+        let dir = std::env::temp_dir().join("adbook-test-git-revdate");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+
+        fs::write(dir.join("a.adoc"), "= A\n").unwrap();
+        fs::write(dir.join("b.adoc"), "= B\n").unwrap();
+
+        // not a git repository
+        let revdates = GitRevdates::new(&dir);
+        assert_eq!(revdates.revdate(&dir.join("a.adoc")), None);
+
+        git(&dir, &["init", "-q"]);
+        git(&dir, &["add", "a.adoc"]);
+        git(&dir, &["commit", "-q", "-m", "a"]);
+
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("sub/c.adoc"), "= C\n").unwrap();
+        git(&dir, &["add", "sub/c.adoc"]);
+        git_at(
+            &dir,
+            &["commit", "-q", "-m", "c"],
+            "2021-03-04T05:06:07+00:00",
+        );
+
+        let revdates = GitRevdates::new(&dir);
+        let a = dir.join("a.adoc");
+        assert_eq!(revdates.revdate(&a).as_deref(), Some("2020-01-02"));
+        assert_eq!(
+            revdates.revdate(&dir.join("sub/c.adoc")).as_deref(),
+            Some("2021-03-04")
+        );
+        // not committed
+        assert_eq!(revdates.revdate(&dir.join("b.adoc")), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
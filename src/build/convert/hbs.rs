@@ -2,26 +2,225 @@
 Handlebars application
 
 HBS templates are supplied [`HbsInput`].
+
+# Navigation links
+
+Navigation fields of [`HbsInput`] (such as `home`) are [`NavLink`]s:
+
+```hbs
+{{#if home}}<a href="{{home.url}}">{{home.name}}</a>{{/if}}
+```
+
+`external` is true for `Link` items in `index.ron`. The sidebar (`sidebar_items`) is made of
+richer [`SidebarItem`]s with nesting and the active state.
+
+`prev` and `next` are the neighbors of the article in the depth-first order of the sidebar (the
+build order), skipping external links. They're `None` for the first and the last article:
+
+```hbs
+{{#if prev}}<a href="{{prev.url}}">← {{prev.name}}</a>{{/if}}
+{{#if next}}<a href="{{next.url}}">{{next.name}} →</a>{{/if}}
+```
+
+`breadcrumbs` is the chain from the root summary down to the article, with directory summaries in
+between. The article itself is the last crumb. It's empty for files not in the sidebar (such as
+`converts` in `book.ron`):
+
+```hbs
+{{#each breadcrumbs}}{{#unless @first}} / {{/unless}}<a href="{{url}}">{{name}}</a>{{/each}}
+```
 */
 
 use std::{
+    collections::{BTreeMap, HashMap},
     fs,
-    io::{BufRead, BufReader},
-    path::Path,
+    path::{Path, PathBuf},
+    sync::Arc,
 };
 
-use anyhow::{anyhow, ensure, Context, Error, Result};
+use anyhow::{bail, ensure, Context, Error, Result};
 use handlebars::Handlebars;
 use serde::Serialize;
 
 use crate::{
-    book::{index::IndexItem, BookStructure},
-    build::convert::adoc::AdocMetadata,
+    book::{
+        config::{BookRon, MathRenderer, OrderBy},
+        index::{self, IndexItem},
+        BookStructure,
+    },
+    build::convert::{
+        adoc::{self, AdocMetadata},
+        git::GitRevdates,
+    },
 };
 
+/// HTML loading KaTeX with the auto-render extension
+const KATEX_HEAD: &str = r#"<link rel="stylesheet" href="https://cdn.jsdelivr.net/npm/katex@0.15.1/dist/katex.min.css">
+<script defer src="https://cdn.jsdelivr.net/npm/katex@0.15.1/dist/katex.min.js"></script>
+<script defer src="https://cdn.jsdelivr.net/npm/katex@0.15.1/dist/contrib/auto-render.min.js"
+    onload="renderMathInElement(document.body, {
+        delimiters: [
+            {left: '\\[', right: '\\]', display: true},
+            {left: '\\(', right: '\\)', display: false},
+        ],
+        ignoredClasses: ['nostem', 'nolatexmath'],
+    });"></script>"#;
+
+/// HTML loading MathJax configured for Asciidoctor output
+const MATHJAX_HEAD: &str = r#"<script type="text/x-mathjax-config">
+    MathJax.Hub.Config({
+        messageStyle: "none",
+        tex2jax: {
+            inlineMath: [["\\(", "\\)"]],
+            displayMath: [["\\[", "\\]"]],
+            ignoreClass: "nostem|nolatexmath"
+        },
+        asciimath2jax: {
+            delimiters: [["\\$", "\\$"]],
+            ignoreClass: "nostem|noasciimath"
+        },
+        TeX: { equationNumbers: { autoNumber: "none" } }
+    })
+    MathJax.Hub.Register.StartupHook("AsciiMath Jax Ready", function () {
+        MathJax.InputJax.AsciiMath.postfilterHooks.Add(function (data, node) {
+            if ((node = data.script.parentNode) && (node = node.parentNode) && node.classList.contains('stemblock')) {
+                data.math.root.display = "block"
+            }
+            return data
+        })
+    })
+</script>
+<script src="https://cdnjs.cloudflare.com/ajax/libs/mathjax/2.7.5/MathJax.js?config=TeX-MML-AM_HTMLorMML"></script>"#;
+
+/// HTML to load the math renderer in `<head>`
+fn math_head(math: MathRenderer) -> &'static str {
+    match math {
+        MathRenderer::KaTeX => KATEX_HEAD,
+        MathRenderer::MathJax => MATHJAX_HEAD,
+    }
+}
+
+/// Title of a plain text file: the file stem
+pub fn text_title(file: &Path) -> String {
+    file.file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "<untitled>".to_string())
+}
+
 // --------------------------------------------------------------------------------
 // Context
 
+/// Name and URL of a page, used for navigation fields in [`HbsInput`]
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct NavLink {
+    pub name: String,
+    pub url: String,
+    /// If the link is to an external resource
+    pub external: bool,
+}
+
+impl NavLink {
+    /// Returns `None` if the item doesn't have URL
+    pub fn from_sidebar_item(item: &SidebarItem) -> Option<Self> {
+        Some(Self {
+            name: item.name.clone(),
+            url: item.url.clone()?,
+            external: item.external,
+        })
+    }
+}
+
+/// Section heading in an article, an element of `page_toc` in [`HbsInput`]
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct TocEntry {
+    /// Section level: 1 for `<h2>`, 2 for `<h3>`, ..
+    pub level: usize,
+    /// `id` of the heading, the anchor to link to (`#id`)
+    pub id: String,
+    /// Plain text of the heading
+    pub text: String,
+}
+
+/// Extracts the section headings with `id`s (`<h2 id="..">` to `<h6 id="..">`) from an HTML
+/// string. The document title (`<h1>`) is skipped. Headings deeper than `max_level` (section
+/// level) are skipped
+pub fn page_toc(html: &str, max_level: Option<usize>) -> Vec<TocEntry> {
+    let mut toc = Vec::new();
+    let mut rest = html;
+
+    while let Some(pos) = rest.find("<h") {
+        rest = &rest[pos + 2..];
+
+        // `<h2 ..>`
+        let level = match rest.as_bytes().first() {
+            Some(c @ b'2'..=b'6') => (c - b'1') as usize,
+            _ => continue,
+        };
+        let tag_end = match rest.find('>') {
+            Some(end) => end,
+            None => break,
+        };
+        let attrs = &rest[1..tag_end];
+        rest = &rest[tag_end + 1..];
+
+        if let Some(max) = max_level {
+            if level > max {
+                continue;
+            }
+        }
+
+        let id = match self::attr_value(attrs, "id") {
+            Some(id) => id,
+            None => continue,
+        };
+
+        let close = format!("</h{}", level + 1);
+        let inner = match rest.find(&close) {
+            Some(end) => &rest[..end],
+            None => break,
+        };
+
+        toc.push(TocEntry {
+            level,
+            id: id.to_string(),
+            text: self::strip_tags(inner),
+        });
+    }
+
+    toc
+}
+
+/// `value` of `name="value"` in the attributes of a tag
+fn attr_value<'a>(attrs: &'a str, name: &str) -> Option<&'a str> {
+    let pattern = format!("{}=\"", name);
+    let start = attrs
+        .match_indices(&pattern)
+        .map(|(pos, _)| pos)
+        // not a suffix of another attribute name (`data-id`)
+        .find(|pos| *pos == 0 || attrs.as_bytes()[pos - 1].is_ascii_whitespace())?
+        + pattern.len();
+    let end = start + attrs[start..].find('"')?;
+    Some(&attrs[start..end])
+}
+
+/// Plain text of an HTML fragment: tags are removed and character references are decoded
+fn strip_tags(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(start) = rest.find('<') {
+        text.push_str(&rest[..start]);
+        rest = match rest[start..].find('>') {
+            Some(end) => &rest[start + end + 1..],
+            None => "",
+        };
+    }
+    text.push_str(rest);
+
+    let text = crate::build::search::decode_entities(&text);
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
 #[derive(Serialize, Debug, Clone)]
 pub struct SidebarItem {
     pub name: String,
@@ -29,6 +228,13 @@ pub struct SidebarItem {
     pub children: Option<Box<Vec<Self>>>,
     pub active: bool,
     pub depth: usize,
+    /// If the item is a link to an external resource
+    pub external: bool,
+    /// If the item is a draft (`:draft:`). Drafts are listed only with `adbook build --drafts`
+    pub draft: bool,
+    /// If the children are expanded. Items shallower than `fold_level`, the active item and its
+    /// ancestors are open
+    pub open: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -39,53 +245,32 @@ pub struct Sidebar {
 }
 
 impl Sidebar {
-    /// Reads the first line of a file and if it starts with `= ` it is the title
-    fn get_title(title: &str, file: &Path) -> Result<String> {
-        if !title.is_empty() {
-            return Ok(title.to_string());
+    /// If items at the depth are open by default. `None` opens all the items
+    fn is_open_by_default(fold_level: Option<usize>, depth: usize) -> bool {
+        match fold_level {
+            Some(level) => depth < level,
+            None => true,
         }
+    }
 
-        let f = fs::File::open(&file)
-            .with_context(|| anyhow!("Unable to open file {}", file.display()))?;
-        let mut f = BufReader::new(f);
-
-        let mut buf = String::with_capacity(200);
-        f.read_line(&mut buf)
-            .with_context(|| anyhow!("Unable to peek file {}", file.display()))?;
-
-        if buf.starts_with("= ") {
-            Ok(buf[2..].trim().to_string())
-        } else {
-            Ok("<untitled>".to_string())
+    /// The name in `index.ron` or the document title of the file
+    pub(crate) fn get_title(title: &str, file: &Path) -> Result<String> {
+        if !title.is_empty() {
+            return Ok(title.to_string());
         }
-    }
 
-    /// Gets an URL for an article in the sidebar
-    ///
-    /// The `base_url_str` is in for of `/path/to/dir`.
-    pub fn get_url(src_dir: &Path, src_file: &Path, base_url_str: &str) -> Result<String> {
-        let url = src_file
-            .strip_prefix(src_dir)
-            .with_context(|| {
-                anyhow!(
-                    "Unable to strip prefix: `{}` from `{}`",
-                    src_dir.display(),
-                    src_file.display()
-                )
-            })?
-            .with_extension("html");
-
-        let url = format!("{}/{}", base_url_str, url.display());
-        Ok(url)
+        ensure!(file.is_file(), "Unable to open file {}", file.display());
+        Ok(index::peek_title(file).unwrap_or_else(|| "<untitled>".to_string()))
     }
 
     pub fn from_book(book: &BookStructure) -> (Self, Vec<Error>) {
         let mut errors = Vec::with_capacity(20);
 
         let summary_item = {
-            let (name, file) = (&book.index.name, &book.index.summary);
+            // the root item links to the home page if any
+            let file = book.home.as_ref().unwrap_or(&book.index.summary);
 
-            let name = match Self::get_title(name, file) {
+            let name = match Self::get_title(&book.index.name, file) {
                 Ok(name) => name,
                 Err(err) => {
                     errors.push(err);
@@ -93,19 +278,13 @@ impl Sidebar {
                 }
             };
 
-            IndexItem::File(name, book.index.summary.clone())
+            IndexItem::File(name, file.clone())
         };
 
-        let items = std::iter::once(&summary_item).chain(&book.index.items);
-        let items: Vec<SidebarItem> = {
-            Self::collect_sidebar_items(
-                items,
-                &book.src_dir_path(),
-                &book.book_ron.base_url,
-                &mut errors,
-                0,
-            )
-        };
+        let items =
+            std::iter::once(&summary_item).chain(Self::filter_excluded(&book.index.items, book));
+        let items: Vec<SidebarItem> =
+            Self::collect_sidebar_items(items, book, &mut errors, 0, book.index.order_by);
         // log::trace!("items: {:#?}", items);
 
         (
@@ -117,78 +296,259 @@ impl Sidebar {
         )
     }
 
-    /// Highlight the sidebar item with that url
+    /// Top-level sidebar items
+    pub fn items(&self) -> &[SidebarItem] {
+        &self.items
+    }
+
+    /// Links from the root item down to the item with the URL. Empty if the URL is not in the
+    /// sidebar
+    pub fn breadcrumbs(&self, url: &str) -> Vec<NavLink> {
+        fn find_path<'a>(
+            items: &'a [SidebarItem],
+            url: &str,
+            path: &mut Vec<&'a SidebarItem>,
+        ) -> bool {
+            for item in items {
+                path.push(item);
+                if !item.external && item.url.as_deref() == Some(url) {
+                    return true;
+                }
+                if let Some(children) = &item.children {
+                    if find_path(children, url, path) {
+                        return true;
+                    }
+                }
+                path.pop();
+            }
+            false
+        }
+
+        let (root, rest) = match self.items.split_first() {
+            Some(x) => x,
+            None => return vec![],
+        };
+
+        let mut path = vec![root];
+        if root.url.as_deref() != Some(url) && !find_path(rest, url, &mut path) {
+            return vec![];
+        }
+
+        path.into_iter()
+            .filter_map(NavLink::from_sidebar_item)
+            .collect()
+    }
+
+    /// Internal links in the depth-first order (directory summaries come before their children)
+    pub fn flatten(&self) -> Vec<NavLink> {
+        fn visit(items: &[SidebarItem], links: &mut Vec<NavLink>) {
+            for item in items {
+                if !item.external {
+                    links.extend(NavLink::from_sidebar_item(item));
+                }
+                if let Some(children) = &item.children {
+                    visit(children, links);
+                }
+            }
+        }
+
+        let mut links = Vec::new();
+        visit(&self.items, &mut links);
+        links
+    }
+
+    /// Highlight the sidebar item with that url and open it with its ancestors
     pub fn set_active_url(&mut self, url: &str) {
-        for item in self.items.iter_mut() {
-            item.active = matches!(&item.url, Some(u) if u == url);
-            for child in item.children.iter_mut().flat_map(|xs| xs.iter_mut()) {
-                child.active = matches!(&child.url, Some(u) if u == url);
+        /// Returns if the items contain the active item
+        fn visit(items: &mut [SidebarItem], url: &str, fold_level: Option<usize>) -> bool {
+            let mut contains_active = false;
+            for item in items.iter_mut() {
+                item.active = !item.external && matches!(&item.url, Some(u) if u == url);
+                let child_active = match &mut item.children {
+                    Some(children) => visit(children, url, fold_level),
+                    None => false,
+                };
+                item.open = Sidebar::is_open_by_default(fold_level, item.depth)
+                    || item.active
+                    || child_active;
+                contains_active |= item.active || child_active;
             }
+            contains_active
         }
+
+        visit(&mut self.items, url, self.fold_level);
+    }
+
+    /// Omits ignored files and drafts (unless `adbook build --drafts` is given). An excluded
+    /// summary omits the directory
+    fn filter_excluded<'a>(
+        items: &'a [IndexItem],
+        book: &'a BookStructure,
+    ) -> impl Iterator<Item = &'a IndexItem> {
+        items.iter().filter(move |item| match item {
+            IndexItem::File(_name, file) => !book.is_excluded(file),
+            IndexItem::Dir(index) => !book.is_excluded(&index.summary),
+            IndexItem::Link(_name, _url) => true,
+        })
     }
 
     fn collect_sidebar_items<'a>(
         items: impl Iterator<Item = &'a IndexItem>,
-        src_dir: &Path,
-        base_url_str: &str,
+        book: &BookStructure,
         errors: &mut Vec<Error>,
         depth: usize,
+        order_by: OrderBy,
     ) -> Vec<SidebarItem> {
         items
-            .filter_map(
-                |item| match Self::map_item(item, src_dir, base_url_str, errors, depth) {
-                    Ok(item) => Some(item),
-                    Err(err) => {
-                        errors.push(err);
-                        None
-                    }
-                },
-            )
+            .filter_map(|item| match Self::map_item(item, book, errors, depth) {
+                // the items are already sorted, but the numbers are stripped from the titles
+                Ok(mut item) if order_by == OrderBy::FilenamePrefix => {
+                    item.name = index::strip_numeric_prefix(&item.name).to_string();
+                    Some(item)
+                }
+                Ok(item) => Some(item),
+                Err(err) => {
+                    errors.push(err);
+                    None
+                }
+            })
             .collect()
     }
 
     fn map_item(
         item: &IndexItem,
-        src_dir: &Path,
-        base_url_str: &str,
+        book: &BookStructure,
         errors: &mut Vec<Error>,
         depth: usize,
     ) -> Result<SidebarItem> {
         match &item {
             IndexItem::File(name, file) => Ok(SidebarItem {
                 name: Self::get_title(name, file)?,
-                url: Some(Self::get_url(src_dir, file, base_url_str)?),
+                url: Some(book.src_file_to_url(file)?),
                 children: None,
                 active: false,
                 depth,
+                external: false,
+                draft: adoc::is_draft_file(file),
+                open: Self::is_open_by_default(book.book_ron.fold_level, depth),
             }),
             IndexItem::Dir(index) => {
                 let children = Self::collect_sidebar_items(
-                    index.items.iter(),
-                    src_dir,
-                    base_url_str,
+                    Self::filter_excluded(&index.items, book),
+                    book,
                     errors,
                     depth + 1,
+                    index.order_by,
                 );
                 // add preface
                 Ok(SidebarItem {
                     name: Self::get_title(&index.name, &index.summary)?,
-                    url: Some(Self::get_url(src_dir, &index.summary, base_url_str)?),
+                    url: Some(book.src_file_to_url(&index.summary)?),
                     children: Some(Box::new(children)),
                     active: false,
                     depth,
+                    external: false,
+                    draft: adoc::is_draft_file(&index.summary),
+                    open: Self::is_open_by_default(book.book_ron.fold_level, depth),
                 })
             }
+            IndexItem::Link(name, url) => Ok(SidebarItem {
+                name: name.to_string(),
+                url: Some(url.to_string()),
+                children: None,
+                active: false,
+                depth,
+                external: true,
+                draft: false,
+                open: Self::is_open_by_default(book.book_ron.fold_level, depth),
+            }),
         }
     }
 }
 
+/// File copied to the site directory with `includes` in `book.ron`
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct IncludedFile {
+    /// Relative path from the site directory (with `/` as the separator)
+    pub path: String,
+    /// `{asset_url}/path`
+    pub url: String,
+    /// Extension such as `css` or `js` (empty if there's none)
+    pub ext: String,
+}
+
+impl IncludedFile {
+    /// Lists files in the `includes` items (directories are walked recursively). Missing items are
+    /// skipped since they're reported when copying. Files matching the `ignore` patterns are not
+    /// copied and not listed either
+    pub fn list(book: &BookStructure) -> Vec<Self> {
+        let src_dir = book.src_dir_path();
+        let mut files = Vec::new();
+
+        for rel_path in &book.book_ron.includes {
+            let path = src_dir.join(rel_path);
+
+            if path.is_file() {
+                files.push(path);
+            } else if path.is_dir() {
+                let res = crate::utils::visit_files_rec(&path, &mut |file| {
+                    files.push(file.to_path_buf());
+                    Ok(())
+                });
+                if let Err(err) = res {
+                    log::warn!(
+                        "Unable to list included files in {}: {}",
+                        path.display(),
+                        err
+                    );
+                }
+            }
+        }
+
+        files.sort();
+        files.dedup();
+
+        files
+            .iter()
+            .filter(|file| !book.is_ignored(file))
+            .filter_map(|file| file.strip_prefix(&src_dir).ok())
+            .map(|rel_path| {
+                let path = rel_path
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+
+                Self {
+                    url: format!("{}/{}", book.book_ron.asset_url(), path),
+                    ext: rel_path
+                        .extension()
+                        .map(|ext| ext.to_string_lossy().into_owned())
+                        .unwrap_or_default(),
+                    path,
+                }
+            })
+            .collect()
+    }
+}
+
 /// Context to generate [`HbsInput`]
 #[derive(Debug, Clone)]
 pub struct HbsContext {
     // pub src_dir: PathBuf,
     // pub base_url: String,
     sidebar: Sidebar,
+    math: Option<MathRenderer>,
+    includes: Vec<IncludedFile>,
+    /// Absolute paths to `partial_dirs` in `book.ron`
+    partial_dirs: Vec<PathBuf>,
+    /// Articles in the reading order, for `prev` and `next`
+    pages: Vec<NavLink>,
+    /// Time when the build started (only with `embed_build_time` in `book.ron`)
+    build_time: Option<String>,
+    /// Last commit dates of the source files (only with `revdate_from_git` in `book.ron`), shared
+    /// by the clones
+    git_revdates: Option<Arc<GitRevdates>>,
 }
 
 impl HbsContext {
@@ -198,12 +558,72 @@ impl HbsContext {
         let me = Self {
             // src_dir: book.src_dir_path(),
             // base_url: book.book_ron.base_url.clone(),
+            pages: sidebar.flatten(),
             sidebar,
+            math: book.book_ron.math,
+            includes: IncludedFile::list(book),
+            partial_dirs: book
+                .book_ron
+                .partial_dirs
+                .iter()
+                .map(|dir| book.root.join(dir))
+                .collect(),
+            build_time: book
+                .book_ron
+                .embed_build_time
+                .then(|| chrono::Utc::now().to_rfc3339()),
+            git_revdates: book
+                .book_ron
+                .revdate_from_git
+                .then(|| Arc::new(GitRevdates::new(&book.root))),
         };
 
         (me, errors)
     }
 
+    /// HTML to load the math renderer in `<head>`, if it's enabled in `book.ron`
+    pub fn math_head(&self) -> Option<&'static str> {
+        self.math.map(self::math_head)
+    }
+
+    /// Files copied to the site directory with `includes` in `book.ron`
+    pub fn includes(&self) -> &[IncludedFile] {
+        &self.includes
+    }
+
+    /// RFC 3339 time when the build started, if `embed_build_time` is set in `book.ron`
+    pub fn build_time(&self) -> Option<&str> {
+        self.build_time.as_deref()
+    }
+
+    /// `yyyy-mm-dd` of the last git commit touching the source file, if `revdate_from_git` is set
+    /// in `book.ron`
+    pub fn git_revdate(&self, src_file: &Path) -> Option<String> {
+        self.git_revdates.as_ref()?.revdate(src_file)
+    }
+
+    /// Shared partial directories registered before the template's own `partials` directory
+    pub fn partial_dirs(&self) -> &[PathBuf] {
+        &self.partial_dirs
+    }
+
+    /// Previous and next articles of the URL in the reading order
+    pub fn neighbors(&self, url: &str) -> (Option<NavLink>, Option<NavLink>) {
+        let pos = match self.pages.iter().position(|page| page.url == url) {
+            Some(pos) => pos,
+            None => return (None, None),
+        };
+
+        let prev = pos.checked_sub(1).map(|i| self.pages[i].clone());
+        let next = self.pages.get(pos + 1).cloned();
+        (prev, next)
+    }
+
+    /// Links from the root summary down to the article with the URL
+    pub fn breadcrumbs(&self, url: &str) -> Vec<NavLink> {
+        self.sidebar.breadcrumbs(url)
+    }
+
     /// Creates sidebar context for an article (highlight the article)
     pub fn sidebar_for_url(&self, url: &str) -> Sidebar {
         let mut s = self.sidebar.clone();
@@ -220,6 +640,13 @@ impl HbsContext {
 pub struct HbsInput<'a> {
     /// Used in Handlebars template for hard coding paths
     pub base_url: String,
+    /// Used in Handlebars template for paths to static assets. Same as `base_url` unless
+    /// `asset_url` is set in `book.ron`
+    pub asset_url: String,
+    /// `title` in `book.ron`
+    pub book_title: String,
+    /// `authors` in `book.ron`
+    pub book_authors: Vec<String>,
     /// html data
     pub h_title: String,
     pub h_author: String,
@@ -229,52 +656,184 @@ pub struct HbsInput<'a> {
     pub a_revdate: Option<String>,
     pub a_author: Option<String>,
     pub a_email: Option<String>,
+    /// First of `a_stylesheets`
     pub a_stylesheet: Option<String>,
+    /// URLs of the `stylesheet` attribute and the comma-separated `stylesheets` attribute,
+    /// resolved against `stylesdir`
+    pub a_stylesheets: Vec<String>,
+    /// `noindex` attribute. Use it to emit `<meta name="robots" content="noindex">`
+    pub noindex: bool,
+    /// Attributes with the `hbs_var_prefix` in `book.ron` (`hbs-` by default), keyed by the names
+    /// without the prefix: `:hbs-banner: warning` is `{{custom.banner}}`
+    pub custom: HashMap<String, String>,
     /// Handlebars template context
     pub sidebar_items: Vec<SidebarItem>,
+    /// Link to the home page (the root item of the sidebar)
+    pub home: Option<NavLink>,
+    /// Previous article in the reading order
+    pub prev: Option<NavLink>,
+    /// Next article in the reading order
+    pub next: Option<NavLink>,
+    /// Root summary → directory summaries → the article
+    pub breadcrumbs: Vec<NavLink>,
+    /// HTML to load the math renderer (`math` in `book.ron`). Use it in `<head>` with
+    /// `{{{math_head}}}`
+    pub math_head: Option<&'static str>,
+    /// Raw HTML for `<head>` (`head_html` in `book.ron` or the `head-extra` attribute). It's not
+    /// escaped, so use it with `{{{head_extra}}}`
+    pub head_extra: Option<String>,
+    /// Files copied with `includes` in `book.ron`. Iterate them to emit `<link>` or `<script>`:
+    ///
+    /// ```hbs
+    /// {{#each includes}}{{#if (eq ext "css")}}<link rel="stylesheet" href="{{url}}">{{/if}}{{/each}}
+    /// ```
+    pub includes: &'a [IncludedFile],
+    /// `adbook <version>`. Use it to emit `<meta name="generator">`
+    pub generator: String,
+    /// RFC 3339 time when the build started. It's `None` unless `embed_build_time` is set in
+    /// `book.ron`, so that builds are reproducible by default
+    pub build_time: Option<String>,
+    /// Section headings of the article for in-page navigation, up to `page_toc_depth` in
+    /// `book.ron`
+    pub page_toc: Vec<TocEntry>,
 }
 
 impl<'a> HbsInput<'a> {
     /// WARN: be sure to set `sidebar_items` later
-    pub fn new(html: &'a str, meta: &AdocMetadata, base_url: &str, sidebar: Sidebar) -> Self {
+    pub fn new(html: &'a str, meta: &AdocMetadata, book_ron: &BookRon, sidebar: Sidebar) -> Self {
         fn attr(name: &str, metadata: &AdocMetadata) -> Option<String> {
             metadata
                 .find_attr(name)
                 .and_then(|a| a.value().map(|s| s.to_string()))
         }
 
-        let css = attr("stylesheet", &meta).map(|rel| {
-            if let Some(base) = attr("stylesdir", &meta) {
-                // the css file path is supplied with base directory path!
-                format!("{}/{}", base, rel)
-            } else {
-                rel
-            }
-        });
+        // the document attributes or else the defaults in `book.ron`
+        let author = attr("author", meta).or_else(|| book_ron.default_author.clone());
+        let revdate = attr("revdate", meta).or_else(|| book_ron.default_revdate.clone());
+
+        let asset_url = book_ron.asset_url();
+        let css = self::stylesheet_url_list(
+            attr("stylesheet", &meta),
+            attr("stylesheets", meta),
+            attr("stylesdir", &meta),
+            asset_url,
+        );
 
         HbsInput {
-            base_url: base_url.to_string(),
+            base_url: book_ron.base_url.to_string(),
+            asset_url: asset_url.to_string(),
+            book_title: book_ron.title.clone(),
+            book_authors: book_ron.authors.clone(),
             // TODO: supply html title via `book.ron` using placeholder sutring
             h_title: meta.title.clone().unwrap_or("".into()),
-            h_author: attr("author", &meta).unwrap_or("".into()),
+            h_author: author.clone().unwrap_or("".into()),
             //
             a_title: meta.title.clone(),
             a_article: html,
-            a_revdate: attr("revdate", &meta),
-            a_author: attr("author", &meta),
+            a_revdate: revdate,
+            a_author: author,
             a_email: attr("email", &meta),
-            a_stylesheet: css,
+            a_stylesheet: css.first().cloned(),
+            a_stylesheets: css,
+            noindex: meta.is_set("noindex"),
+            custom: meta.attrs_with_prefix(book_ron.hbs_var_prefix()),
             //
+            home: sidebar.items.first().and_then(NavLink::from_sidebar_item),
+            prev: None,
+            next: None,
+            breadcrumbs: vec![],
             sidebar_items: sidebar.items,
+            math_head: None,
+            head_extra: None,
+            includes: &[],
+            generator: self::generator(),
+            build_time: None,
+            page_toc: self::page_toc(html, book_ron.page_toc_depth),
+        }
+    }
+}
+
+/// `adbook <version>`
+pub fn generator() -> String {
+    format!("adbook {}", env!("CARGO_PKG_VERSION"))
+}
+
+/// Composes the stylesheet URLs from the `stylesheet` attribute and the comma-separated
+/// `stylesheets` attribute. Empty entries and duplicates are skipped
+fn stylesheet_url_list(
+    stylesheet: Option<String>,
+    stylesheets: Option<String>,
+    stylesdir: Option<String>,
+    asset_url: &str,
+) -> Vec<String> {
+    let names = stylesheet
+        .into_iter()
+        .chain(
+            stylesheets
+                .iter()
+                .flat_map(|s| s.split(','))
+                .map(|s| s.to_string()),
+        )
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty());
+
+    let mut urls = Vec::new();
+    for name in names {
+        let url = match self::stylesheet_url(Some(name), stylesdir.clone(), asset_url) {
+            Some(url) => url,
+            None => continue,
+        };
+        if !urls.contains(&url) {
+            urls.push(url);
         }
     }
+
+    urls
+}
+
+/// Composes the stylesheet URL from the `stylesheet` and `stylesdir` attributes
+///
+/// Absolute paths (`/path`) and URLs (`https://..`) are left as-is. Relative paths are prefixed
+/// with `asset_url` (`base_url` by default) so that they work when the site is hosted under a
+/// subpath.
+fn stylesheet_url(
+    stylesheet: Option<String>,
+    stylesdir: Option<String>,
+    asset_url: &str,
+) -> Option<String> {
+    fn is_absolute(url: &str) -> bool {
+        url.starts_with('/') || url.contains("://")
+    }
+
+    let stylesheet = stylesheet?;
+    if is_absolute(&stylesheet) {
+        return Some(stylesheet);
+    }
+
+    // the css file path is supplied with base directory path!
+    let url = match stylesdir {
+        Some(dir) if !dir.is_empty() => format!("{}/{}", dir.trim_end_matches('/'), stylesheet),
+        _ => stylesheet,
+    };
+
+    if is_absolute(&url) {
+        Some(url)
+    } else {
+        Some(format!("{}/{}", asset_url, url))
+    }
 }
 
 // --------------------------------------------------------------------------------
 // Procedure
 
 /// Setup [`Handlebars`] with user theme files
-pub fn init_hbs_user(hbs_dir: &Path) -> Result<Handlebars> {
+///
+/// Partials are registered from `partial_dirs` in order and then from `<hbs_dir>/partials`. Later
+/// directories override same-named partials in earlier ones.
+///
+/// Partials in subdirectories are registered with names relative to the partials directory:
+/// `partials/nav/header.hbs` is referred to as `{{> nav/header}}`.
+pub fn init_hbs_user<'a>(hbs_dir: &Path, partial_dirs: &[PathBuf]) -> Result<Handlebars<'a>> {
     ensure!(
         hbs_dir.is_dir(),
         "Unable to find handlebars directory in source directory"
@@ -290,31 +849,73 @@ pub fn init_hbs_user(hbs_dir: &Path) -> Result<Handlebars> {
         partials_dir.display(),
     );
 
-    for entry in fs::read_dir(&partials_dir)? {
-        let entry = entry.context("Unexpected entry")?;
-        let partial = entry.path();
+    // partial name -> the file registered
+    let mut registered = HashMap::<String, PathBuf>::new();
+    // partial name -> the text registered
+    let mut texts = BTreeMap::<String, String>::new();
 
-        // filter non-hbs files
-        if matches!(partial.extension().and_then(|s| s.to_str()), Some(".hbs")) {
-            continue;
-        }
+    for dir in partial_dirs.iter().chain(std::iter::once(&partials_dir)) {
+        ensure!(
+            dir.is_dir(),
+            "Unable to find handlebars partials directory at: {}",
+            dir.display(),
+        );
+
+        // partials in subdirectories are registered, too
+        let mut partials = Vec::new();
+        crate::utils::visit_files_rec(dir, &mut |path| {
+            // register `.hbs` files only (skip `README.md`, `.DS_Store`, etc.)
+            if matches!(path.extension().and_then(|s| s.to_str()), Some("hbs")) {
+                partials.push(path.to_path_buf());
+            }
+            Ok(())
+        })
+        .with_context(|| format!("Unable to read partials directory: {}", dir.display()))?;
+        partials.sort();
+
+        for partial in partials {
+            // NOTE: the name is used as key to specify partial files!
+            let name = self::partial_name(dir, &partial)
+                .context("Unable to stringify partial hbs file path")?;
+            let name = name.as_str();
 
-        // NOTE: the name is used as key to specify partial files!
-        let name = partial
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .context("Unable to stringify partial hbs file path")?;
+            // register the hbs file as a partial
+            let text = fs::read_to_string(&partial).with_context(|| {
+                format!("Unable to load partial hbs file: {}", partial.display())
+            })?;
 
-        // register the hbs file as a partial
-        let text = fs::read_to_string(&partial)
-            .with_context(|| format!("Unable to load partial hbs file: {}", partial.display()))?;
+            hbs.register_partial(name, &text)?;
+            texts.insert(name.to_string(), text);
 
-        hbs.register_partial(name, &text)?;
+            if let Some(overridden) = registered.insert(name.to_string(), partial.clone()) {
+                log::debug!(
+                    "Partial `{}` in {} is overridden by {}",
+                    name,
+                    overridden.display(),
+                    partial.display()
+                );
+            }
+        }
     }
 
+    self::ensure_no_partial_cycle(&texts)?;
+
     Ok(hbs)
 }
 
+/// Name of a partial file: path relative to the partials directory without the extension,
+/// delimited with `/` (`partials/nav/header.hbs` → `nav/header`)
+fn partial_name(partials_dir: &Path, partial: &Path) -> Option<String> {
+    let rel_path = partial.strip_prefix(partials_dir).ok()?.with_extension("");
+
+    let components = rel_path
+        .components()
+        .map(|c| c.as_os_str().to_str())
+        .collect::<Option<Vec<_>>>()?;
+
+    Some(components.join("/"))
+}
+
 /// Setup [`Handlebars`] with default theme files
 pub fn init_hbs_default() -> Result<Handlebars<'static>> {
     let mut hbs = Handlebars::new();
@@ -323,14 +924,140 @@ pub fn init_hbs_default() -> Result<Handlebars<'static>> {
     use crate::book::init::files::src::theme::hbs;
 
     // NOTE: the name is used as key to specify partial files!
+    let mut texts = BTreeMap::new();
     let text = std::str::from_utf8(hbs::partials::SIDEBAR)?;
     hbs.register_partial("sidebar", &text)?;
+    texts.insert("sidebar".to_string(), text.to_string());
     let text = std::str::from_utf8(hbs::partials::SIDEBAR_ITEM)?;
     hbs.register_partial("sidebar_item", &text)?;
+    texts.insert("sidebar_item".to_string(), text.to_string());
+
+    self::ensure_no_partial_cycle(&texts)?;
 
     Ok(hbs)
 }
 
+/// Names of partials referred to in a template (`{{> name}}`, `{{~> name}}` or `{{#> name}}`)
+/// outside of `#each` blocks
+///
+/// References in `#each` blocks (e.g. `{{#each children}}{{> item}}{{/each}}`) are skipped since
+/// such recursion is bounded by data. Other blocks (`#if`, `#unless`, `#with`, partial blocks)
+/// don't bound recursion, so references in them are counted. Dynamic partials (`{{> (expr)}}`)
+/// and comments are skipped.
+fn unbounded_partial_refs(text: &str) -> Vec<&str> {
+    let mut refs = Vec::new();
+    let mut rest = text;
+    // if each open block is an `#each` block
+    let mut blocks = Vec::<bool>::new();
+
+    while let Some(pos) = rest.find("{{") {
+        rest = &rest[pos + 2..];
+        let tag = rest.trim_start_matches('~');
+
+        // comment
+        if tag.starts_with('!') {
+            let end = if tag.starts_with("!--") { "--}}" } else { "}}" };
+            match rest.find(end) {
+                Some(pos) => rest = &rest[pos + end.len()..],
+                None => break,
+            }
+            continue;
+        }
+
+        // end of block
+        if tag.starts_with('/') {
+            blocks.pop();
+            continue;
+        }
+
+        let (is_block, tag) = match tag.strip_prefix('#') {
+            Some(tag) => (true, tag),
+            None => (false, tag),
+        };
+
+        if let Some(tag) = tag.strip_prefix('>') {
+            let tag = tag.trim_start();
+            let end = tag
+                .find(|c: char| c.is_whitespace() || c == '}' || c == '~')
+                .unwrap_or(tag.len());
+            let name = &tag[..end];
+
+            let in_each = blocks.iter().any(|is_each| *is_each);
+            if !in_each && !name.is_empty() && !name.starts_with('(') {
+                refs.push(name);
+            }
+        }
+
+        if is_block {
+            let mut helper = tag.split(|c: char| c.is_whitespace() || c == '}' || c == '~');
+            blocks.push(helper.next() == Some("each"));
+        }
+    }
+
+    refs
+}
+
+/// Fails with the cycle (`a -> b -> a`) if partials refer to each other unconditionally and
+/// circularly, which would make rendering recurse infinitely
+pub fn ensure_no_partial_cycle(partials: &BTreeMap<String, String>) -> Result<()> {
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum State {
+        Visiting,
+        Done,
+    }
+
+    fn visit<'a>(
+        name: &'a str,
+        partials: &'a BTreeMap<String, String>,
+        states: &mut HashMap<&'a str, State>,
+        path: &mut Vec<&'a str>,
+    ) -> Option<Vec<String>> {
+        match states.get(name) {
+            Some(State::Done) => return None,
+            Some(State::Visiting) => {
+                // found a cycle
+                let start = path.iter().position(|n| *n == name).unwrap();
+                let mut cycle = path[start..]
+                    .iter()
+                    .map(|n| n.to_string())
+                    .collect::<Vec<_>>();
+                cycle.push(name.to_string());
+                return Some(cycle);
+            }
+            None => {}
+        }
+
+        // references to unknown partials are reported on rendering
+        let text = partials.get(name)?;
+
+        states.insert(name, State::Visiting);
+        path.push(name);
+
+        for child in self::unbounded_partial_refs(text) {
+            if let Some(cycle) = visit(child, partials, states, path) {
+                return Some(cycle);
+            }
+        }
+
+        path.pop();
+        states.insert(name, State::Done);
+
+        None
+    }
+
+    let mut states = HashMap::new();
+    for name in partials.keys() {
+        if let Some(cycle) = visit(name, partials, &mut states, &mut vec![]) {
+            bail!(
+                "Circular Handlebars partial references: {}",
+                cycle.join(" -> ")
+            );
+        }
+    }
+
+    Ok(())
+}
+
 pub fn render_hbs_user<'a>(
     hbs: &mut Handlebars,
     hbs_input: &HbsInput,
@@ -365,3 +1092,527 @@ pub fn render_hbs_default<'a>(
 
     Ok(output)
 }
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, fs};
+
+    use crate::{
+        book::{ignore::IgnorePatterns, init, walk, BookStructure},
+        build::convert::AdocMetadata,
+    };
+
+    use super::{
+        ensure_no_partial_cycle, init_hbs_default, init_hbs_user, page_toc, render_hbs_default,
+        stylesheet_url, stylesheet_url_list, unbounded_partial_refs, HbsContext, HbsInput,
+        IncludedFile, Sidebar, SidebarItem, TocEntry,
+    };
+
+    fn partials(items: &[(&str, &str)]) -> BTreeMap<String, String> {
+        items
+            .iter()
+            .map(|(name, text)| (name.to_string(), text.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn refs() {
+        let text = "{{> a}} {{~> b ~}} {{#> c}}{{> x}}{{/c}} {{> (lookup . \"d\")}} {{e}}";
+        assert_eq!(unbounded_partial_refs(text), vec!["a", "b", "c", "x"]);
+
+        let text =
+            "{{#if title}}{{> a}}{{/if}} {{#with this}}{{#each xs}}{{> b}}{{/each}}{{/with}}";
+        assert_eq!(unbounded_partial_refs(text), vec!["a"]);
+
+        let text = "{{#each children}}{{> item}}{{/each}} {{!-- {{> f}} --}} {{> g}}";
+        assert_eq!(unbounded_partial_refs(text), vec!["g"]);
+    }
+
+    #[test]
+    fn toc() {
+        let html = r##"<h1>Title</h1>
+<div class="sect1">
+<h2 id="_intro"><a class="anchor" href="#_intro"></a>1. Intro &amp; setup</h2>
+<div class="sect2">
+<h3 id="_details" class="discrete">1.1. <code>Details</code></h3>
+<h4 id="_deep">Deep</h4>
+</div>
+<h2 data-id="x">No id</h2>
+<h2 id="_usage">2. Usage</h2>
+</div>"##;
+
+        let entry = |level: usize, id: &str, text: &str| TocEntry {
+            level,
+            id: id.to_string(),
+            text: text.to_string(),
+        };
+
+        assert_eq!(
+            page_toc(html, None),
+            vec![
+                entry(1, "_intro", "1. Intro & setup"),
+                entry(2, "_details", "1.1. Details"),
+                entry(3, "_deep", "Deep"),
+                entry(1, "_usage", "2. Usage"),
+            ]
+        );
+
+        assert_eq!(
+            page_toc(html, Some(1)),
+            vec![
+                entry(1, "_intro", "1. Intro & setup"),
+                entry(1, "_usage", "2. Usage")
+            ]
+        );
+    }
+
+    #[test]
+    fn partial_cycle() {
+        // recursion bounded by data
+        let ok = partials(&[
+            (
+                "sidebar",
+                "<ul>{{#each items}}{{> sidebar_item}}{{/each}}</ul>",
+            ),
+            (
+                "sidebar_item",
+                "<li>{{#each children}}{{> sidebar_item}}{{/each}}</li>",
+            ),
+        ]);
+        assert!(ensure_no_partial_cycle(&ok).is_ok());
+        assert!(init_hbs_default().is_ok());
+
+        let cyclic = partials(&[("a", "{{> b}}"), ("b", "<p>{{~> a}}</p>")]);
+        let err = ensure_no_partial_cycle(&cyclic).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Circular Handlebars partial references: a -> b -> a"
+        );
+
+        // conditions and context changes don't bound recursion
+        let cyclic = partials(&[("a", "{{#if title}}{{> a}}{{/if}}")]);
+        let err = ensure_no_partial_cycle(&cyclic).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Circular Handlebars partial references: a -> a"
+        );
+
+        let cyclic = partials(&[("a", "{{#with this}}{{> b}}{{/with}}"), ("b", "{{> a}}")]);
+        let err = ensure_no_partial_cycle(&cyclic).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Circular Handlebars partial references: a -> b -> a"
+        );
+    }
+
+    #[test]
+    fn prev_next() {
+        let item = |name: &str, url: &str, children: Option<Vec<SidebarItem>>| SidebarItem {
+            name: name.to_string(),
+            url: Some(url.to_string()),
+            children: children.map(Box::new),
+            active: false,
+            depth: 0,
+            external: url.starts_with("https://"),
+            draft: false,
+            open: true,
+        };
+
+        let sidebar = Sidebar {
+            items: vec![
+                item("Home", "/index.html", None),
+                item(
+                    "Part",
+                    "/part/index.html",
+                    Some(vec![
+                        item("A", "/part/a.html", None),
+                        item("Ext", "https://example.com", None),
+                    ]),
+                ),
+                item("B", "/b.html", None),
+            ],
+            fold_level: None,
+        };
+
+        let hcx = HbsContext {
+            pages: sidebar.flatten(),
+            sidebar,
+            math: None,
+            includes: vec![],
+            partial_dirs: vec![],
+            build_time: None,
+            git_revdates: None,
+        };
+
+        let urls = |(prev, next): (Option<super::NavLink>, Option<super::NavLink>)| {
+            (prev.map(|l| l.url), next.map(|l| l.url))
+        };
+
+        assert_eq!(
+            urls(hcx.neighbors("/index.html")),
+            (None, Some("/part/index.html".to_string()))
+        );
+        assert_eq!(
+            urls(hcx.neighbors("/part/a.html")),
+            (
+                Some("/part/index.html".to_string()),
+                Some("/b.html".to_string())
+            )
+        );
+        assert_eq!(
+            urls(hcx.neighbors("/b.html")),
+            (Some("/part/a.html".to_string()), None)
+        );
+    }
+
+    #[test]
+    fn breadcrumbs() {
+        let item = |url: &str, children: Option<Vec<SidebarItem>>| SidebarItem {
+            name: url.to_string(),
+            url: Some(url.to_string()),
+            children: children.map(Box::new),
+            active: false,
+            depth: 0,
+            external: false,
+            draft: false,
+            open: true,
+        };
+
+        let sidebar = Sidebar {
+            items: vec![
+                item("/index.html", None),
+                item(
+                    "/a/index.html",
+                    Some(vec![item(
+                        "/a/b/index.html",
+                        Some(vec![item("/a/b/c.html", None)]),
+                    )]),
+                ),
+                item("/d.html", None),
+            ],
+            fold_level: None,
+        };
+
+        let urls = |url: &str| {
+            sidebar
+                .breadcrumbs(url)
+                .into_iter()
+                .map(|link| link.url)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(urls("/index.html"), vec!["/index.html"]);
+        assert_eq!(urls("/d.html"), vec!["/index.html", "/d.html"]);
+        assert_eq!(
+            urls("/a/b/c.html"),
+            vec![
+                "/index.html",
+                "/a/index.html",
+                "/a/b/index.html",
+                "/a/b/c.html"
+            ]
+        );
+        assert!(urls("/404.html").is_empty());
+    }
+
+    #[test]
+    fn stylesheet_urls() {
+        let s = |x: &str| Some(x.to_string());
+
+        // absolute URL
+        assert_eq!(
+            stylesheet_url(s("https://cdn.example/a.css"), s("css"), "/docs"),
+            s("https://cdn.example/a.css")
+        );
+        assert_eq!(
+            stylesheet_url(s("a.css"), s("/docs/theme/css"), "/docs"),
+            s("/docs/theme/css/a.css")
+        );
+
+        // relative stylesheet under a subpath `base_url`
+        assert_eq!(
+            stylesheet_url(s("a.css"), s("theme/css"), "/docs"),
+            s("/docs/theme/css/a.css")
+        );
+        assert_eq!(stylesheet_url(s("a.css"), None, "/docs"), s("/docs/a.css"));
+        assert_eq!(stylesheet_url(s("a.css"), None, ""), s("/a.css"));
+
+        assert_eq!(stylesheet_url(None, s("theme/css"), "/docs"), None);
+    }
+
+    #[test]
+    fn multiple_stylesheets() {
+        let s = |x: &str| Some(x.to_string());
+
+        assert_eq!(
+            stylesheet_url_list(
+                s("article.css"),
+                s(" extra.css, ,https://cdn.example/b.css ,article.css,"),
+                s("theme/css"),
+                "/docs"
+            ),
+            vec![
+                "/docs/theme/css/article.css".to_string(),
+                "/docs/theme/css/extra.css".to_string(),
+                "https://cdn.example/b.css".to_string(),
+            ]
+        );
+
+        assert_eq!(
+            stylesheet_url_list(None, s("a.css"), None, ""),
+            vec!["/a.css".to_string()]
+        );
+        assert!(stylesheet_url_list(None, s(" , "), None, "").is_empty());
+    }
+
+    #[test]
+    fn fold_level() {
+        let item = |url: &str, depth: usize, children: Option<Vec<SidebarItem>>| SidebarItem {
+            name: url.to_string(),
+            url: Some(url.to_string()),
+            children: children.map(Box::new),
+            active: false,
+            depth,
+            external: false,
+            draft: false,
+            open: Sidebar::is_open_by_default(Some(1), depth),
+        };
+
+        let sidebar = Sidebar {
+            items: vec![
+                item("/index.html", 0, None),
+                item(
+                    "/a/index.html",
+                    0,
+                    Some(vec![
+                        item(
+                            "/a/b/index.html",
+                            1,
+                            Some(vec![item("/a/b/c.html", 2, None)]),
+                        ),
+                        item(
+                            "/a/d/index.html",
+                            1,
+                            Some(vec![item("/a/d/e.html", 2, None)]),
+                        ),
+                    ]),
+                ),
+            ],
+            fold_level: Some(1),
+        };
+
+        let opens = |sidebar: &Sidebar| {
+            let a = &sidebar.items[1];
+            let children = a.children.as_ref().unwrap();
+            (a.open, children[0].open, children[1].open)
+        };
+
+        // only the top-level items are open by default
+        assert_eq!(opens(&sidebar), (true, false, false));
+
+        // the ancestors of the active item are open
+        let mut s = sidebar.clone();
+        s.set_active_url("/a/b/c.html");
+        assert_eq!(opens(&s), (true, true, false));
+        assert!(
+            s.items[1].children.as_ref().unwrap()[0]
+                .children
+                .as_ref()
+                .unwrap()[0]
+                .active
+        );
+
+        // the active directory is open, too
+        let mut s = sidebar.clone();
+        s.set_active_url("/a/d/index.html");
+        assert_eq!(opens(&s), (true, false, true));
+        assert!(s.items[1].children.as_ref().unwrap()[1].active);
+
+        let mut s = sidebar;
+        s.set_active_url("/index.html");
+        assert_eq!(opens(&s), (true, false, false));
+    }
+
+    #[test]
+    fn drafts() {
+        let dir = std::env::temp_dir().join("adbook-test-drafts");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        init::gen_init_files(&dir).unwrap();
+
+        let src_dir = dir.join("src");
+        fs::create_dir_all(src_dir.join("sub")).unwrap();
+        fs::write(src_dir.join("draft.adoc"), "= Draft\n:draft:\n\nText\n").unwrap();
+        // a draft summary hides the whole directory
+        fs::write(src_dir.join("sub/index.adoc"), "= Sub\n:draft:\n").unwrap();
+        fs::write(src_dir.join("sub/a.adoc"), "= A\n").unwrap();
+        fs::write(
+            src_dir.join("sub/index.ron"),
+            r#"summary: ("", "index.adoc"), items: [File("", "a.adoc")]"#,
+        )
+        .unwrap();
+        fs::write(
+            src_dir.join("index.ron"),
+            r#"summary: ("", "index.adoc"), items: [File("", "article.adoc"), File("", "draft.adoc"), Dir("sub")]"#,
+        )
+        .unwrap();
+
+        let names = |book: &BookStructure| {
+            let (sidebar, errors) = Sidebar::from_book(book);
+            assert!(errors.is_empty(), "{:?}", errors);
+            sidebar
+                .flatten()
+                .into_iter()
+                .map(|link| link.name)
+                .collect::<Vec<_>>()
+        };
+        let n_files = |book: &BookStructure| walk::list_src_files(book).len();
+
+        let mut book = BookStructure::from_dir(&dir).unwrap();
+        assert_eq!(names(&book), vec!["Index", "Article"]);
+        assert_eq!(n_files(&book), 2);
+
+        book.drafts = true;
+        assert_eq!(names(&book), vec!["Index", "Article", "Draft", "Sub", "A"]);
+        assert_eq!(n_files(&book), 5);
+
+        let (sidebar, _errors) = Sidebar::from_book(&book);
+        let drafts = sidebar
+            .items()
+            .iter()
+            .map(|item| item.draft)
+            .collect::<Vec<_>>();
+        assert_eq!(drafts, vec![false, false, true, true]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hbs_partials_only() {
+        let dir = std::env::temp_dir().join("adbook-test-partials");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(dir.join("partials")).unwrap();
+        fs::write(dir.join("partials/item.hbs"), "<li>{{name}}</li>").unwrap();
+        fs::write(dir.join("partials/notes.txt"), "{{#if}} not a partial").unwrap();
+
+        let hbs = init_hbs_user(&dir, &[]).unwrap();
+        assert!(hbs.has_template("item"));
+        assert!(!hbs.has_template("notes"));
+
+        fs::create_dir_all(dir.join("partials/nav")).unwrap();
+        fs::write(
+            dir.join("partials/nav/header.hbs"),
+            "<header>{{title}}</header>",
+        )
+        .unwrap();
+
+        let hbs = init_hbs_user(&dir, &[]).unwrap();
+        assert!(hbs.has_template("item"));
+        assert!(hbs.has_template("nav/header"));
+        assert!(!hbs.has_template("header"));
+        assert_eq!(
+            hbs.render_template("{{> nav/header}}", &serde_json::json!({ "title": "T" }))
+                .unwrap(),
+            "<header>T</header>"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn book_metadata() {
+        let dir = std::env::temp_dir().join("adbook-test-book-metadata");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        init::gen_init_files(&dir).unwrap();
+
+        let book = BookStructure::from_dir(&dir).unwrap();
+        let (sidebar, _errors) = Sidebar::from_book(&book);
+
+        let render = |adoc: &str| {
+            let meta = AdocMetadata::extract_header(adoc);
+            let input = HbsInput::new("<p>Text</p>", &meta, &book.book_ron, sidebar.clone());
+            assert_eq!(input.book_title, "adbook demo site");
+            assert_eq!(input.book_authors, vec!["adbook".to_string()]);
+
+            let mut hbs = init_hbs_default().unwrap();
+            render_hbs_default(&mut hbs, &input, "article.adoc").unwrap()
+        };
+
+        let html = render("= Article\n");
+        assert!(
+            html.contains("<title>Article - adbook demo site</title>"),
+            "{}",
+            html
+        );
+        assert!(html.contains(r#"<meta name="author" content="adbook">"#));
+
+        // the article author overrides the book authors
+        let html = render("= Article\n:author: someone\n");
+        assert!(html.contains(r#"<meta name="author" content="someone">"#));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn default_author_revdate() {
+        let dir = std::env::temp_dir().join("adbook-test-default-author");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        init::gen_init_files(&dir).unwrap();
+
+        let mut book = BookStructure::from_dir(&dir).unwrap();
+        book.book_ron.default_author = Some("site author".to_string());
+        book.book_ron.default_revdate = Some("2020-01-01".to_string());
+        let (sidebar, _errors) = Sidebar::from_book(&book);
+
+        // fallback
+        let meta = AdocMetadata::extract_header("= Article\n");
+        let input = HbsInput::new("", &meta, &book.book_ron, sidebar.clone());
+        assert_eq!(input.a_author.as_deref(), Some("site author"));
+        assert_eq!(input.a_revdate.as_deref(), Some("2020-01-01"));
+        assert_eq!(input.h_author, "site author");
+
+        // override
+        let meta =
+            AdocMetadata::extract_header("= Article\n:author: someone\n:revdate: 2021-02-03\n");
+        let input = HbsInput::new("", &meta, &book.book_ron, sidebar);
+        assert_eq!(input.a_author.as_deref(), Some("someone"));
+        assert_eq!(input.a_revdate.as_deref(), Some("2021-02-03"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn included_files_skip_ignored() {
+        let dir = std::env::temp_dir().join("adbook-test-included-files");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        init::gen_init_files(&dir).unwrap();
+
+        let static_dir = dir.join("src/static");
+        fs::create_dir_all(&static_dir).unwrap();
+        fs::write(static_dir.join("a.css"), "a").unwrap();
+        fs::write(static_dir.join("b.tmp.css"), "b").unwrap();
+
+        let mut book = BookStructure::from_dir(&dir).unwrap();
+        book.book_ron.includes = vec!["static".into()];
+        book.book_ron.ignore = vec!["*.tmp.css".to_string()];
+        book.ignore = IgnorePatterns::new(&book.book_ron.ignore).unwrap();
+
+        let files = IncludedFile::list(&book);
+        let paths = files.iter().map(|f| f.path.as_str()).collect::<Vec<_>>();
+        assert_eq!(paths, vec!["static/a.css"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,349 @@
+/*!
+Lightweight AsciiDoc to plain text extractor
+
+It approximates the text of an article without running `asciidoctor`, which is good enough for
+keyword indexing and counting words. Markup is stripped line by line:
+
+* header/attribute lines, comments, directives (`include::`, `ifdef::`, ..) and block macros
+  (`image::`, `toc::`, ..) are skipped
+* block delimiters and block attribute lines (`[source,rust]`, `[[anchor]]`) are skipped, and
+  passthrough blocks (`++++`) and comment blocks (`////`) are skipped with their contents
+* heading markers, block titles, list bullets, admonition labels and table cell separators are
+  removed
+* inline macros (`link:url[text]`, `https://url[text]`, `xref:id[text]`, `<<id,text>>`) are
+  replaced with their text, and formatting marks (`*bold*`, `_emphasis_`, `` `mono` ``) and
+  attribute references (`{name}`) are removed
+*/
+
+/// Extracts approximate plain text from an AsciiDoc string
+///
+/// Paragraphs are separated with one blank line.
+pub fn to_plain_text(adoc: &str) -> String {
+    let mut out = String::with_capacity(adoc.len());
+    // the block delimiter of a skipped block (`////` or `++++`)
+    let mut skipping: Option<&str> = None;
+
+    for line in adoc.lines() {
+        let trimmed = line.trim();
+
+        if let Some(delim) = skipping {
+            if trimmed == delim {
+                skipping = None;
+            }
+            continue;
+        }
+
+        if trimmed == "////" || trimmed == "++++" {
+            skipping = Some(trimmed);
+            continue;
+        }
+
+        if trimmed.is_empty() {
+            // paragraph break
+            if !out.is_empty() && !out.ends_with("\n\n") {
+                out.push('\n');
+            }
+            continue;
+        }
+
+        if self::is_line_to_skip(trimmed) {
+            continue;
+        }
+
+        let text = self::strip_inline(self::strip_line_prefix(trimmed));
+        // also collapses spaces left by removed markup
+        let words = text.split_whitespace().collect::<Vec<_>>();
+        if words.is_empty() {
+            continue;
+        }
+
+        out.push_str(&words.join(" "));
+        out.push('\n');
+    }
+
+    out.trim_end().to_string()
+}
+
+/// Counts whitespace-separated words
+pub fn count_words(text: &str) -> usize {
+    text.split_whitespace().count()
+}
+
+fn is_line_to_skip(ln: &str) -> bool {
+    // comment
+    if ln.starts_with("//") {
+        return true;
+    }
+
+    // attribute entry: `:name: value` or `:!name:`
+    if ln.starts_with(':') && ln[1..].contains(':') && !ln[1..].starts_with(char::is_whitespace) {
+        return true;
+    }
+
+    // block attribute line: `[source,rust]`, `[[anchor]]`, `[NOTE]`
+    if ln.starts_with('[') && ln.ends_with(']') {
+        return true;
+    }
+
+    // block delimiter: `----`, `....`, `====`, `****`, `____`, `|===`, `--`
+    if ln == "--" || ln.starts_with("|===") || self::is_delimiter(ln) {
+        return true;
+    }
+
+    // directive or block macro: `include::file[]`, `image::a.png[]`, `ifdef::attr[]`
+    if let Some(pos) = ln.find("::") {
+        let name = &ln[..pos];
+        if !name.is_empty()
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            && ln.ends_with(']')
+        {
+            return true;
+        }
+    }
+
+    false
+}
+
+/// Four or more of the same delimiter character
+fn is_delimiter(ln: &str) -> bool {
+    let first = match ln.chars().next() {
+        Some(c) => c,
+        None => return false,
+    };
+
+    ln.len() >= 4 && "-.=*_+/".contains(first) && ln.chars().all(|c| c == first)
+}
+
+/// Removes heading markers, block titles, list bullets, admonition labels and table separators
+fn strip_line_prefix(ln: &str) -> &str {
+    // heading: `== Title`, list: `* item`, `. item`, `- item`
+    for marker in &['=', '*', '.', '-'] {
+        let rest = ln.trim_start_matches(*marker);
+        if rest.len() != ln.len() && rest.starts_with(' ') {
+            return rest.trim_start();
+        }
+    }
+
+    // block title: `.Title`
+    if let Some(rest) = ln.strip_prefix('.') {
+        if rest.starts_with(|c: char| c.is_alphanumeric()) {
+            return rest;
+        }
+    }
+
+    // admonition paragraph: `NOTE: text`
+    for label in &["NOTE:", "TIP:", "IMPORTANT:", "WARNING:", "CAUTION:"] {
+        if let Some(rest) = ln.strip_prefix(label) {
+            return rest.trim_start();
+        }
+    }
+
+    // table cell: `| cell`
+    if let Some(rest) = ln.strip_prefix('|') {
+        return rest.trim_start();
+    }
+
+    // line continuation: `text +`
+    ln.strip_suffix(" +").unwrap_or(ln)
+}
+
+/// Replaces inline macros with their text and removes formatting marks and attribute references
+fn strip_inline(ln: &str) -> String {
+    let ln = self::strip_xrefs(ln);
+    let ln = self::strip_macros(&ln);
+    let ln = self::strip_attr_refs(&ln);
+    self::strip_formatting(&ln)
+}
+
+/// `<<id,text>>` -> `text`, `<<id>>` -> `id`
+fn strip_xrefs(ln: &str) -> String {
+    let mut out = String::with_capacity(ln.len());
+    let mut rest = ln;
+
+    while let Some(open) = rest.find("<<") {
+        let close = match rest[open..].find(">>") {
+            Some(close) => open + close,
+            None => break,
+        };
+
+        out.push_str(&rest[..open]);
+        let inner = &rest[open + 2..close];
+        let text = inner
+            .split_once(',')
+            .map(|(_id, text)| text)
+            .unwrap_or(inner);
+        out.push_str(text.trim());
+        rest = &rest[close + 2..];
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// `name:target[text]` -> `text`, `https://url[]` -> `https://url`, `[.role]#text#` -> `#text#`
+fn strip_macros(ln: &str) -> String {
+    let mut out = String::with_capacity(ln.len());
+    let mut rest = ln;
+
+    while let Some(open) = rest.find('[') {
+        let close = match rest[open..].find(']') {
+            Some(close) => open + close,
+            None => break,
+        };
+
+        let token_start = rest[..open]
+            .rfind(char::is_whitespace)
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let token = &rest[token_start..open];
+        let text = &rest[open + 1..close];
+
+        if token.contains(':') {
+            // inline macro
+            out.push_str(&rest[..token_start]);
+            if !text.is_empty() {
+                out.push_str(text.trim_end_matches('^'));
+            } else if token.starts_with("http") {
+                out.push_str(token);
+            }
+        } else if token.is_empty() && (text.starts_with('.') || text.starts_with('#')) {
+            // role or ID: `[.role]#text#`
+            out.push_str(&rest[..open]);
+        } else {
+            out.push_str(&rest[..=close]);
+        }
+
+        rest = &rest[close + 1..];
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// `{name}` -> ``
+fn strip_attr_refs(ln: &str) -> String {
+    let mut out = String::with_capacity(ln.len());
+    let mut rest = ln;
+
+    while let Some(open) = rest.find('{') {
+        let close = match rest[open..].find('}') {
+            Some(close) => open + close,
+            None => break,
+        };
+
+        let name = &rest[open + 1..close];
+        out.push_str(&rest[..open]);
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            // not an attribute reference
+            out.push_str(&rest[open..=close]);
+        }
+        rest = &rest[close + 1..];
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// Removes `*` and `` ` `` and `_`, `#`, `^`, `~` at word boundaries (so `snake_case` is kept)
+fn strip_formatting(ln: &str) -> String {
+    let chars = ln.chars().collect::<Vec<_>>();
+    let mut out = String::with_capacity(ln.len());
+
+    for (i, &c) in chars.iter().enumerate() {
+        match c {
+            '*' | '`' => continue,
+            '_' | '#' | '^' | '~' => {
+                let prev = i.checked_sub(1).map(|i| chars[i]);
+                let next = chars.get(i + 1).copied();
+                let is_word = |c: Option<char>| matches!(c, Some(c) if c.is_alphanumeric());
+                if !(is_word(prev) && is_word(next)) {
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        out.push(c);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::{count_words, to_plain_text};
+
+    const ARTICLE: &str = r###"= Getting started
+:revdate: Oct 23, 2020
+:author: someone
+:toc:
+
+// a comment line
+
+== Installation
+
+Install *adbook* with `cargo install`. See link:https://asciidoctor.org[Asciidoctor] and
+https://www.rust-lang.org/[Rust] for details, or <<usage,the usage section>>.
+
+NOTE: The {project} name came from _mdBook_.
+
+include::partial.adoc[]
+
+image::diagram.png[Diagram]
+
+[source,rust]
+.main.rs
+----
+fn main() {}
+----
+
+////
+commented out block
+////
+
+++++
+<div>raw HTML</div>
+++++
+
+* first item
+* second item with snake_case_word
+. ordered item
+
+|===
+| cell a
+|===
+"###;
+
+    #[test]
+    fn article() {
+        let text = to_plain_text(ARTICLE);
+
+        assert_eq!(
+            text,
+            r###"Getting started
+
+Installation
+
+Install adbook with cargo install. See Asciidoctor and
+Rust for details, or the usage section.
+
+The name came from mdBook.
+
+main.rs
+fn main() {}
+
+first item
+second item with snake_case_word
+ordered item
+
+cell a"###
+        );
+    }
+
+    #[test]
+    fn words() {
+        assert_eq!(
+            count_words(&to_plain_text("= Title\n\nOne *two* three.")),
+            4
+        );
+    }
+}
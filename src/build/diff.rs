@@ -0,0 +1,179 @@
+/*!
+Output diff against the previous site directory (`adbook build --diff`)
+
+Before the site directory is rebuilt, the content hashes of the existing output files are taken as
+a [`SiteSnapshot`]. After the build, each output is compared with it and the output paths are
+reported as added, modified or removed. Files whose content is the same are not reported, even if
+they're rewritten. It doesn't change the build result.
+
+With `--diff-json`, the list is printed as JSON:
+
+```json
+{
+  "added": ["new.html"],
+  "modified": ["index.html"],
+  "removed": ["old.html"]
+}
+```
+*/
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::*;
+use colored::*;
+use serde::Serialize;
+
+use crate::{book::BookStructure, build::cache};
+
+/// How the output diff is printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffFormat {
+    Text,
+    Json,
+}
+
+/// Content hashes of the output files in the site directory before a build
+#[derive(Debug, Clone, Default)]
+pub struct SiteSnapshot {
+    /// Relative path from the site directory → SHA-256 hash
+    hashes: BTreeMap<PathBuf, String>,
+}
+
+impl SiteSnapshot {
+    /// Hashes the output files (files with the output extension) in the site directory. The theme
+    /// and `includes` are not outputs and skipped
+    pub fn take(book: &BookStructure) -> Result<Self> {
+        let site_dir = book.site_dir_path();
+        let mut hashes = BTreeMap::new();
+
+        if !site_dir.is_dir() {
+            return Ok(Self { hashes });
+        }
+
+        let ext = book.book_ron.output_extension();
+        let skipped = book
+            .book_ron
+            .includes
+            .iter()
+            .map(|p| p.as_path())
+            .chain(std::iter::once(Path::new("theme")))
+            .collect::<Vec<_>>();
+
+        crate::utils::visit_files_rec(&site_dir, &mut |file| {
+            let rel_path = file.strip_prefix(&site_dir).unwrap();
+            if file.extension().and_then(|s| s.to_str()) != Some(ext)
+                || skipped.iter().any(|dir| rel_path.starts_with(dir))
+            {
+                return Ok(());
+            }
+
+            hashes.insert(rel_path.to_path_buf(), cache::content_hash(file)?);
+            Ok(())
+        })?;
+
+        Ok(Self { hashes })
+    }
+}
+
+/// Output paths that changed from the [`SiteSnapshot`]
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct OutputDiff {
+    pub added: Vec<PathBuf>,
+    pub modified: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+}
+
+impl OutputDiff {
+    /// * `outputs`: relative path from the site directory → SHA-256 hash of the new output
+    pub fn create(snapshot: &SiteSnapshot, outputs: &BTreeMap<PathBuf, String>) -> Self {
+        let mut diff = Self::default();
+
+        for (path, hash) in outputs {
+            match snapshot.hashes.get(path) {
+                None => diff.added.push(path.clone()),
+                Some(old) if old != hash => diff.modified.push(path.clone()),
+                Some(_) => {}
+            }
+        }
+
+        diff.removed = snapshot
+            .hashes
+            .keys()
+            .filter(|path| !outputs.contains_key(*path))
+            .cloned()
+            .collect();
+
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.modified.is_empty() && self.removed.is_empty()
+    }
+
+    pub fn print(&self, format: DiffFormat) -> Result<()> {
+        match format {
+            DiffFormat::Json => {
+                let json = serde_json::to_string_pretty(self)
+                    .context("Unable to serialize output diff")?;
+                println!("{}", json);
+            }
+            DiffFormat::Text => {
+                if self.is_empty() {
+                    println!("No output changed");
+                }
+                for path in &self.added {
+                    println!("{} {}", "added:   ".green(), path.display());
+                }
+                for path in &self.modified {
+                    println!("{} {}", "modified:".yellow(), path.display());
+                }
+                for path in &self.removed {
+                    println!("{} {}", "removed: ".red(), path.display());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, path::PathBuf};
+
+    use super::{OutputDiff, SiteSnapshot};
+
+    #[test]
+    fn output_diff() {
+        let entry = |path: &str, hash: &str| (PathBuf::from(path), hash.to_string());
+
+        let snapshot = SiteSnapshot {
+            hashes: vec![
+                entry("same.html", "a"),
+                entry("mod.html", "b"),
+                entry("old.html", "c"),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        let outputs: BTreeMap<_, _> = vec![
+            entry("same.html", "a"),
+            entry("mod.html", "x"),
+            entry("new.html", "d"),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(
+            OutputDiff::create(&snapshot, &outputs),
+            OutputDiff {
+                added: vec![PathBuf::from("new.html")],
+                modified: vec![PathBuf::from("mod.html")],
+                removed: vec![PathBuf::from("old.html")],
+            }
+        );
+    }
+}
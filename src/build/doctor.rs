@@ -0,0 +1,197 @@
+/*!
+Preflight checks of the build environment (`adbook doctor`)
+
+* `adoc_cmd` in `book.ron` (or `asciidoctor`) is in `PATH`
+* `asciidoctor --version` runs
+* `asciidoctor-diagram` is loadable. It's required only if `adoc_opts` loads it (`-r
+  asciidoctor-diagram`)
+*/
+
+use std::process::{Command, Stdio};
+
+use anyhow::*;
+use colored::*;
+
+use crate::{book::config::BookRon, build::convert};
+
+/// Result of a check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Ok,
+    /// Missing optional dependency
+    Warn,
+    /// Missing required dependency
+    Missing,
+}
+
+/// Item in the report of `adbook doctor`
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: String,
+    pub status: CheckStatus,
+    /// Version, path or the reason of the failure
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status,
+            detail: detail.into(),
+        }
+    }
+
+    /// Prints a colored line
+    pub fn print(&self) {
+        let status = match self.status {
+            CheckStatus::Ok => "[ok]".green(),
+            CheckStatus::Warn => "[warn]".yellow(),
+            CheckStatus::Missing => "[missing]".red(),
+        };
+        println!("{} {}: {}", status.bold(), self.name, self.detail);
+    }
+}
+
+/// Fails if `adoc_cmd` in `book.ron` (or `asciidoctor`) is not in `PATH`
+pub fn ensure_adoc_cmd(book_ron: &BookRon) -> Result<()> {
+    let adoc_cmd = book_ron.adoc_cmd();
+    if which::which(adoc_cmd).is_err() {
+        bail!(
+            "`{}` is not in PATH (set `adoc_cmd` in `book.ron` to the path to `asciidoctor`)",
+            adoc_cmd
+        );
+    }
+    Ok(())
+}
+
+/// Runs the checks. `book_ron` is `None` outside of a book, where the default `asciidoctor` is
+/// checked
+pub fn run_checks(book_ron: Option<&BookRon>) -> Vec<Check> {
+    let (adoc_cmd, args_prefix) = match book_ron {
+        Some(book_ron) => (book_ron.adoc_cmd(), book_ron.adoc_args_prefix.as_slice()),
+        None => ("asciidoctor", &[][..]),
+    };
+
+    let mut checks = Vec::new();
+
+    // `adoc_cmd`
+    match which::which(adoc_cmd) {
+        Result::Ok(path) => checks.push(Check::new(
+            "adoc_cmd",
+            CheckStatus::Ok,
+            format!("`{}` at {}", adoc_cmd, path.display()),
+        )),
+        Err(_) => {
+            checks.push(Check::new(
+                "adoc_cmd",
+                CheckStatus::Missing,
+                format!(
+                    "`{}` is not in PATH (set `adoc_cmd` in `book.ron` to the path to `asciidoctor`)",
+                    adoc_cmd
+                ),
+            ));
+            // nothing else can be checked
+            return checks;
+        }
+    }
+
+    let command = || convert::adoc_command(adoc_cmd, args_prefix);
+
+    // `asciidoctor --version`
+    checks.push(
+        match command().map(|mut cmd| self::run(cmd.arg("--version"))) {
+            Result::Ok(Result::Ok(stdout)) => Check::new(
+                "asciidoctor",
+                CheckStatus::Ok,
+                stdout.lines().next().unwrap_or_default(),
+            ),
+            Result::Ok(Err(err)) => Check::new("asciidoctor", CheckStatus::Missing, err),
+            Err(err) => Check::new("asciidoctor", CheckStatus::Missing, err.to_string()),
+        },
+    );
+
+    // `asciidoctor-diagram`
+    let diagram_required = match book_ron {
+        Some(book_ron) => book_ron
+            .adoc_opts
+            .iter()
+            .any(|(_opt, args)| args.iter().any(|a| a.contains("asciidoctor-diagram"))),
+        None => false,
+    };
+    let missing_status = if diagram_required {
+        CheckStatus::Missing
+    } else {
+        CheckStatus::Warn
+    };
+    checks.push(
+        match command()
+            .map(|mut cmd| self::run(cmd.args(["-r", "asciidoctor-diagram", "-o", "-", "-"])))
+        {
+            Result::Ok(Result::Ok(_stdout)) => {
+                Check::new("asciidoctor-diagram", CheckStatus::Ok, "loadable")
+            }
+            Result::Ok(Err(err)) => Check::new("asciidoctor-diagram", missing_status, err),
+            Err(err) => Check::new("asciidoctor-diagram", missing_status, err.to_string()),
+        },
+    );
+
+    checks
+}
+
+/// Runs the command with empty stdin and returns the stdout, or the stderr on failure
+fn run(cmd: &mut Command) -> std::result::Result<String, String> {
+    let output = cmd
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|err| format!("unable to run: {}", err))?;
+
+    if output.status.success() {
+        std::result::Result::Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(format!(
+            "{} ({})",
+            stderr.lines().next().unwrap_or_default(),
+            output.status
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::{run_checks, CheckStatus};
+    use crate::book::{init, BookStructure};
+
+    #[test]
+    fn missing_adoc_cmd() {
+        let dir = std::env::temp_dir().join("adbook-test-doctor");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        init::gen_init_files(&dir).unwrap();
+
+        let mut book = BookStructure::from_dir(&dir).unwrap();
+        book.book_ron.adoc_cmd = Some("adbook-no-such-asciidoctor".to_string());
+
+        let checks = run_checks(Some(&book.book_ron));
+        assert_eq!(checks.len(), 1);
+        assert_eq!(checks[0].status, CheckStatus::Missing);
+
+        if which::which("sh").is_ok() {
+            // `sh -c 'echo ..' --version`
+            book.book_ron.adoc_cmd = Some("sh".to_string());
+            book.book_ron.adoc_args_prefix =
+                vec!["-c".to_string(), "echo 'Asciidoctor 2.0.17'".to_string()];
+
+            let checks = run_checks(Some(&book.book_ron));
+            assert_eq!(checks[1].status, CheckStatus::Ok);
+            assert_eq!(checks[1].detail, "Asciidoctor 2.0.17");
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,183 @@
+/*!
+`adbook build --dry-run`: what a build would do, without running `asciidoctor` or writing anything
+
+* Source files to convert and ones whose outputs are copied from the cache
+* Files to copy with `includes` and `copies` in `book.ron`, and whether they're up to date
+*/
+
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use colored::*;
+
+use crate::{
+    book::{walk, BookStructure},
+    build::{cache::CacheIndex, visit, BuildOptions},
+    utils,
+};
+
+/// File to copy in the dry run report
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedCopy {
+    pub src: PathBuf,
+    pub dst: PathBuf,
+    /// If the destination is up to date and the copy is skipped
+    pub up_to_date: bool,
+}
+
+/// What `adbook build` would do
+#[derive(Debug, Clone, Default)]
+pub struct DryRunReport {
+    /// Source files to convert
+    pub rebuild: Vec<PathBuf>,
+    /// Source files whose outputs are copied from the cache
+    pub cached: Vec<PathBuf>,
+    /// Files in `includes`
+    pub includes: Vec<PlannedCopy>,
+    /// Files in `copies`
+    pub copies: Vec<PlannedCopy>,
+}
+
+impl DryRunReport {
+    /// Computes the cache diff and the copy plans. Invalid `includes`/`copies` items are returned
+    /// as errors
+    pub fn create(book: &BookStructure, opts: &BuildOptions) -> Result<(Self, Vec<Error>)> {
+        let index = if opts.force_rebuild {
+            CacheIndex::empty()
+        } else {
+            CacheIndex::load(book)?
+        };
+
+        let cache_diff = index.create_diff(book)?;
+        let mut errors = Vec::new();
+
+        let mut report = Self::default();
+        for src_file in walk::list_src_files(book) {
+            if visit::is_cached(book, &cache_diff, &src_file) {
+                report.cached.push(src_file);
+            } else {
+                report.rebuild.push(src_file);
+            }
+        }
+
+        let plan = |files: Vec<(PathBuf, PathBuf)>| {
+            files
+                .into_iter()
+                .map(|(src, dst)| PlannedCopy {
+                    up_to_date: utils::is_up_to_date(&src, &dst),
+                    src,
+                    dst,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        if !opts.cache_only {
+            report.includes = plan(super::plan_includes(
+                &book.src_dir_path(),
+                &book.site_dir_path(),
+                &book.book_ron.includes,
+                &book.ignore,
+                &mut errors,
+            ));
+
+            let mut warns = Vec::new();
+            report.copies = plan(super::plan_copies(
+                &book.root,
+                &book.book_ron.copies,
+                &mut errors,
+                &mut warns,
+            ));
+            errors.extend(warns.into_iter().map(Error::msg));
+        }
+
+        Ok((report, errors))
+    }
+
+    /// Prints the report with paths relative to the root directory
+    pub fn print(&self, book: &BookStructure) {
+        let rel = |path: &Path| -> String {
+            let path = path.strip_prefix(&book.root).unwrap_or(path);
+            format!("{}", path.display())
+        };
+
+        println!(
+            "{} ({} to convert, {} from cache)",
+            "Source files".bold(),
+            self.rebuild.len(),
+            self.cached.len()
+        );
+        for file in &self.rebuild {
+            println!("  {} {}", "convert".yellow(), rel(file));
+        }
+        for file in &self.cached {
+            println!("  {}  {}", "cached".green(), rel(file));
+        }
+
+        for (name, copies) in [("includes", &self.includes), ("copies", &self.copies)] {
+            let n_copies = copies.iter().filter(|c| !c.up_to_date).count();
+            println!(
+                "{} ({} to copy, {} up to date)",
+                format!("Files in `{}`", name).bold(),
+                n_copies,
+                copies.len() - n_copies
+            );
+            for copy in copies {
+                let status = if copy.up_to_date {
+                    "skip".green()
+                } else {
+                    "copy".yellow()
+                };
+                println!("  {}    {} -> {}", status, rel(&copy.src), rel(&copy.dst));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::DryRunReport;
+    use crate::{
+        book::{init, BookStructure},
+        build::BuildOptions,
+    };
+
+    #[test]
+    fn dry_run() {
+        let dir = std::env::temp_dir().join("adbook-test-dry-run");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        init::gen_init_files(&dir).unwrap();
+
+        // as if the site directory was removed
+        fs::remove_dir_all(dir.join("site")).unwrap();
+
+        let mut book = BookStructure::from_dir(&dir).unwrap();
+        let src_dir = book.src_dir_path();
+        fs::create_dir_all(src_dir.join("img")).unwrap();
+        fs::write(src_dir.join("img/a.png"), "a").unwrap();
+        book.book_ron.includes = vec!["img".into()];
+
+        let (report, errors) = DryRunReport::create(&book, &BuildOptions::default()).unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        // no cache yet
+        assert!(report.cached.is_empty());
+        assert_eq!(report.rebuild.len(), 2);
+
+        assert_eq!(report.includes.len(), 1);
+        assert_eq!(
+            report.includes[0].dst,
+            book.site_dir_path().join("img/a.png")
+        );
+        assert!(!report.includes[0].up_to_date);
+
+        // nothing is written
+        assert!(!book.site_dir_path().exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
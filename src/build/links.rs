@@ -0,0 +1,366 @@
+/*!
+Internal link validation of generated HTML files
+
+Every `href` in the site directory is resolved to a file in the site directory. Fragment-only
+links (`#section`) and external links (`https://..`, `mailto:..`, ..) are skipped.
+
+# External links
+
+[`check_external_links`] requests every `http(s)://` link with `curl` (one request per URL). It's
+slow and depends on the network, so it's opt-in (`adbook build --check-external-links`).
+
+# Assets
+
+[`check_assets`] focuses on static assets: `<link href>`, `<script src>` and `<img src>`. Absolute
+paths are resolved with `asset_url` (which falls back to `base_url`), so it catches stylesheets or
+images that are referenced but missing from `includes`.
+*/
+
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use anyhow::*;
+
+/// `href` that doesn't resolve to any file in the site directory
+#[derive(Debug, Clone)]
+pub struct BrokenLink {
+    /// Absolute path to the HTML file that contains the link
+    pub html_file: PathBuf,
+    /// `href` (or `src` of assets)
+    pub href: String,
+}
+
+impl fmt::Display for BrokenLink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}` in {}", self.href, self.html_file.display())
+    }
+}
+
+/// Scans the HTML files in the site directory and returns internal links that don't resolve
+pub fn check_links(site_dir: &Path, base_url: &str) -> Result<Vec<BrokenLink>> {
+    let mut broken = Vec::new();
+
+    crate::utils::visit_files_rec(site_dir, &mut |html_file| {
+        if html_file.extension().and_then(|s| s.to_str()) != Some("html") {
+            return Ok(());
+        }
+
+        let html = fs::read_to_string(html_file)
+            .with_context(|| format!("Unable to read HTML file: {}", html_file.display()))?;
+
+        for href in self::extract_hrefs(&html) {
+            let target = match self::resolve_href(href, html_file, site_dir, base_url) {
+                Some(target) => target,
+                None => continue,
+            };
+
+            if !(target.is_file() || target.join("index.html").is_file()) {
+                broken.push(BrokenLink {
+                    html_file: html_file.to_path_buf(),
+                    href: href.to_string(),
+                });
+            }
+        }
+
+        Ok(())
+    })?;
+
+    Ok(broken)
+}
+
+/// Scans the HTML files in the site directory and returns `http(s)://` links that `curl` fails to
+/// fetch. Each URL is requested once and reported with the first HTML file that contains it
+pub fn check_external_links(site_dir: &Path) -> Result<Vec<BrokenLink>> {
+    let curl = which::which("curl").map_err(|_| anyhow!("`curl` is not in PATH"))?;
+
+    // URL -> the first HTML file with the link
+    let mut urls = BTreeMap::<String, PathBuf>::new();
+
+    crate::utils::visit_files_rec(site_dir, &mut |html_file| {
+        if html_file.extension().and_then(|s| s.to_str()) != Some("html") {
+            return Ok(());
+        }
+
+        let html = fs::read_to_string(html_file)
+            .with_context(|| format!("Unable to read HTML file: {}", html_file.display()))?;
+
+        for href in self::extract_hrefs(&html) {
+            if self::is_http_url(href) && !urls.contains_key(href) {
+                urls.insert(href.to_string(), html_file.to_path_buf());
+            }
+        }
+
+        Ok(())
+    })?;
+
+    let mut broken = Vec::new();
+    for (url, html_file) in urls {
+        log::debug!("checking external link: {}", url);
+
+        // HEAD request following redirects
+        let status = Command::new(&curl)
+            .args([
+                "--silent",
+                "--fail",
+                "--head",
+                "--location",
+                "--max-time",
+                "10",
+            ])
+            .arg(&url)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .context("Unable to run `curl`")?;
+
+        if !status.success() {
+            broken.push(BrokenLink {
+                html_file,
+                href: url,
+            });
+        }
+    }
+
+    Ok(broken)
+}
+
+/// `http://..` or `https://..`
+fn is_http_url(href: &str) -> bool {
+    href.starts_with("http://") || href.starts_with("https://")
+}
+
+/// Scans the HTML files in the site directory and returns asset references that don't resolve
+///
+/// * `asset_url`: URL prefix of the assets (`asset_url` or `base_url` in `book.ron`)
+pub fn check_assets(site_dir: &Path, asset_url: &str) -> Result<Vec<BrokenLink>> {
+    let mut missing = Vec::new();
+
+    crate::utils::visit_files_rec(site_dir, &mut |html_file| {
+        if html_file.extension().and_then(|s| s.to_str()) != Some("html") {
+            return Ok(());
+        }
+
+        let html = fs::read_to_string(html_file)
+            .with_context(|| format!("Unable to read HTML file: {}", html_file.display()))?;
+
+        for src in self::extract_asset_refs(&html) {
+            let target = match self::resolve_href(src, html_file, site_dir, asset_url) {
+                Some(target) => target,
+                None => continue,
+            };
+
+            if !target.is_file() {
+                missing.push(BrokenLink {
+                    html_file: html_file.to_path_buf(),
+                    href: src.to_string(),
+                });
+            }
+        }
+
+        Ok(())
+    })?;
+
+    Ok(missing)
+}
+
+/// Extracts asset paths from an HTML string: `<link href>`, `<script src>` and `<img src>`
+pub fn extract_asset_refs(html: &str) -> Vec<&str> {
+    let mut refs = Vec::new();
+    let mut rest = html;
+
+    while let Some(pos) = rest.find('<') {
+        rest = &rest[pos + 1..];
+        let end = match rest.find('>') {
+            Some(end) => end,
+            None => break,
+        };
+        let tag = &rest[..end];
+        rest = &rest[end + 1..];
+
+        let name = tag
+            .split(|c: char| c.is_whitespace())
+            .next()
+            .unwrap_or("")
+            .to_lowercase();
+
+        let attr = match name.as_str() {
+            "link" => "href",
+            "script" | "img" => "src",
+            _ => continue,
+        };
+
+        if let Some(value) = self::attr_value(tag, attr) {
+            refs.push(value);
+        }
+    }
+
+    refs
+}
+
+/// Finds a quoted attribute value in the inside of a tag (`name attr="value" ..`)
+fn attr_value<'a>(tag: &'a str, attr: &str) -> Option<&'a str> {
+    let pattern = format!("{}=", attr);
+    let mut offset = 0;
+
+    while let Some(pos) = tag[offset..].find(&pattern) {
+        let start = offset + pos;
+        offset = start + pattern.len();
+
+        // skip `data-src=` and such
+        if !tag[..start].ends_with(|c: char| c.is_whitespace()) {
+            continue;
+        }
+
+        let rest = &tag[offset..];
+        let quote = match rest.chars().next() {
+            Some(c) if c == '"' || c == '\'' => c,
+            _ => continue,
+        };
+
+        let rest = &rest[1..];
+        return rest.find(quote).map(|end| &rest[..end]);
+    }
+
+    None
+}
+
+/// Extracts `href` attribute values from an HTML string
+pub fn extract_hrefs(html: &str) -> Vec<&str> {
+    let mut hrefs = Vec::new();
+    let mut rest = html;
+
+    while let Some(pos) = rest.find("href=") {
+        rest = &rest[pos + "href=".len()..];
+
+        let quote = match rest.chars().next() {
+            Some(c) if c == '"' || c == '\'' => c,
+            _ => continue,
+        };
+
+        rest = &rest[1..];
+        let end = match rest.find(quote) {
+            Some(end) => end,
+            None => break,
+        };
+
+        hrefs.push(&rest[..end]);
+        rest = &rest[end + 1..];
+    }
+
+    hrefs
+}
+
+/// Maps an internal `href` to a path in the site directory. Returns `None` for links to skip.
+fn resolve_href(href: &str, html_file: &Path, site_dir: &Path, base_url: &str) -> Option<PathBuf> {
+    // fragment-only link
+    if href.is_empty() || href.starts_with('#') {
+        return None;
+    }
+
+    // external link (`https://`, `//host`, `mailto:`, ..)
+    if href.starts_with("//") || href.contains(':') {
+        return None;
+    }
+
+    // strip fragment and query
+    let path = href.split(['#', '?']).next().unwrap_or("");
+    if path.is_empty() {
+        return None;
+    }
+
+    if let Some(abs) = path.strip_prefix('/') {
+        // absolute path: it has to be under the `base_url`
+        let base = base_url.trim_matches('/');
+        let rel = if base.is_empty() {
+            abs
+        } else {
+            let rest = abs.strip_prefix(base)?;
+            if !(rest.is_empty() || rest.starts_with('/')) {
+                return None;
+            }
+            rest.trim_start_matches('/')
+        };
+        Some(site_dir.join(rel))
+    } else {
+        // relative path from the HTML file
+        Some(html_file.parent()?.join(path))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::Path};
+
+    use super::{check_assets, extract_asset_refs, extract_hrefs, resolve_href};
+
+    #[test]
+    fn hrefs() {
+        let html = r#"<a href="a.html">a</a> <link href='/base/b.css'> <a href=noquote>"#;
+        assert_eq!(extract_hrefs(html), vec!["a.html", "/base/b.css"]);
+    }
+
+    #[test]
+    fn asset_refs() {
+        let html = r#"<link rel="stylesheet" href="a.css"><script src='b.js'></script>
+<img data-src="lazy.png" src="c.png"> <a href="d.html"> <img alt="no src">"#;
+        assert_eq!(extract_asset_refs(html), vec!["a.css", "b.js", "c.png"]);
+    }
+
+    #[test]
+    fn missing_assets() {
+        let dir = std::env::temp_dir().join("adbook-test-missing-assets");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+
+        // `theme/css/extra.css` is referenced, but it's not in `includes`
+        let site_dir = dir.join("site");
+        fs::create_dir_all(site_dir.join("theme/css")).unwrap();
+        fs::create_dir_all(site_dir.join("static/img")).unwrap();
+        fs::write(site_dir.join("theme/css/article.css"), "").unwrap();
+        fs::write(site_dir.join("static/img/a.png"), "").unwrap();
+        fs::write(
+            site_dir.join("index.html"),
+            r#"<link rel="stylesheet" href="/base/theme/css/article.css">
+<link rel="stylesheet" href="/base/theme/css/extra.css">
+<link rel="canonical" href="https://example.com/">
+<img src="static/img/a.png">"#,
+        )
+        .unwrap();
+
+        let missing = check_assets(&site_dir, "/base").unwrap();
+        assert_eq!(missing.len(), 1);
+        assert_eq!(missing[0].href, "/base/theme/css/extra.css");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn resolve() {
+        let site = Path::new("/site");
+        let html = Path::new("/site/sub/x.html");
+
+        assert_eq!(resolve_href("#top", html, site, "/base"), None);
+        assert_eq!(resolve_href("https://a.b", html, site, "/base"), None);
+        assert_eq!(resolve_href("mailto:a@b", html, site, "/base"), None);
+        assert_eq!(resolve_href("/other/a.html", html, site, "/base"), None);
+        assert_eq!(resolve_href("/basement/a.html", html, site, "/base"), None);
+
+        assert_eq!(
+            resolve_href("y.html#sec", html, site, "/base"),
+            Some(Path::new("/site/sub/y.html").to_path_buf())
+        );
+        assert_eq!(
+            resolve_href("/base/theme/a.css", html, site, "/base"),
+            Some(Path::new("/site/theme/a.css").to_path_buf())
+        );
+        assert_eq!(
+            resolve_href("/theme/a.css", html, site, ""),
+            Some(Path::new("/site/theme/a.css").to_path_buf())
+        );
+    }
+}
@@ -0,0 +1,141 @@
+/*!
+Build manifest for reproducibility audits
+
+With `manifest: true` in `book.ron`, `.adbook-cache/manifest.json` is written after a build. It
+maps each source file to its output file:
+
+```json
+{
+  "config_fingerprint": "<SHA-256 of `book.ron` and the build profile>",
+  "entries": [
+    {
+      "src": "article.adoc",
+      "output": "article.html",
+      "src_hash": "<SHA-256 of the source file>",
+      "output_hash": "<SHA-256 of the output file>",
+      "template": "theme/hbs/article.hbs"
+    }
+  ]
+}
+```
+
+`template` is `"<default>"` for the default theme and `null` if no template is applied. Diff two
+manifests to see what changed between builds.
+*/
+
+use std::{fs, path::Path};
+
+use anyhow::*;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    book::BookStructure,
+    build::{
+        cache::{self, CacheIndex},
+        convert::{self, AdocRunContext},
+    },
+};
+
+/// Serialized to `manifest.json`
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    /// SHA-256 of `book.ron` and the build profile
+    pub config_fingerprint: String,
+    pub entries: Vec<ManifestEntry>,
+}
+
+/// Source file → output file
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    /// Relative path from the source directory
+    pub src: String,
+    /// Relative path from the site directory
+    pub output: String,
+    pub src_hash: String,
+    pub output_hash: String,
+    pub template: Option<String>,
+}
+
+/// Hex string of the SHA-256 hash of `book.ron` and the active build profile
+///
+/// If the book is constructed in memory without `book.ron`, the serialized [`BookRon`] is hashed
+/// instead.
+///
+/// [`BookRon`]: crate::book::config::BookRon
+pub fn config_fingerprint(book: &BookStructure) -> Result<String> {
+    let book_ron = book.root.join("book.ron");
+    let bytes = if book_ron.is_file() {
+        fs::read(&book_ron).with_context(|| format!("Unable to read {}", book_ron.display()))?
+    } else {
+        ron::to_string(&book.book_ron)
+            .context("Unable to serialize `BookRon`")?
+            .into_bytes()
+    };
+
+    let mut hasher = Sha256::new();
+    hasher.update(&bytes);
+    hasher.update(b"\0");
+    hasher.update(book.profile.as_deref().unwrap_or("").as_bytes());
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+impl Manifest {
+    /// Creates the manifest of built source files. Output files have to be written already
+    pub fn create(book: &BookStructure, src_files: &[impl AsRef<Path>]) -> Result<Self> {
+        let acx = AdocRunContext::from_book(book)?;
+        let src_dir = book.src_dir_path();
+
+        let mut entries = Vec::with_capacity(src_files.len());
+        for src_file in src_files {
+            let src_file = src_file.as_ref();
+
+            let rel_src = src_file.strip_prefix(&src_dir).with_context(|| {
+                format!(
+                    "Source file out of source directory: {}",
+                    src_file.display()
+                )
+            })?;
+            let rel_output = book.src_file_to_dst_rel(src_file)?;
+
+            let metadata = convert::extract_metadata(src_file, &acx, book)?;
+
+            entries.push(ManifestEntry {
+                src: self::slash_path(rel_src),
+                output: self::slash_path(&rel_output),
+                src_hash: cache::content_hash(src_file)?,
+                output_hash: cache::content_hash(&book.site_dir_path().join(&rel_output))?,
+                template: convert::template_name(book, &acx, &metadata),
+            });
+        }
+
+        Ok(Self {
+            config_fingerprint: self::config_fingerprint(book)?,
+            entries,
+        })
+    }
+
+    /// Writes `.adbook-cache/manifest.json`
+    pub fn write(&self, book: &BookStructure) -> Result<()> {
+        let json =
+            serde_json::to_string_pretty(self).context("Unable to serialize `manifest.json`")?;
+
+        let path = CacheIndex::locate_root(book).join("manifest.json");
+        fs::write(&path, json).with_context(|| format!("Unable to write {}", path.display()))?;
+
+        Ok(())
+    }
+}
+
+/// Path string with `/` as the separator
+fn slash_path(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
@@ -0,0 +1,198 @@
+/*!
+Conservative HTML minifier (`minify` in `book.ron`)
+
+It only removes what can't change the rendering:
+
+* Whitespace runs in text are collapsed into a single space (or a single newline if the run
+  contains one), as browsers do for `white-space: normal`.
+* Comments are removed, except for conditional comments (`<!--[if IE]>`).
+
+Tags (and so attribute values) are left as they are, and the contents of `<pre>`, `<code>`,
+`<textarea>`, `<script>` and `<style>` are preserved verbatim.
+*/
+
+/// Elements whose contents are preserved verbatim
+const PRESERVED_ELEMENTS: &[&str] = &["pre", "code", "textarea", "script", "style"];
+
+/// Minifies an HTML string
+pub fn minify_html(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        // text
+        let text_end = rest.find('<').unwrap_or(rest.len());
+        self::push_collapsed(&mut out, &rest[..text_end]);
+        rest = &rest[text_end..];
+
+        if rest.is_empty() {
+            break;
+        }
+
+        // comment
+        if rest.starts_with("<!--") && !rest.starts_with("<!--[if") {
+            rest = match rest.find("-->") {
+                Some(end) => &rest[end + "-->".len()..],
+                None => "",
+            };
+            // merge the whitespaces around the comment
+            if out.ends_with(|c: char| c.is_ascii_whitespace()) {
+                rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace());
+            }
+            continue;
+        }
+
+        // tag
+        let tag_end = match self::find_tag_end(rest) {
+            Some(end) => end + 1,
+            None => {
+                out.push_str(rest);
+                break;
+            }
+        };
+        let tag = &rest[..tag_end];
+        out.push_str(tag);
+        rest = &rest[tag_end..];
+
+        // preserve the contents
+        let name = self::tag_name(tag);
+        if let Some(preserved) = PRESERVED_ELEMENTS
+            .iter()
+            .find(|e| e.eq_ignore_ascii_case(name))
+        {
+            let close = format!("</{}", preserved);
+            let end = self::find_ignore_ascii_case(rest, &close).unwrap_or(rest.len());
+            out.push_str(&rest[..end]);
+            rest = &rest[end..];
+        }
+    }
+
+    out
+}
+
+/// Position of the `>` that closes the tag at the start of the string. `>` in quoted attribute
+/// values (`alt="a > b"`) is skipped
+fn find_tag_end(s: &str) -> Option<usize> {
+    let mut quote: Option<u8> = None;
+    // if the last non-whitespace byte is `=`, where a quoted value can start
+    let mut after_eq = false;
+
+    for (i, b) in s.bytes().enumerate() {
+        match quote {
+            Some(q) if b == q => quote = None,
+            Some(_) => {}
+            None => match b {
+                b'>' => return Some(i),
+                b'"' | b'\'' if after_eq => quote = Some(b),
+                _ if b.is_ascii_whitespace() => continue,
+                _ => {}
+            },
+        }
+        after_eq = b == b'=';
+    }
+
+    None
+}
+
+/// Byte position of the ASCII `pattern` in the string, ignoring ASCII case
+fn find_ignore_ascii_case(s: &str, pattern: &str) -> Option<usize> {
+    s.as_bytes()
+        .windows(pattern.len())
+        .position(|window| window.eq_ignore_ascii_case(pattern.as_bytes()))
+}
+
+/// `pre` of `<pre class="..">`. Empty for closing tags, comments and doctypes
+fn tag_name(tag: &str) -> &str {
+    let tag = &tag[1..tag.len() - 1];
+    let end = tag
+        .find(|c: char| !c.is_ascii_alphanumeric())
+        .unwrap_or(tag.len());
+    &tag[..end]
+}
+
+/// Pushes text collapsing whitespace runs
+fn push_collapsed(out: &mut String, text: &str) {
+    let mut whitespace: Option<char> = None;
+
+    for c in text.chars() {
+        if c.is_ascii_whitespace() {
+            whitespace = match whitespace {
+                Some('\n') => Some('\n'),
+                _ if c == '\n' || c == '\r' => Some('\n'),
+                _ => Some(' '),
+            };
+            continue;
+        }
+
+        if let Some(ws) = whitespace.take() {
+            out.push(ws);
+        }
+        out.push(c);
+    }
+
+    if let Some(ws) = whitespace {
+        out.push(ws);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::minify_html;
+
+    #[test]
+    fn minify() {
+        let html = r#"<!DOCTYPE html>
+<html>
+    <head>
+        <!-- comment -->
+        <!--[if IE]><p>IE</p><![endif]-->
+        <style>
+            p  { color: red; }
+        </style>
+    </head>
+    <body>
+        <p class="a  b">Some   text
+            <em>here</em> </p>
+        <pre><code>fn main() {
+    println!("  indented  ");
+}</code></pre>
+        <p><code>a  b</code></p>
+    </body>
+</html>
+"#;
+
+        let expected = r#"<!DOCTYPE html>
+<html>
+<head>
+<!--[if IE]><p>IE</p><![endif]-->
+<style>
+            p  { color: red; }
+        </style>
+</head>
+<body>
+<p class="a  b">Some text
+<em>here</em> </p>
+<pre><code>fn main() {
+    println!("  indented  ");
+}</code></pre>
+<p><code>a  b</code></p>
+</body>
+</html>
+"#;
+
+        assert_eq!(minify_html(html), expected);
+    }
+
+    #[test]
+    fn quoted_attributes() {
+        let html = "<img alt=\"a > b   c\" src=\"x.png\">  text   here <a title='1>0'>x</a>";
+        assert_eq!(
+            minify_html(html),
+            "<img alt=\"a > b   c\" src=\"x.png\"> text here <a title='1>0'>x</a>"
+        );
+
+        // closing tags of preserved elements in any case
+        let html = "<PRE>a   b</Pre>  c   d";
+        assert_eq!(minify_html(html), "<PRE>a   b</Pre> c d");
+    }
+}
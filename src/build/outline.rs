@@ -0,0 +1,59 @@
+/*!
+Reader-facing outline of a book
+
+It's the sidebar structure (titles and URLs) without running `asciidoctor`. Titles are extracted
+from the first lines of source files, so it's fast.
+*/
+
+use anyhow::*;
+use serde::Serialize;
+
+use crate::{
+    book::BookStructure,
+    build::convert::hbs::{Sidebar, SidebarItem},
+};
+
+/// Item in the outline of a book
+#[derive(Serialize, Debug, Clone)]
+pub struct OutlineItem {
+    pub title: String,
+    /// `{base_url}/path/to/article.html`, or an external URL
+    pub url: Option<String>,
+    /// If the item is a link to an external resource
+    pub external: bool,
+    pub children: Vec<OutlineItem>,
+}
+
+impl OutlineItem {
+    fn from_sidebar_item(item: &SidebarItem) -> Self {
+        Self {
+            title: item.name.clone(),
+            url: item.url.clone(),
+            external: item.external,
+            children: item
+                .children
+                .iter()
+                .flat_map(|xs| xs.iter())
+                .map(Self::from_sidebar_item)
+                .collect(),
+        }
+    }
+}
+
+/// Creates the outline of a book
+pub fn outline(book: &BookStructure) -> (Vec<OutlineItem>, Vec<Error>) {
+    let (sidebar, errors) = Sidebar::from_book(book);
+    let items = sidebar
+        .items()
+        .iter()
+        .map(OutlineItem::from_sidebar_item)
+        .collect();
+    (items, errors)
+}
+
+/// Creates the outline of a book as a JSON string
+pub fn outline_json(book: &BookStructure) -> Result<(String, Vec<Error>)> {
+    let (items, errors) = self::outline(book);
+    let json = serde_json::to_string_pretty(&items).context("Unable to serialize outline")?;
+    Ok((json, errors))
+}
@@ -0,0 +1,247 @@
+/*!
+Search index for client-side search
+
+`search_index.json` is a flat array of articles, so that a small script can fetch it and do
+substring matching:
+
+```json
+[
+  {
+    "url": "/base_url/path/to/article.html",
+    "title": "Article title",
+    "body": "Plain text of the article"
+  }
+]
+```
+
+The body is extracted from the rendered HTML: the contents of `<main>` (or `<body>`) with the
+tags stripped and the character references decoded. `<script>`, `<style>` and `<nav>` (the
+sidebar) are skipped with their contents. Pages with the `noindex` attribute are not indexed.
+*/
+
+use std::{fs, path::Path};
+
+use anyhow::*;
+use serde::Serialize;
+
+use crate::book::walk::BuildOutput;
+
+/// Element of `search_index.json`
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct SearchEntry {
+    pub url: String,
+    /// Document title. Empty if the article doesn't have one
+    pub title: String,
+    /// Plain text of the article
+    pub body: String,
+}
+
+impl SearchEntry {
+    /// Returns `None` if the page has the `noindex` attribute
+    pub fn from_output(output: &BuildOutput) -> Option<Self> {
+        if output.metadata.noindex {
+            return None;
+        }
+
+        Some(Self {
+            url: output.metadata.url.clone(),
+            title: output.metadata.title.clone().unwrap_or_default(),
+            body: self::html_to_text(&output.string),
+        })
+    }
+}
+
+/// Writes `search_index.json` to the site directory
+pub fn write_search_index(entries: &[SearchEntry], site_dir: &Path) -> Result<()> {
+    let json = serde_json::to_string(entries).context("Unable to serialize `search_index.json`")?;
+
+    let path = site_dir.join("search_index.json");
+    fs::write(&path, json).with_context(|| format!("Unable to write {}", path.display()))?;
+
+    Ok(())
+}
+
+/// Elements skipped with their contents
+const SKIPPED_ELEMENTS: &[&str] = &["script", "style", "nav"];
+
+/// Elements that don't separate words
+const INLINE_ELEMENTS: &[&str] = &[
+    "a", "abbr", "b", "code", "em", "i", "kbd", "mark", "small", "span", "strong", "sub", "sup",
+    "u",
+];
+
+/// Extracts the plain text of the main contents of an HTML page. Whitespaces are collapsed into
+/// single spaces
+pub fn html_to_text(html: &str) -> String {
+    let html = self::inner_html(html, "main")
+        .or_else(|| self::inner_html(html, "body"))
+        .unwrap_or(html);
+
+    let mut text = String::with_capacity(html.len() / 2);
+    let mut rest = html;
+
+    while let Some(start) = rest.find('<') {
+        text.push_str(&self::decode_entities(&rest[..start]));
+        rest = &rest[start..];
+
+        let end = match rest.find('>') {
+            Some(end) => end,
+            None => {
+                rest = "";
+                break;
+            }
+        };
+
+        let name = self::tag_name(&rest[1..end]);
+        rest = &rest[end + 1..];
+
+        // separate words unless it's an inline element
+        if !INLINE_ELEMENTS.iter().any(|e| e.eq_ignore_ascii_case(name)) {
+            text.push(' ');
+        }
+
+        // skip the contents
+        if let Some(skipped) = SKIPPED_ELEMENTS
+            .iter()
+            .find(|e| e.eq_ignore_ascii_case(name))
+        {
+            let close = format!("</{}", skipped);
+            rest = match rest.to_ascii_lowercase().find(&close) {
+                Some(pos) => &rest[pos..],
+                None => "",
+            };
+        }
+    }
+    text.push_str(&self::decode_entities(rest));
+
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Contents of the first element with the name
+fn inner_html<'a>(html: &'a str, name: &str) -> Option<&'a str> {
+    let lower = html.to_ascii_lowercase();
+
+    let open = lower
+        .match_indices(&format!("<{}", name))
+        .map(|(pos, _)| pos)
+        .find(|pos| {
+            // not a prefix of another tag name (`<mainly>`)
+            matches!(
+                lower.as_bytes().get(pos + name.len() + 1),
+                Some(b'>' | b' ' | b'\t' | b'\n')
+            )
+        })?;
+    let start = open + lower[open..].find('>')? + 1;
+    let end = start + lower[start..].rfind(&format!("</{}", name))?;
+
+    Some(&html[start..end])
+}
+
+/// `div` of `<div class="..">` or `</div>`
+fn tag_name(tag: &str) -> &str {
+    let tag = tag.trim_start_matches('/');
+    let end = tag
+        .find(|c: char| c.is_ascii_whitespace() || c == '/')
+        .unwrap_or(tag.len());
+    &tag[..end]
+}
+
+/// Decodes character references: `&amp;`, `&#8217;`, `&#x2019;`, etc.
+pub fn decode_entities(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let decoded = rest
+            .find(';')
+            .filter(|end| *end <= 10)
+            .and_then(|end| Some((self::decode_entity(&rest[1..end])?, end)));
+
+        match decoded {
+            Some((c, end)) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+
+    out
+}
+
+/// `amp` -> `&`, `#39` -> `'`, `#x27` -> `'`
+fn decode_entity(name: &str) -> Option<char> {
+    if let Some(code) = name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")) {
+        return u32::from_str_radix(code, 16).ok().and_then(char::from_u32);
+    }
+
+    if let Some(code) = name.strip_prefix('#') {
+        return code.parse().ok().and_then(char::from_u32);
+    }
+
+    let c = match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "copy" => '©',
+        "hellip" => '…',
+        "mdash" => '—',
+        "ndash" => '–',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        _ => return None,
+    };
+
+    Some(c)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode_entities, html_to_text};
+
+    #[test]
+    fn entities() {
+        assert_eq!(
+            decode_entities("Tom &amp; Jerry&#8217;s &lt;tag&gt; &#x2014; &unknown; & co"),
+            "Tom & Jerry’s <tag> — &unknown; & co"
+        );
+    }
+
+    #[test]
+    fn main_text() {
+        let html = r#"<!DOCTYPE html>
+<html>
+<head><title>Title</title><style>p { color: red; }</style></head>
+<body>
+<nav id="sidebar"><ol><li><a href="/a.html">Sidebar item</a></li></ol></nav>
+<div id="content">
+<main>
+<h2 id="_section">Section</h2>
+<div class="paragraph"><p>Fish &amp; chips,
+  <code>x &lt; y</code></p><p><strong>Bold</strong>ly</p></div>
+<script>var inMain = "<p>not text</p>";</script>
+</main>
+</div>
+<script src="/theme/js/prism.js"></script>
+</body>
+</html>"#;
+
+        assert_eq!(html_to_text(html), "Section Fish & chips, x < y Boldly");
+
+        // fallback to `<body>`
+        let html = "<html><body><p>Body</p><nav>Nav</nav></body></html>";
+        assert_eq!(html_to_text(html), "Body");
+    }
+}
@@ -0,0 +1,179 @@
+/*!
+`structure.json`, the navigation hierarchy of a book
+
+It mirrors the `index.ron` tree rather than the rendered sidebar:
+
+```json
+{
+  "name": "Index",
+  "url": "index.html",
+  "summary": "index.adoc",
+  "children": [
+    { "name": "Article", "url": "article.html", "summary": "article.adoc", "children": [] },
+    { "name": "External", "url": "https://example.com", "summary": null, "children": [] }
+  ]
+}
+```
+
+* `url`: path of the output file relative to the site directory, or the URL of an external link
+* `summary`: path of the source file relative to the source directory (the summary file for a
+  directory). `null` for external links
+
+Empty names are filled with the titles of the source files. Ignored files and drafts are excluded
+as they are in the sidebar.
+*/
+
+use std::{fs, path::Path};
+
+use anyhow::*;
+use serde::Serialize;
+
+use crate::{
+    book::{
+        index::{Index, IndexItem},
+        BookStructure,
+    },
+    build::convert::hbs::Sidebar,
+};
+
+/// Node of `structure.json`
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct StructureNode {
+    pub name: String,
+    pub url: String,
+    pub summary: Option<String>,
+    pub children: Vec<StructureNode>,
+}
+
+impl StructureNode {
+    /// Creates the tree from the root [`Index`]. Items that failed to load are skipped and the
+    /// errors are returned
+    pub fn from_book(book: &BookStructure) -> Result<(Self, Vec<Error>)> {
+        let mut errors = Vec::new();
+        let root = self::map_index(&book.index, book, &mut errors)?;
+        Ok((root, errors))
+    }
+}
+
+fn map_index(
+    index: &Index,
+    book: &BookStructure,
+    errors: &mut Vec<Error>,
+) -> Result<StructureNode> {
+    let mut node = self::map_file(&index.name, &index.summary, book)?;
+
+    for item in &index.items {
+        let excluded = match item {
+            IndexItem::File(_name, file) => book.is_excluded(file),
+            IndexItem::Dir(index) => book.is_excluded(&index.summary),
+            IndexItem::Link(_name, _url) => false,
+        };
+        if excluded {
+            continue;
+        }
+
+        let child = match item {
+            IndexItem::File(name, file) => self::map_file(name, file, book),
+            IndexItem::Dir(index) => self::map_index(index, book, errors),
+            IndexItem::Link(name, url) => Ok(StructureNode {
+                name: name.clone(),
+                url: url.clone(),
+                summary: None,
+                children: Vec::new(),
+            }),
+        };
+
+        match child {
+            Result::Ok(child) => node.children.push(child),
+            Err(err) => errors.push(err),
+        }
+    }
+
+    Ok(node)
+}
+
+fn map_file(name: &str, file: &Path, book: &BookStructure) -> Result<StructureNode> {
+    let src_dir = book.src_dir_path();
+    let summary = file.strip_prefix(&src_dir).unwrap_or(file);
+
+    Ok(StructureNode {
+        name: Sidebar::get_title(name, file)?,
+        url: self::slash_path(&book.src_file_to_dst_rel(file)?),
+        summary: Some(self::slash_path(summary)),
+        children: Vec::new(),
+    })
+}
+
+/// Path delimited with `/`
+fn slash_path(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Writes `structure.json` to the site directory
+pub fn write_structure_json(book: &BookStructure, site_dir: &Path) -> Result<Vec<Error>> {
+    let (root, errors) = StructureNode::from_book(book)?;
+    let json =
+        serde_json::to_string_pretty(&root).context("Unable to serialize `structure.json`")?;
+
+    let path = site_dir.join("structure.json");
+    fs::write(&path, json).with_context(|| format!("Unable to write {}", path.display()))?;
+
+    Ok(errors)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::StructureNode;
+    use crate::book::{init, BookStructure};
+
+    #[test]
+    fn structure() {
+        let dir = std::env::temp_dir().join("adbook-test-structure");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        init::gen_init_files(&dir).unwrap();
+
+        let src_dir = dir.join("src");
+        fs::create_dir_all(src_dir.join("sub")).unwrap();
+        fs::write(src_dir.join("sub/index.adoc"), "= Sub\n").unwrap();
+        fs::write(src_dir.join("sub/a.adoc"), "= A\n").unwrap();
+        fs::write(
+            src_dir.join("sub/index.ron"),
+            r#"summary: ("", "index.adoc"), items: [File("", "a.adoc")]"#,
+        )
+        .unwrap();
+        fs::write(
+            src_dir.join("index.ron"),
+            r#"summary: ("", "index.adoc"), items: [File("", "article.adoc"), Dir("sub"), Link("Rust", "https://www.rust-lang.org")]"#,
+        )
+        .unwrap();
+
+        let book = BookStructure::from_dir(&dir).unwrap();
+        let (root, errors) = StructureNode::from_book(&book).unwrap();
+        assert!(errors.is_empty());
+
+        assert_eq!(root.url, "index.html");
+        assert_eq!(root.summary.as_deref(), Some("index.adoc"));
+        assert_eq!(root.children.len(), 3);
+
+        let sub = &root.children[1];
+        assert_eq!(sub.name, "Sub");
+        assert_eq!(sub.url, "sub/index.html");
+        assert_eq!(sub.summary.as_deref(), Some("sub/index.adoc"));
+        assert_eq!(sub.children[0].name, "A");
+        assert_eq!(sub.children[0].url, "sub/a.html");
+
+        let link = &root.children[2];
+        assert_eq!(link.url, "https://www.rust-lang.org");
+        assert_eq!(link.summary, None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
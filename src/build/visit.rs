@@ -4,18 +4,24 @@ Implementation of [`crate::book::walk::BookBuilder`]
 TODO: Enable other source formats than Asciidoc
 */
 
-use std::{fs, io::prelude::*, path::Path};
+use std::{
+    fs,
+    io::prelude::*,
+    path::{Path, PathBuf},
+    time::Instant,
+};
 
 use anyhow::{anyhow, Context, Error, Result};
 
 use crate::{
     book::{
-        walk::{BookBuilder, BuildError, BuildOutput, BuildResult},
+        config::OnConvertError,
+        walk::{self, BookBuilder, BuildError, BuildOutput, BuildResult, PageMetadata},
         BookStructure,
     },
     build::{
-        cache::{CacheIndex, CacheIndexDiff},
-        convert::{hbs::HbsContext, AdocRunContext},
+        cache::{CacheIndex, CacheIndexDiff, SharedCacheIndex},
+        convert::{hbs::HbsContext, AdocError, AdocMetadata, AdocRunContext},
     },
 };
 
@@ -24,28 +30,31 @@ use crate::{
 pub struct AdocBookBuilder {
     book: BookStructure,
     pub(crate) cache_diff: CacheIndexDiff,
+    /// Updated as each file is built
+    pub(crate) cache_index: SharedCacheIndex,
     // context to run `asciidoctor` and Handlebars
     acx: AdocRunContext,
     hcx: HbsContext,
 }
 
 impl AdocBookBuilder {
-    pub fn from_book(
-        book: &BookStructure,
-        cache_diff: CacheIndexDiff,
-    ) -> Result<(Self, Vec<Error>)> {
+    pub fn from_book(book: &BookStructure, cache_index: CacheIndex) -> Result<(Self, Vec<Error>)> {
+        let cache_diff = cache_index.create_diff(book)?;
+
         let acx = AdocRunContext::from_book(book)?;
         log::trace!("asciidoctor context created");
         // log::trace!("{:#?}", acx);
 
-        let (hcx, errors) = HbsContext::from_book(book);
+        let (hcx, mut errors) = HbsContext::from_book(book);
         log::trace!("handlebars context created");
+        errors.extend(acx.undefined_env_vars());
         // log::trace!("{:#?}", hcx);
 
         Ok((
             Self {
                 book: book.clone(),
                 cache_diff,
+                cache_index: SharedCacheIndex::new(cache_index),
                 acx,
                 hcx,
             },
@@ -53,22 +62,109 @@ impl AdocBookBuilder {
         ))
     }
 
-    fn convert_file_into_buf(&mut self, buf: &mut String, src_file: &Path) -> Result<()> {
-        crate::build::convert::convert_adoc_buf(buf, src_file, &self.acx, &self.hcx, &self.book)?;
+    /// Turns conversion warnings such as unresolved includes into errors
+    pub fn set_strict(&mut self, strict: bool) {
+        self.acx.set_strict(strict);
+    }
+
+    /// Converts the files to build with a single `asciidoctor` process (`batch_convert` in
+    /// `book.ron`). On failure, the files are converted one by one as usual
+    pub fn batch_convert(&mut self) -> Result<()> {
+        let src_files = walk::list_src_files(&self.book)
+            .into_iter()
+            .filter(|src_file| !self.can_skip_build(src_file))
+            .collect::<Vec<_>>();
+
+        let out_dir = CacheIndex::locate_root(&self.book).join("batch");
+        if out_dir.exists() {
+            fs::remove_dir_all(&out_dir)
+                .with_context(|| format!("Unable to clear directory: {}", out_dir.display()))?;
+        }
+
+        let start = Instant::now();
+        match crate::build::convert::batch_convert(&src_files, &out_dir, &self.acx, &self.book) {
+            Result::Ok(outputs) => {
+                log::info!(
+                    "Converted {} file(s) in a batch in {:.2}s",
+                    outputs.len(),
+                    start.elapsed().as_secs_f64()
+                );
+                self.acx.set_batch_outputs(outputs);
+            }
+            Err(err) => {
+                log::warn!(
+                    "Batch conversion failed. Converting files one by one: {:#}",
+                    err
+                );
+            }
+        }
+
         Ok(())
     }
 
-    fn convert_file_impl(&mut self, src_file: &Path) -> Result<String> {
-        let mut buf = String::with_capacity(1024 * 5);
+    fn convert_file_into_buf(&mut self, buf: &mut String, src_file: &Path) -> Result<AdocMetadata> {
+        crate::build::convert::convert_adoc_buf(buf, src_file, &self.acx, &self.hcx, &self.book)
+    }
 
-        if self.can_skip_build(src_file) {
-            // just copy
-            let src_dir = self.book.src_dir_path();
-            let rel_path = src_file.strip_prefix(&src_dir)?;
+    fn page_metadata(&self, src_file: &Path, meta: &AdocMetadata) -> Result<PageMetadata> {
+        fn attr(name: &str, meta: &AdocMetadata) -> Option<String> {
+            meta.find_attr(name)
+                .and_then(|a| a.value().map(|s| s.to_string()))
+        }
+
+        let url = self.book.src_file_to_url(src_file)?;
+
+        Ok(PageMetadata {
+            title: meta.title.clone(),
+            url,
+            author: attr("author", meta),
+            date: attr("revdate", meta),
+            description: attr("description", meta),
+            noindex: meta.is_set("noindex"),
+        })
+    }
+
+    /// Writes the output to the cache file and records it in the cache index
+    fn write_cache(&self, src_file: &Path, cached_file: &Path, output: &str) -> Result<()> {
+        if let Some(dir) = cached_file.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Unable to create directory: {}", dir.display()))?;
+        }
+
+        fs::write(cached_file, output)
+            .with_context(|| format!("Unable to write cache: {}", cached_file.display()))?;
+
+        self.cache_index
+            .record_built(&self.cache_diff, &self.book, src_file)
+    }
+
+    /// Creates a placeholder page for a file that failed to convert. It's not cached, so the file
+    /// is converted again in the next build
+    fn placeholder(&self, src_file: &Path, err: &Error) -> Result<BuildOutput> {
+        let mut buf = String::new();
+        let meta = crate::build::convert::placeholder_buf(
+            &mut buf, src_file, err, &self.acx, &self.hcx, &self.book,
+        )?;
+        let metadata = self.page_metadata(src_file, &meta)?;
+        Ok(BuildOutput {
+            string: buf,
+            src_file: src_file.to_path_buf(),
+            metadata,
+            cache_hit: false,
+        })
+    }
+
+    fn convert_file_impl(&mut self, src_file: &Path) -> Result<BuildOutput> {
+        // the capacity is reserved on reading the cache or converting the file
+        let mut buf = String::new();
 
-            let cache_dir = CacheIndex::locate_cache_dir(&self.book)?;
-            let cached_file = cache_dir.join(rel_path).with_extension("html");
+        // located once, since it hashes the source file with `cache_by_hash`
+        let cached_file = self::locate_cache_file(&self.book, &self.cache_diff, src_file)?;
+        let cache_hit = !self.cache_diff.need_build(&self.book, src_file)
+            && (!self.book.book_ron.cache_by_hash || cached_file.is_file());
 
+        let meta = if cache_hit {
+            // just copy
             let mut f = fs::File::open(&cached_file).with_context(|| {
                 anyhow!(
                     "Unable to locate cached file at {}\nPlease run `adbook clear`",
@@ -76,35 +172,107 @@ impl AdocBookBuilder {
                 )
             })?;
 
-            log::trace!("- skip: {}", src_file.display());
             f.read_to_string(&mut buf)?;
+
+            crate::build::convert::extract_metadata(src_file, &self.acx, &self.book)?
         } else {
             // convert
-            log::trace!("- convert: {}", src_file.display());
-            self.convert_file_into_buf(&mut buf, src_file)?;
-        }
+            let meta = self.convert_file_into_buf(&mut buf, src_file)?;
+            self.write_cache(src_file, &cached_file, &buf)?;
+            meta
+        };
 
-        Ok(buf)
+        let metadata = self.page_metadata(src_file, &meta)?;
+        Ok(BuildOutput {
+            string: buf,
+            src_file: src_file.to_path_buf(),
+            metadata,
+            cache_hit,
+        })
     }
 }
 
 unsafe impl Send for AdocBookBuilder {}
 
+/// Can the output of the source file be copied from the cache?
+///
+/// `adbook build --force` starts with an empty cache index, where every file needs to be built.
+pub(crate) fn is_cached(
+    book: &BookStructure,
+    cache_diff: &CacheIndexDiff,
+    src_file: &Path,
+) -> bool {
+    if cache_diff.need_build(book, src_file) {
+        return false;
+    }
+
+    if book.book_ron.cache_by_hash {
+        return self::locate_cache_file(book, cache_diff, src_file)
+            .map(|cached_file| cached_file.is_file())
+            .unwrap_or(false);
+    }
+
+    true
+}
+
+/// Path to the cached output of the source file, whether it exists or not
+fn locate_cache_file(
+    book: &BookStructure,
+    cache_diff: &CacheIndexDiff,
+    src_file: &Path,
+) -> Result<PathBuf> {
+    if book.book_ron.cache_by_hash {
+        return cache_diff.locate_hashed_output(book, src_file);
+    }
+
+    let src_dir = book.src_dir_path();
+    let rel_path = src_file.strip_prefix(&src_dir)?;
+
+    let cache_dir = CacheIndex::locate_cache_dir(book)?;
+    Ok(cache_dir.join(rel_path).with_extension("html"))
+}
+
 impl BookBuilder for AdocBookBuilder {
     fn can_skip_build(&self, src_file: &Path) -> bool {
-        !self.cache_diff.need_build(&self.book, src_file)
+        self::is_cached(&self.book, &self.cache_diff, src_file)
     }
 
     fn convert_file(&mut self, src_file: &Path) -> BuildResult {
         match self.convert_file_impl(src_file) {
-            Ok(output) => Ok(BuildOutput {
-                string: output,
-                src_file: src_file.to_path_buf(),
-            }),
-            Err(err) => Err(BuildError {
-                err,
-                src_file: src_file.to_path_buf(),
-            }),
+            Result::Ok(output) => Ok(output),
+            Err(err) => match self.book.book_ron.on_convert_error {
+                OnConvertError::Abort => Err(self::build_error(err, src_file, false)),
+                OnConvertError::Skip => Err(self::build_error(err, src_file, true)),
+                OnConvertError::Placeholder => match self.placeholder(src_file, &err) {
+                    Result::Ok(output) => {
+                        log::error!("{:?}", err.context("Wrote placeholder page"));
+                        Ok(output)
+                    }
+                    Err(placeholder_err) => Err(self::build_error(
+                        err.context(placeholder_err),
+                        src_file,
+                        false,
+                    )),
+                },
+            },
         }
     }
 }
+
+/// Creates [`BuildError`] carrying the exit code and stderr of `asciidoctor` if any
+fn build_error(err: Error, src_file: &Path, skipped: bool) -> BuildError {
+    let (exit_code, stderr) = match err.chain().find_map(|e| e.downcast_ref::<AdocError>()) {
+        Some(AdocError::FailedToConvert {
+            exit_code, stderr, ..
+        }) => (*exit_code, Some(stderr.clone())),
+        _ => (None, None),
+    };
+
+    BuildError {
+        err,
+        src_file: src_file.to_path_buf(),
+        skipped,
+        exit_code,
+        stderr,
+    }
+}
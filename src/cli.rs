@@ -1,5 +1,5 @@
 /*!
-Command line interface by clap 3.0
+Command line interface by clap 3.2
 
 # Example
 
@@ -23,13 +23,18 @@ fn main() -> Result<()> {
 use std::{
     fs,
     path::{Path, PathBuf},
+    sync::mpsc,
+    time::Duration,
 };
 
 use anyhow::*;
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use colored::*;
 
-use crate::book::BookStructure;
+use crate::{
+    book::{BookLoadError, BookStructure, RootSearch},
+    build::{diff::DiffFormat, dry_run::DryRunReport, BuildOptions},
+};
 
 // `adbook`
 #[derive(Parser, Debug)]
@@ -53,20 +58,40 @@ pub enum SubCommand {
     /// Builds an `adbook` project
     #[clap(name = "build", alias = "b")]
     Build(Build),
-    /// Prints one of the preset files: `article.adoc`, `book.ron` or `index.ron`
+    /// Creates a new article and adds it to the `index.ron` in the same directory
+    #[clap(name = "new", alias = "n")]
+    New(New),
+    /// Converts a single AsciiDoc file and prints the output
+    #[clap(name = "convert", alias = "c")]
+    Convert(Convert),
+    /// Builds an `adbook` project and rebuilds it on changes
+    #[clap(name = "watch", alias = "w")]
+    Watch(Watch),
+    /// Prints one of the preset files (`article.adoc`, `book.ron` or `index.ron`) or lists presets/themes
     #[clap(name = "preset", alias = "p")]
     Preset(Preset),
+    /// Checks `asciidoctor` and its extensions required for building
+    #[clap(name = "doctor")]
+    Doctor(Doctor),
     /// Clears the site directory contents and the build cache
     Clear(Clear),
+    /// Prints a shell completion script
+    #[clap(name = "completions")]
+    Completions(Completions),
 }
 
 impl SubCommand {
     pub fn run(&mut self) -> Result<()> {
         match self {
             SubCommand::Build(build) => build.run(),
+            SubCommand::New(new) => new.run(),
+            SubCommand::Convert(convert) => convert.run(),
+            SubCommand::Watch(watch) => watch.run(),
             SubCommand::Init(init) => init.run(),
             SubCommand::Preset(preset) => preset.run(),
             SubCommand::Clear(clear) => clear.run(),
+            SubCommand::Doctor(doctor) => doctor.run(),
+            SubCommand::Completions(completions) => completions.run(),
         }
     }
 }
@@ -75,12 +100,69 @@ impl SubCommand {
 #[derive(Parser, Debug)]
 pub struct Build {
     pub dir: Option<String>,
+    /// Looks for `book.ron` only in the given directory, not in the ancestors
+    #[clap(long)]
+    pub no_ancestors: bool,
     /// Clears cache and builds the whole book
     #[clap(short, long = "force")]
     pub force_rebuild: bool,
     /// Prints verbose log
     #[clap(short, long)]
     pub verbose: bool,
+    /// Maximum number of `asciidoctor` processes (and file copies) running at a time. Defaults to
+    /// the number of CPUs
+    #[clap(short, long)]
+    pub jobs: Option<usize>,
+    /// Fails on warnings such as broken links. `asciidoctor` warnings are controlled by
+    /// `failure_level` in `book.ron`
+    #[clap(long)]
+    pub strict: bool,
+    /// Checks internal links (as `check_links` in `book.ron`) and fails on broken ones
+    #[clap(long)]
+    pub deny_broken_links: bool,
+    /// Checks external `http(s)://` links with `curl`, too
+    #[clap(long)]
+    pub check_external_links: bool,
+    /// Activates a build profile in `profile_attributes` of `book.ron`
+    #[clap(long)]
+    pub profile: Option<String>,
+    /// Builds articles with the `draft` attribute and lists them in the sidebar
+    #[clap(long)]
+    pub drafts: bool,
+    /// Opens `index.html` of the site directory with the default browser after building
+    #[clap(long)]
+    pub open: bool,
+    /// Skips copying the default theme, assuming it's already in the site directory
+    #[clap(long)]
+    pub no_theme: bool,
+    /// Converts the source files into the cache without writing the site directory
+    #[clap(long)]
+    pub cache_only: bool,
+    /// Prints the source files to convert and the files to copy, without running `asciidoctor` or
+    /// writing the site directory
+    #[clap(long, conflicts_with_all = &["open", "diff", "diff-json"])]
+    pub dry_run: bool,
+    /// Prints output files added, modified or removed since the previous build
+    #[clap(long)]
+    pub diff: bool,
+    /// Prints the output diff as JSON (implies `--diff`)
+    #[clap(long)]
+    pub diff_json: bool,
+    /// Fails on empty outputs for non-empty sources (`fail_on_empty` in `book.ron`)
+    #[clap(long)]
+    pub fail_on_empty_output: bool,
+    /// `json-outline` prints the outline (titles and URLs) as JSON without running `asciidoctor`
+    #[clap(long, value_enum, default_value = "html")]
+    pub output_format: OutputFormat,
+}
+
+/// Output format of `adbook build`
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Site directory with HTML files
+    Html,
+    /// Outline of the book (titles and URLs) printed as JSON
+    JsonOutline,
 }
 
 impl Build {
@@ -88,16 +170,258 @@ impl Build {
         let dir = self.dir.as_ref().unwrap_or(&".".into()).clone();
 
         log::trace!("---- Loading book structure");
-        let book = BookStructure::from_dir(&dir)?;
+        let search = if self.no_ancestors {
+            RootSearch::GivenDir
+        } else {
+            RootSearch::Ancestors
+        };
+        let mut book = BookStructure::from_dir_with(&dir, search)?;
+        book.book_ron.fail_on_empty |= self.fail_on_empty_output;
+        book.drafts = self.drafts;
+        if let Some(profile) = &self.profile {
+            book.set_profile(profile)?;
+        }
+
+        if self.output_format == OutputFormat::JsonOutline {
+            let (json, errors) = crate::build::outline::outline_json(&book)?;
+            crate::utils::print_errors(&errors, "while creating outline");
+            println!("{}", json);
+            return Ok(());
+        }
 
         log::info!("===> Building the book");
-        crate::build::build_book(&book, self.force_rebuild, self.verbose)?;
+        let opts = BuildOptions {
+            force_rebuild: self.force_rebuild,
+            log: self.verbose,
+            strict: self.strict,
+            no_theme: self.no_theme,
+            cache_only: self.cache_only,
+            jobs: self.jobs,
+            deny_broken_links: self.deny_broken_links,
+            check_external_links: self.check_external_links,
+            diff: if self.diff_json {
+                Some(DiffFormat::Json)
+            } else if self.diff {
+                Some(DiffFormat::Text)
+            } else {
+                None
+            },
+        };
+
+        if self.dry_run {
+            let (report, errors) = DryRunReport::create(&book, &opts)?;
+            report.print(&book);
+            crate::utils::print_errors(&errors, "while planning the build");
+            return Ok(());
+        }
+
+        crate::build::build_book(&book, &opts)?;
         log::info!("<==> Finished bulding");
 
+        if self.open {
+            self::open_site(&book);
+        }
+
         Ok(())
     }
 }
 
+/// Opens `index.html` of the site directory with the default browser. Failures are just warned
+fn open_site(book: &BookStructure) {
+    let index = book.site_dir_path().join("index.html");
+    let url = crate::utils::file_url(&index);
+
+    log::info!("Opening {}", url);
+    if let Err(err) = crate::utils::open_browser(&url) {
+        crate::utils::print_warnings(&[err], "while opening the browser");
+    }
+}
+
+/// `adbook watch`
+///
+/// Changes in the source directory are rebuilt with the cache, so only the modified files are
+/// converted again. Changes to `book.ron` or the `watch_extra` items trigger a full rebuild.
+#[derive(Parser, Debug)]
+pub struct Watch {
+    pub dir: Option<String>,
+    /// Prints verbose log
+    #[clap(short, long)]
+    pub verbose: bool,
+    /// Activates a build profile in `profile_attributes` of `book.ron`
+    #[clap(long)]
+    pub profile: Option<String>,
+    /// Opens `index.html` of the site directory with the default browser after the first build
+    #[clap(long)]
+    pub open: bool,
+}
+
+impl Watch {
+    /// Successive events within this duration trigger only one rebuild
+    const DEBOUNCE: Duration = Duration::from_millis(200);
+
+    pub fn run(&mut self) -> Result<()> {
+        use notify::{DebouncedEvent, RecursiveMode, Watcher};
+
+        let dir = self.dir.as_ref().unwrap_or(&".".into()).clone();
+        let mut book = self.load_book(&dir)?;
+        // fail on missing `watch_extra` items before the initial build
+        let mut targets = WatchTargets::from_book(&book)?;
+
+        log::info!("===> Building the book");
+        if let Err(err) = crate::build::build_book(&book, &self.build_options(false)) {
+            log::error!("{:#}", err);
+        }
+
+        if self.open {
+            self::open_site(&book);
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = notify::watcher(tx, Self::DEBOUNCE)?;
+
+        // `book.ron` is watched through the root directory, since editors may save it by renaming
+        // a temporary file over it, which would end a watch on the file itself
+        let book_ron = book.root.join("book.ron");
+        watcher.watch(&book.root, RecursiveMode::NonRecursive)?;
+        targets.watch(&mut watcher)?;
+
+        log::info!("===> Watching {}", book.src_dir_path().display());
+
+        loop {
+            // collect the paths until the events settle down
+            let mut paths = Vec::new();
+            let mut event = rx.recv()?;
+            loop {
+                match event {
+                    DebouncedEvent::Create(path)
+                    | DebouncedEvent::Write(path)
+                    | DebouncedEvent::Remove(path)
+                    | DebouncedEvent::Rename(_, path) => paths.push(path),
+                    DebouncedEvent::Error(err, path) => {
+                        log::warn!("Watch error: {} ({:?})", err, path);
+                    }
+                    _ => {}
+                }
+
+                event = match rx.recv_timeout(Self::DEBOUNCE) {
+                    Result::Ok(event) => event,
+                    Err(_) => break,
+                };
+            }
+
+            // other files in the root directory
+            paths.retain(|path| *path == book_ron || targets.contains(path));
+            if paths.is_empty() {
+                continue;
+            }
+
+            for path in &paths {
+                log::info!("Changed: {}", path.display());
+            }
+
+            let index_file = book.book_ron.index_file().to_string();
+            let is_config = |path: &PathBuf| {
+                *path == book_ron || targets.extra.iter().any(|extra| path.starts_with(extra))
+            };
+            let force_rebuild = paths.iter().any(is_config);
+            let reload = force_rebuild
+                || paths
+                    .iter()
+                    .any(|path| path.file_name() == Some(index_file.as_ref()));
+
+            // the book structure changed
+            if reload {
+                book = match self.load_book(&dir) {
+                    Result::Ok(book) => book,
+                    Err(err) => {
+                        log::error!("{:#}", err);
+                        continue;
+                    }
+                };
+
+                // `src_dir` or `watch_extra` may have changed
+                let new_targets = match WatchTargets::from_book(&book) {
+                    Result::Ok(targets) => targets,
+                    Err(err) => {
+                        log::error!("{:#}", err);
+                        continue;
+                    }
+                };
+                if new_targets != targets {
+                    targets.unwatch(&mut watcher);
+                    targets = new_targets;
+                    if let Err(err) = targets.watch(&mut watcher) {
+                        log::error!("{:#}", err);
+                    }
+                    log::info!("===> Watching {}", targets.src_dir.display());
+                }
+            }
+
+            log::info!("===> Rebuilding the book");
+            match crate::build::build_book(&book, &self.build_options(force_rebuild)) {
+                Result::Ok(()) => log::info!("<==> Finished bulding"),
+                Err(err) => log::error!("{:#}", err),
+            }
+        }
+    }
+
+    fn load_book(&self, dir: &str) -> Result<BookStructure> {
+        let mut book = BookStructure::from_dir(dir)?;
+        if let Some(profile) = &self.profile {
+            book.set_profile(profile)?;
+        }
+        Ok(book)
+    }
+
+    fn build_options(&self, force_rebuild: bool) -> BuildOptions {
+        BuildOptions {
+            force_rebuild,
+            log: self.verbose,
+            ..Default::default()
+        }
+    }
+}
+
+/// Paths watched by `adbook watch` besides the root directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct WatchTargets {
+    src_dir: PathBuf,
+    /// `watch_extra` in `book.ron`
+    extra: Vec<PathBuf>,
+}
+
+impl WatchTargets {
+    fn from_book(book: &BookStructure) -> Result<Self> {
+        Ok(Self {
+            src_dir: book.src_dir_path(),
+            extra: book.watch_extra_paths()?,
+        })
+    }
+
+    fn contains(&self, path: &Path) -> bool {
+        path.starts_with(&self.src_dir) || self.extra.iter().any(|extra| path.starts_with(extra))
+    }
+
+    fn watch(&self, watcher: &mut impl notify::Watcher) -> Result<()> {
+        use notify::RecursiveMode;
+
+        watcher.watch(&self.src_dir, RecursiveMode::Recursive)?;
+        for path in &self.extra {
+            watcher.watch(path, RecursiveMode::Recursive)?;
+        }
+        Ok(())
+    }
+
+    /// Stops watching the paths. Paths that no longer exist are just skipped
+    fn unwatch(&self, watcher: &mut impl notify::Watcher) {
+        for path in std::iter::once(&self.src_dir).chain(&self.extra) {
+            if let Err(err) = watcher.unwatch(path) {
+                log::trace!("Unable to unwatch {}: {}", path.display(), err);
+            }
+        }
+    }
+}
+
 /// `adbook init`
 #[derive(Parser, Debug)]
 pub struct Init {
@@ -132,32 +456,165 @@ impl Init {
     }
 }
 
+/// `adbook new`
+#[derive(Parser, Debug)]
+pub struct New {
+    /// Path to the new article relative to the source directory (e.g. `sub/article.adoc`)
+    pub path: String,
+}
+
+impl New {
+    pub fn run(&mut self) -> Result<()> {
+        let book = BookStructure::from_dir(".")?;
+        let file = crate::book::init::gen_article(&book, &self.path)?;
+
+        println!(
+            "Created a new article at {}",
+            format!("{}", file.display()).green()
+        );
+
+        Ok(())
+    }
+}
+
+/// `adbook convert`
+///
+/// If the file is in a book (`book.ron` is found in the ancestor directories), `adoc_opts` and
+/// `base_url` of the book are used.
+#[derive(Parser, Debug)]
+pub struct Convert {
+    /// AsciiDoc file to convert
+    pub file: PathBuf,
+    /// Handlebars template applied to the output (the file has to be in a book)
+    #[clap(long)]
+    pub hbs: Option<PathBuf>,
+    /// Writes the output to the file instead of stdout
+    #[clap(short, long)]
+    pub out: Option<PathBuf>,
+}
+
+/// Loads the book the directory belongs to, if any
+fn find_book(dir: &Path) -> Result<Option<BookStructure>> {
+    match BookStructure::from_dir(dir) {
+        Result::Ok(book) => Ok(Some(book)),
+        Err(err) if matches!(err.downcast_ref(), Some(BookLoadError::NotFoundRoot(_))) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+impl Convert {
+    pub fn run(&mut self) -> Result<()> {
+        let file = self
+            .file
+            .canonicalize()
+            .with_context(|| format!("Unable to find {}", self.file.display()))?;
+        ensure!(file.is_file(), "Not a file: {}", file.display());
+
+        let dir = file.parent().unwrap();
+        let book = self::find_book(dir)?;
+
+        let output =
+            crate::build::convert::convert_single_file(&file, book.as_ref(), self.hbs.as_deref())?;
+
+        match &self.out {
+            Some(out) => fs::write(out, output)
+                .with_context(|| format!("Unable to write {}", out.display()))?,
+            None => print!("{}", output),
+        }
+
+        Ok(())
+    }
+}
+
+/// `adbook doctor`
+///
+/// Checks the `adoc_cmd` of the book in the directory (or `asciidoctor` outside of a book) and
+/// fails if a required dependency is missing.
+#[derive(Parser, Debug)]
+pub struct Doctor {
+    pub dir: Option<String>,
+}
+
+impl Doctor {
+    pub fn run(&mut self) -> Result<()> {
+        use crate::build::doctor::{self, CheckStatus};
+
+        let dir = self.dir.as_ref().unwrap_or(&".".into()).clone();
+        let dir = Path::new(&dir)
+            .canonicalize()
+            .with_context(|| format!("Unable to find directory: {}", dir))?;
+
+        let book = self::find_book(&dir)?;
+        if book.is_none() {
+            println!("No `book.ron` found. Checking the default `asciidoctor`");
+        }
+
+        let checks = doctor::run_checks(book.as_ref().map(|b| &b.book_ron));
+        for check in &checks {
+            check.print();
+        }
+
+        let n_missing = checks
+            .iter()
+            .filter(|c| c.status == CheckStatus::Missing)
+            .count();
+        ensure!(
+            n_missing == 0,
+            "{} required dependency(ies) missing",
+            n_missing
+        );
+
+        Ok(())
+    }
+}
+
 /// `adbook preset`
 #[derive(Parser, Debug)]
 pub struct Preset {
+    /// `book.ron`, `index.ron` or `article.adoc`. Lists the preset names if omitted
     pub file: Option<String>,
+    /// Lists the preset names
+    #[clap(long)]
+    pub list_presets: bool,
+    /// Lists the embedded themes and their files (`<theme>\t<file>`)
+    #[clap(long)]
+    pub list_themes: bool,
 }
 
 impl Preset {
     pub fn run(&mut self) -> Result<()> {
-        use crate::book::init::files;
+        use crate::book::init;
 
-        let file = self.file.as_ref().map(|s| s.as_str()).unwrap_or("");
-        match file {
-            "b" | "book" | "book.ron" => {
-                let s = std::str::from_utf8(files::BOOK)?;
-                println!("{}", s);
+        if self.list_themes {
+            for theme in init::THEMES {
+                for file in init::theme_files(theme).unwrap_or_default() {
+                    println!("{}\t{}", theme, file);
+                }
             }
-            "i" | "index" | "index.ron" => {
-                let s = std::str::from_utf8(files::src::INDEX_RON)?;
-                println!("{}", s);
+            return Ok(());
+        }
+
+        let file = match &self.file {
+            Some(file) if !self.list_presets => file,
+            _ => {
+                for (name, _aliases, _bytes) in init::PRESETS {
+                    println!("{}", name);
+                }
+                return Ok(());
             }
-            "a" | "article" | "article.adoc" => {
-                let s = std::str::from_utf8(files::src::ARTICLE)?;
+        };
+
+        match init::find_preset(file) {
+            Some(bytes) => {
+                let s = std::str::from_utf8(bytes)?;
                 println!("{}", s);
             }
-            _ => {
-                eprintln!("specify one of `book`, `index` or `article");
+            None => {
+                let names = init::PRESETS
+                    .iter()
+                    .map(|(name, _aliases, _bytes)| *name)
+                    .collect::<Vec<_>>();
+                bail!("Unknown preset `{}`. Specify one of {:?}", file, names);
             }
         }
 
@@ -165,10 +622,48 @@ impl Preset {
     }
 }
 
+/// `adbook completions`
+///
+/// Prints the completion script to stdout, e.g., `adbook completions bash > ~/.adbook.bash`.
+#[derive(Parser, Debug)]
+pub struct Completions {
+    /// `bash`, `zsh`, `fish`, `powershell` or `elvish`
+    pub shell: String,
+}
+
+impl Completions {
+    pub fn run(&mut self) -> Result<()> {
+        use clap::CommandFactory;
+        use clap_complete::Shell;
+
+        let shell = match <Shell as ValueEnum>::from_str(&self.shell, true) {
+            Result::Ok(shell) => shell,
+            Err(_) => {
+                let names = Shell::value_variants()
+                    .iter()
+                    .map(|shell| shell.to_string())
+                    .collect::<Vec<_>>();
+                bail!("Unknown shell `{}`. Specify one of {:?}", self.shell, names);
+            }
+        };
+
+        let mut cmd = Cli::command();
+        clap_complete::generate(shell, &mut cmd, "adbook", &mut std::io::stdout());
+
+        Ok(())
+    }
+}
+
 /// `adbook clear`
 #[derive(Parser, Debug)]
 pub struct Clear {
     pub dir: Option<String>,
+    /// Clears the build cache only, keeping the site directory
+    #[clap(long, conflicts_with = "site-only")]
+    pub cache_only: bool,
+    /// Clears the site directory only, keeping the build cache
+    #[clap(long)]
+    pub site_only: bool,
 }
 
 impl Clear {
@@ -189,11 +684,15 @@ impl Clear {
             name.starts_with(".")
         }
 
-        log::info!("===> Clearing the site directory");
-        crate::utils::clear_directory_items(&book.site_dir_path(), is_path_to_keep)?;
+        if !self.cache_only {
+            log::info!("===> Clearing the site directory");
+            crate::utils::clear_directory_items(&book.site_dir_path(), is_path_to_keep)?;
+        }
 
-        log::info!("===> Clearing build cache");
-        crate::build::cache::clear_cache(&book)?;
+        if !self.site_only {
+            log::info!("===> Clearing build cache");
+            crate::build::cache::clear_cache(&book)?;
+        }
 
         Ok(())
     }
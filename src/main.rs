@@ -4,7 +4,6 @@
 # TODOs
 
 * refactor book loading
-* setup convert sub command: consider if the file is in site directory or not
 */
 
 use adbook::cli::Cli;
@@ -2,10 +2,15 @@
 Internal utilities
 */
 
-use std::{fmt, fs, path::Path};
+use std::{
+    collections::BTreeSet,
+    fmt, fs, io,
+    path::{Component, Path, PathBuf},
+};
 
-use anyhow::{anyhow, ensure, Context, Result};
+use anyhow::{anyhow, bail, ensure, Context, Result};
 use colored::*;
+use futures::stream::{self, StreamExt};
 use serde::de::DeserializeOwned;
 
 /// Load the given string as a RON format (or one without outermost parentheses)
@@ -56,14 +61,37 @@ fn print_items(kind: &str, items: &[impl fmt::Display], header: &str) {
     }
 }
 
-/// Copies all items in one directory to another recursively
-pub fn copy_items_rec(src_dir: &Path, dst_dir: &Path) -> Result<()> {
-    // log::trace!(
-    //     "Recursive copy: `{}` -> `{}`",
-    //     src_dir.display(),
-    //     dst_dir.display(),
-    // );
+/// If the destination file is modified at or after the source file
+pub fn is_up_to_date(src: &Path, dst: &Path) -> bool {
+    let (src_meta, dst_meta) = match (fs::metadata(src), fs::metadata(dst)) {
+        (Ok(src_meta), Ok(dst_meta)) => (src_meta, dst_meta),
+        _ => return false,
+    };
+
+    match (src_meta.modified(), dst_meta.modified()) {
+        (Ok(src_time), Ok(dst_time)) => dst_meta.is_file() && dst_time >= src_time,
+        _ => false,
+    }
+}
+
+/// Copies a file unless the destination is up to date (see [`is_up_to_date`]). Returns `true` if
+/// the file is copied
+pub fn copy_file_if_newer(src: &Path, dst: &Path) -> io::Result<bool> {
+    if self::is_up_to_date(src, dst) {
+        return Ok(false);
+    }
+
+    fs::copy(src, dst)?;
+    Ok(true)
+}
 
+/// Lists the files to copy from one directory to another recursively. Nothing is written; the
+/// files are copied with [`copy_files_parallel`]
+pub fn list_copies_rec(
+    src_dir: &Path,
+    dst_dir: &Path,
+    copies: &mut Vec<(PathBuf, PathBuf)>,
+) -> Result<()> {
     ensure!(
         src_dir != dst_dir,
         "Same source/destination when trying recursive copy!: {}",
@@ -76,18 +104,13 @@ pub fn copy_items_rec(src_dir: &Path, dst_dir: &Path) -> Result<()> {
         src_dir.display(),
     );
 
-    if !dst_dir.exists() {
-        fs::create_dir(dst_dir)
-            .with_context(|| "Can't create destination directory on recursive copy")?;
-    } else {
-        ensure!(
-            dst_dir.is_dir(),
-            "Some non-directory item exists to the destination path on recursive copy: {}",
-            dst_dir.display(),
-        );
-    }
+    ensure!(
+        !dst_dir.exists() || dst_dir.is_dir(),
+        "Some non-directory item exists to the destination path on recursive copy: {}",
+        dst_dir.display(),
+    );
 
-    self::copy_items_rec_impl(src_dir, dst_dir).with_context(|| {
+    self::list_copies_rec_impl(src_dir, dst_dir, copies).with_context(|| {
         anyhow!(
             "Error when trying recursive copy:\n  src_dir: {}\n  dst_dir: {}",
             src_dir.display(),
@@ -96,7 +119,11 @@ pub fn copy_items_rec(src_dir: &Path, dst_dir: &Path) -> Result<()> {
     })
 }
 
-fn copy_items_rec_impl(src_dir: &Path, dst_dir: &Path) -> Result<()> {
+fn list_copies_rec_impl(
+    src_dir: &Path,
+    dst_dir: &Path,
+    copies: &mut Vec<(PathBuf, PathBuf)>,
+) -> Result<()> {
     for entry in fs::read_dir(src_dir)? {
         let entry = entry?;
 
@@ -105,28 +132,11 @@ fn copy_items_rec_impl(src_dir: &Path, dst_dir: &Path) -> Result<()> {
         let dst_path = dst_dir.join(rel_path);
 
         if src_path.is_file() {
-            // case 1. file: just copy
-            // log::trace!(
-            //     "- copy file: `{}` -> `{}`",
-            //     src_path.display(),
-            //     dst_path.display()
-            // );
-
-            fs::copy(&src_path, &dst_path)?;
+            // case 1. file
+            copies.push((src_path, dst_path));
         } else if src_path.is_dir() {
-            // case 2. directory: recursive copy
-            // log::trace!(
-            //     "- copy dir: `{}` -> `{}`",
-            //     src_path.display(),
-            //     dst_path.display()
-            // );
-
-            if !dst_path.exists() {
-                fs::create_dir(&dst_path)
-                    .with_context(|| "Unable to create directory on recursive copy")?;
-            }
-
-            self::copy_items_rec_impl(&src_path, &dst_path)?;
+            // case 2. directory: recursive listing
+            self::list_copies_rec_impl(&src_path, &dst_path, copies)?;
         } else {
             // case 3. unexpected kind of item: error
             eprintln!(
@@ -139,6 +149,58 @@ fn copy_items_rec_impl(src_dir: &Path, dst_dir: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Copies `(src, dst)` files in parallel, running at most `jobs` copies at a time. Up-to-date
+/// files are skipped (see [`copy_file_if_newer`]) and failures are pushed to `errors`. Returns the
+/// number of the copied files
+///
+/// The parent directories of the destinations are created beforehand, one by one, so that the
+/// tasks don't race on creating them.
+pub fn copy_files_parallel(
+    copies: Vec<(PathBuf, PathBuf)>,
+    jobs: usize,
+    errors: &mut Vec<anyhow::Error>,
+) -> usize {
+    let dirs = copies
+        .iter()
+        .filter_map(|(_src, dst)| dst.parent())
+        .collect::<BTreeSet<_>>();
+    for dir in dirs {
+        if let Err(err) = fs::create_dir_all(dir) {
+            errors.push(anyhow!(
+                "Unable to create directory: {} (IO error: {})",
+                dir.display(),
+                err
+            ));
+        }
+    }
+
+    let results = futures::executor::block_on(
+        stream::iter(copies)
+            .map(|(src, dst)| {
+                // `fs::copy` blocks, so it runs on the blocking thread pool rather than the
+                // executor threads
+                async_std::task::spawn_blocking(move || {
+                    self::copy_file_if_newer(&src, &dst).with_context(|| {
+                        format!("Unable to copy `{}` to `{}`", src.display(), dst.display())
+                    })
+                })
+            })
+            .buffer_unordered(jobs.max(1))
+            .collect::<Vec<_>>(),
+    );
+
+    let mut n_copies = 0;
+    for res in results {
+        match res {
+            Ok(true) => n_copies += 1,
+            Ok(false) => {}
+            Err(err) => errors.push(err),
+        }
+    }
+
+    n_copies
+}
+
 /// Clears items just under the directory
 pub fn clear_directory_items(dir: &Path, should_keep: impl Fn(&Path) -> bool) -> Result<()> {
     for entry in fs::read_dir(dir)? {
@@ -197,3 +259,108 @@ pub fn validate_dir(dir: &Path) -> Result<()> {
 
     Ok(())
 }
+
+/// Makes sure we can create files in a directory by creating and removing a temporary file
+///
+/// It catches a read-only directory (or a symbolic link to a read-only mount) before writing
+/// anything into it.
+pub fn ensure_writable(dir: &Path) -> Result<()> {
+    let probe = dir.join(".adbook-write-test");
+
+    fs::File::create(&probe)
+        .and_then(|_| fs::remove_file(&probe))
+        .map_err(|err| {
+            let target = match fs::read_link(dir) {
+                Result::Ok(target) => format!(" (symbolic link to {})", target.display()),
+                Err(_) => "".to_string(),
+            };
+            anyhow!(
+                "Directory is not writable: {}{}. Check the permission or the mount. IO error: {}",
+                dir.display(),
+                target,
+                err
+            )
+        })
+}
+
+/// `file://` URL of an absolute path
+pub fn file_url(path: &Path) -> String {
+    let s = path.display().to_string().replace('\\', "/");
+    if s.starts_with('/') {
+        format!("file://{}", s)
+    } else {
+        // `C:/..`
+        format!("file:///{}", s)
+    }
+}
+
+/// Opens a URL with the default browser using the platform command (`open`, `xdg-open` or
+/// `start`)
+pub fn open_browser(url: &str) -> Result<()> {
+    let mut cmd = if cfg!(target_os = "macos") {
+        std::process::Command::new("open")
+    } else if cfg!(windows) {
+        let mut cmd = std::process::Command::new("cmd");
+        // the empty string is the window title
+        cmd.args(["/C", "start", ""]);
+        cmd
+    } else {
+        std::process::Command::new("xdg-open")
+    };
+
+    let status = cmd
+        .arg(url)
+        .status()
+        .with_context(|| format!("Unable to run browser opener for {}", url))?;
+    ensure!(status.success(), "Unable to open {} ({})", url, status);
+
+    Ok(())
+}
+
+/// Resolves `.` and `..` and symbolic links of existing ancestors. Works with non-existing paths
+fn resolve_path(path: &Path) -> PathBuf {
+    // lexically resolve `.` and `..`
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    normalized.push(component);
+                }
+            }
+            _ => normalized.push(component),
+        }
+    }
+
+    // canonicalize the deepest existing ancestor
+    for ancestor in normalized.ancestors() {
+        if let Result::Ok(canon) = ancestor.canonicalize() {
+            let rest = normalized.strip_prefix(ancestor).unwrap();
+            // (`join("")` would add a trailing separator)
+            if rest.as_os_str().is_empty() {
+                return canon;
+            }
+            return canon.join(rest);
+        }
+    }
+
+    normalized
+}
+
+/// Makes sure the (possibly non-existing) path stays in the base directory and returns the
+/// resolved path
+pub fn ensure_within(path: &Path, base_dir: &Path) -> Result<PathBuf> {
+    let resolved = self::resolve_path(path);
+    let base_dir = self::resolve_path(base_dir);
+
+    if !resolved.starts_with(&base_dir) {
+        bail!(
+            "Destination `{}` is out of `{}`",
+            path.display(),
+            base_dir.display()
+        );
+    }
+
+    Ok(resolved)
+}